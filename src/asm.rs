@@ -0,0 +1,366 @@
+use crate::chip8::PROGRAM_START;
+use crate::error::Error;
+use crate::instruction::Instruction;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// A statement of the source with the address it assembles to, before labels are resolved
+struct Statement<'a> {
+    // Line number, counting from 1
+    line: usize,
+    // In capitals
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+/// Assembles `source` into a ROM to be loaded at 0x200, see `assemble_at`
+pub fn assemble(source: &str) -> Result<Vec<u8>, Error> {
+    assemble_at(source, PROGRAM_START)
+}
+
+/// Assembles `source` into a ROM to be loaded at `start`.
+///
+/// Every line holds at most one instruction, written as the disassembler shows it,
+/// such as `LD VA,0x02` or `DRW V0,V1,5`. Mnemonics and registers can be in any case.
+/// A line may start with labels, like `loop:`, which can stand for addresses anywhere
+/// in the program. `DB` and `DW` lay out bytes and words as they are, and `;` starts
+/// a comment. Numbers are decimal, or hex and binary with a `0x` or `0b` prefix.
+///
+/// Errors name the line they are about.
+pub fn assemble_at(source: &str, start: u16) -> Result<Vec<u8>, Error> {
+    // First pass: find out where every label points to
+    let mut labels = BTreeMap::new();
+    let mut statements = Vec::new();
+    let mut address = start as u32;
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut text = text.split(';').next().unwrap_or_default().trim();
+        while let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            if !is_identifier(label) {
+                return Err(error(line, format!("invalid label \"{}\"", label)));
+            }
+            if is_reserved(label) {
+                return Err(error(line, format!("\"{}\" names a register, it can't be a label", label)));
+            }
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(error(line, format!("label \"{}\" is defined twice", label)));
+            }
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let (mnemonic, operands) = match text.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (mnemonic, operands.split(',').map(str::trim).collect()),
+            None => (text, Vec::new()),
+        };
+        let statement = Statement { line, mnemonic: mnemonic.to_ascii_uppercase(), operands };
+        address += match statement.mnemonic.as_str() {
+            "DB" => statement.operands.len() as u32,
+            "DW" => statement.operands.len() as u32 * 2,
+            _ => 2,
+        };
+        statements.push(statement);
+    }
+
+    // Second pass: encode every statement, now that all labels are known
+    let mut rom = Vec::new();
+    for statement in &statements {
+        let assembler = Assembler { labels: &labels };
+        assembler
+            .statement(statement, &mut rom)
+            .map_err(|message| error(statement.line, message))?;
+    }
+    Ok(rom)
+}
+
+/// Turns statements into bytes, resolving the labels they use
+struct Assembler<'a> {
+    labels: &'a BTreeMap<String, u32>,
+}
+
+impl Assembler<'_> {
+    /// Appends the bytes of `statement` to `rom`
+    fn statement(&self, statement: &Statement, rom: &mut Vec<u8>) -> Result<(), String> {
+        match statement.mnemonic.as_str() {
+            "DB" => {
+                for operand in &statement.operands {
+                    rom.push(self.value(operand, 0xFF)? as u8);
+                }
+            }
+            "DW" => {
+                for operand in &statement.operands {
+                    rom.extend_from_slice(&(self.value(operand, 0xFFFF)? as u16).to_be_bytes());
+                }
+            }
+            _ => {
+                let instruction = self.instruction(&statement.mnemonic, &statement.operands)?;
+                rom.extend_from_slice(&instruction.encode().to_be_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses the instruction made of `mnemonic` and its `operands`
+    fn instruction(&self, mnemonic: &str, operands: &[&str]) -> Result<Instruction, String> {
+        use Instruction::*;
+
+        let bad_operands = || format!("invalid operands for {}: \"{}\"", mnemonic, operands.join(","));
+        let instruction = match (mnemonic, operands) {
+            ("CLS", []) => ClearScreen,
+            ("RET", []) => Return,
+            ("SCD", [n]) => ScrollDown(self.value(n, 0xF)? as u8),
+            ("SCR", []) => ScrollRight,
+            ("SCL", []) => ScrollLeft,
+            ("EXIT", []) => Exit,
+            ("LOW", []) => LowRes,
+            ("HIGH", []) => HighRes,
+            ("SYS", [address]) => Sys(self.address(address)?),
+            ("JP", [address]) => Jump(self.address(address)?),
+            ("JP", [v0, address]) if register(v0) == Some(0) => JumpV0(self.address(address)?),
+            ("CALL", [address]) => Call(self.address(address)?),
+            ("SE", [x, y]) => match register(y) {
+                Some(y) => SkipEqReg { x: expect_register(x)?, y },
+                None => SkipEqImm { x: expect_register(x)?, nn: self.value(y, 0xFF)? as u8 },
+            },
+            ("SNE", [x, y]) => match register(y) {
+                Some(y) => SkipNeReg { x: expect_register(x)?, y },
+                None => SkipNeImm { x: expect_register(x)?, nn: self.value(y, 0xFF)? as u8 },
+            },
+            ("ADD", [i, x]) if i.eq_ignore_ascii_case("I") => AddI { x: expect_register(x)? },
+            ("ADD", [x, y]) => match register(y) {
+                Some(y) => Add { x: expect_register(x)?, y },
+                None => AddImm { x: expect_register(x)?, nn: self.value(y, 0xFF)? as u8 },
+            },
+            ("OR", [x, y]) => Or { x: expect_register(x)?, y: expect_register(y)? },
+            ("AND", [x, y]) => And { x: expect_register(x)?, y: expect_register(y)? },
+            ("XOR", [x, y]) => Xor { x: expect_register(x)?, y: expect_register(y)? },
+            ("SUB", [x, y]) => Sub { x: expect_register(x)?, y: expect_register(y)? },
+            ("SUBN", [x, y]) => SubN { x: expect_register(x)?, y: expect_register(y)? },
+            // Shifting a register in place can leave out the second one
+            ("SHR", [x]) => ShiftRight { x: expect_register(x)?, y: expect_register(x)? },
+            ("SHR", [x, y]) => ShiftRight { x: expect_register(x)?, y: expect_register(y)? },
+            ("SHL", [x]) => ShiftLeft { x: expect_register(x)?, y: expect_register(x)? },
+            ("SHL", [x, y]) => ShiftLeft { x: expect_register(x)?, y: expect_register(y)? },
+            ("RND", [x, nn]) => Random { x: expect_register(x)?, nn: self.value(nn, 0xFF)? as u8 },
+            ("DRW", [x, y, n]) => Draw { x: expect_register(x)?, y: expect_register(y)?, n: self.value(n, 0xF)? as u8 },
+            ("SKP", [x]) => SkipKey { x: expect_register(x)? },
+            ("SKNP", [x]) => SkipNotKey { x: expect_register(x)? },
+            ("PLANE", [planes]) => Plane(self.value(planes, 0x3)? as u8),
+            ("AUDIO", []) => Audio,
+            ("PITCH", [x]) => Pitch { x: expect_register(x)? },
+            ("LD", [destination, source]) => self.load(destination, source).ok_or_else(bad_operands)??,
+            ("CLS" | "RET" | "SCD" | "SCR" | "SCL" | "EXIT" | "LOW" | "HIGH" | "SYS" | "JP" | "CALL" | "SE"
+            | "SNE" | "ADD" | "OR" | "AND" | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP"
+            | "SKNP" | "PLANE" | "AUDIO" | "PITCH" | "LD", _) => return Err(bad_operands()),
+            _ => return Err(format!("unknown instruction {}", mnemonic)),
+        };
+        Ok(instruction)
+    }
+
+    /// Parses the many forms of LD. None if they don't match any of them.
+    fn load(&self, destination: &str, source: &str) -> Option<Result<Instruction, String>> {
+        use Instruction::*;
+
+        let destination = destination.to_ascii_uppercase();
+        let source_name = source.to_ascii_uppercase();
+        let instruction = match (destination.as_str(), source_name.as_str()) {
+            ("I", _) => return Some(self.address(source).map(LoadI)),
+            ("DT", _) => SetDelay { x: register(source)? },
+            ("ST", _) => SetSound { x: register(source)? },
+            ("F", _) => Font { x: register(source)? },
+            ("HF", _) => BigFont { x: register(source)? },
+            ("B", _) => Bcd { x: register(source)? },
+            ("R", _) => SaveFlags { x: register(source)? },
+            ("[I]", _) => match register_range(source) {
+                Some((x, y)) => SaveRange { x, y },
+                None => Store { x: register(source)? },
+            },
+            (_, "[I]") => match register_range(&destination) {
+                Some((x, y)) => LoadRange { x, y },
+                None => Load { x: register(&destination)? },
+            },
+            (_, "DT") => GetDelay { x: register(&destination)? },
+            (_, "K") => WaitKey { x: register(&destination)? },
+            (_, "R") => LoadFlags { x: register(&destination)? },
+            _ => {
+                let x = register(&destination)?;
+                match register(source) {
+                    Some(y) => Move { x, y },
+                    None => return Some(self.value(source, 0xFF).map(|nn| LoadImm { x, nn: nn as u8 })),
+                }
+            }
+        };
+        Some(Ok(instruction))
+    }
+
+    /// A 12-bit address, given as a number or a label
+    fn address(&self, operand: &str) -> Result<u16, String> {
+        self.value(operand, 0xFFF).map(|address| address as u16)
+    }
+
+    /// A number or the address of a label, which must be at most `max`
+    fn value(&self, operand: &str, max: u32) -> Result<u32, String> {
+        let value = match number(operand) {
+            Some(value) => value,
+            None if is_identifier(operand) => match self.labels.get(operand) {
+                Some(&address) => address,
+                None => return Err(format!("undefined label \"{}\"", operand)),
+            },
+            None => return Err(format!("invalid number \"{}\"", operand)),
+        };
+        if value > max {
+            return Err(format!("{} is out of range, the most allowed here is {:#x}", operand, max));
+        }
+        Ok(value)
+    }
+}
+
+/// Parses a decimal number, or a hex or binary one with a `0x` or `0b` prefix
+fn number(operand: &str) -> Option<u32> {
+    let lower = operand.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+/// The number of a register written as V0 to VF
+fn register(operand: &str) -> Option<u8> {
+    match operand.as_bytes() {
+        [b'v' | b'V', digit] => (*digit as char).to_digit(16).map(|x| x as u8),
+        _ => None,
+    }
+}
+
+/// Like `register`, failing with a message that says a register was expected
+fn expect_register(operand: &str) -> Result<u8, String> {
+    register(operand).ok_or_else(|| format!("expected a register, V0 to VF, but found \"{}\"", operand))
+}
+
+/// A range of registers written as VX-VY
+fn register_range(operand: &str) -> Option<(u8, u8)> {
+    let (x, y) = operand.split_once('-')?;
+    Some((register(x.trim())?, register(y.trim())?))
+}
+
+/// Whether `name` is taken by the registers and the other operands of LD,
+/// which are looked for before labels, so a label called so could never be used
+fn is_reserved(name: &str) -> bool {
+    register(name).is_some()
+        || ["I", "DT", "ST", "F", "HF", "B", "R", "K"].iter().any(|reserved| name.eq_ignore_ascii_case(reserved))
+}
+
+/// Whether `name` can be a label: letters, digits and underscores, not starting with a digit
+fn is_identifier(name: &str) -> bool {
+    let mut characters = name.chars();
+    matches!(characters.next(), Some(first) if first.is_ascii_alphabetic() || first == '_')
+        && characters.all(|character| character.is_ascii_alphanumeric() || character == '_')
+}
+
+fn error(line: usize, message: String) -> Error {
+    Error::Assembly { line, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm;
+
+    const PROGRAM: &str = "
+        ; Bounces a dot around the screen
+        start:  CLS
+                LD V0,0x10      ; x
+                LD V1,8         ; y
+                LD I,dot
+        loop:   DRW V0,V1,1
+                CALL move
+                SE VF,0
+                JP start
+                JP loop
+        move:   ADD V0,1
+                SHR V2
+                LD DT,V2
+                LD V3,[I]
+                LD [I],V3
+                RET
+        dot:    DB 0b10000000, 0
+                DW 0x1234
+    ";
+
+    /// The instructions of a listing by `disasm::disassemble`, one per line
+    fn mnemonics(listing: &str) -> Vec<String> {
+        listing.lines().map(|line| line[18..].to_string()).collect()
+    }
+
+    #[test]
+    fn assembles_a_program() {
+        let rom = assemble(PROGRAM).unwrap();
+        assert_eq!(rom.len(), 34);
+        assert_eq!(rom[..8], [0x00, 0xE0, 0x60, 0x10, 0x61, 0x08, 0xA2, 0x1E]);
+        // Labels used before they are defined
+        assert_eq!(rom[10..12], [0x22, 0x12]);
+        assert_eq!(rom[30..], [0x80, 0x00, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn disassembles_back_to_the_same_program() {
+        let rom = assemble(PROGRAM).unwrap();
+        let listing = disasm::disassemble(PROGRAM_START, &rom[..30]);
+        assert_eq!(
+            mnemonics(&listing),
+            [
+                "CLS", "LD V0,0x10", "LD V1,0x08", "LD I,0x21e", "DRW V0,V1,0x1", "CALL 0x212",
+                "SE VF,0x00", "JP 0x200", "JP 0x208", "ADD V0,0x01", "SHR V2,V2", "LD DT,V2",
+                "LD V3,[I]", "LD [I],V3", "RET",
+            ]
+        );
+        // What the disassembler writes assembles back to the same bytes
+        assert_eq!(assemble(&mnemonics(&listing).join("\n")).unwrap(), rom[..30]);
+    }
+
+    #[test]
+    fn assembles_at_another_address() {
+        let rom = assemble_at("here: JP here", 0x600).unwrap();
+        assert_eq!(rom, [0x16, 0x00]);
+    }
+
+    #[test]
+    fn reports_undefined_labels() {
+        let err = assemble("CLS\nJP nowhere").unwrap_err();
+        assert!(matches!(&err, Error::Assembly { line: 2, .. }));
+        assert_eq!(err.to_string(), "line 2: undefined label \"nowhere\".");
+    }
+
+    #[test]
+    fn reports_values_out_of_range() {
+        assert!(matches!(assemble("LD V0,256"), Err(Error::Assembly { line: 1, .. })));
+        assert!(matches!(assemble("DRW V0,V1,16"), Err(Error::Assembly { line: 1, .. })));
+        assert!(matches!(assemble("\nJP 0x1000"), Err(Error::Assembly { line: 2, .. })));
+        assert!(matches!(assemble("DB 0x100"), Err(Error::Assembly { line: 1, .. })));
+        assert!(assemble("LD V0,255\nDRW V0,V1,15\nJP 0xfff").is_ok());
+    }
+
+    #[test]
+    fn rejects_register_names_as_labels() {
+        for label in ["VA", "v0", "I", "DT", "st", "K"] {
+            let source = format!("{}: CLS", label);
+            assert!(matches!(assemble(&source), Err(Error::Assembly { line: 1, .. })), "{}", label);
+        }
+        assert!(assemble("VAL: JP VAL").is_ok());
+    }
+
+    #[test]
+    fn reports_other_mistakes() {
+        let err = assemble("a: CLS\na: CLS").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: label \"a\" is defined twice.");
+        assert!(matches!(assemble("MOV V0,V1"), Err(Error::Assembly { line: 1, .. })));
+        assert!(matches!(assemble("ADD V0"), Err(Error::Assembly { line: 1, .. })));
+        assert!(matches!(assemble("LD V0,0xZZ"), Err(Error::Assembly { line: 1, .. })));
+    }
+}
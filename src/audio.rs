@@ -0,0 +1,236 @@
+/// Default pitch of the classic CHIP-8 buzzer, in Hz
+pub const BUZZER_FREQUENCY: f64 = 440.0;
+
+/// Default loudness of the generated sound, in percent
+pub const DEFAULT_VOLUME: u8 = 20;
+
+/// The loudest the generated sound can be, in percent
+pub const MAX_VOLUME: u8 = 100;
+
+/// How the sound is generated, as set with --beep-freq and --beep-volume
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Beep {
+    // Pitch of the classic buzzer, in Hz. XO-CHIP patterns play at their own rate.
+    pub frequency: f64,
+    // Loudness of all sound, in percent up to `MAX_VOLUME`
+    pub volume: u8,
+}
+
+impl Beep {
+    /// The amplitude of the samples, from 0 to 1
+    fn amplitude(&self) -> f32 {
+        self.volume.min(MAX_VOLUME) as f32 / 100.0
+    }
+}
+
+impl Default for Beep {
+    fn default() -> Beep {
+        Beep { frequency: BUZZER_FREQUENCY, volume: DEFAULT_VOLUME }
+    }
+}
+
+/// What the speaker plays while the sound timer is running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tone {
+    // The classic CHIP-8 buzzer, a plain square wave
+    Square,
+    // An XO-CHIP 128-bit sample pattern, most significant bit first,
+    // played at `rate` bits per second
+    Pattern { pattern: [u8; 16], rate: f64 },
+}
+
+/// 2^(1/48), the ratio between consecutive XO-CHIP pitches
+const PITCH_STEP: f64 = 1.0145453349375237;
+
+/// The XO-CHIP playback rate for the pitch set by FX3A, in bits per second.
+/// The default pitch of 64 plays 4000 bits per second, and every 48 steps double it.
+pub fn playback_rate(pitch: u8) -> f64 {
+    // Worked out without `powf`, which isn't available without std.
+    // Counting from the 1000 bits per second of a (virtual) pitch of -32, every
+    // whole 48 steps double the rate and each step left over multiplies it by `PITCH_STEP`.
+    let steps = pitch as u32 + 32;
+    let mut rate = 1000.0 * (1u32 << (steps / 48)) as f64;
+    for _ in 0..steps % 48 {
+        rate *= PITCH_STEP;
+    }
+    rate
+}
+
+/// Synthesizes `tone` into `out` as `beep` says, at `sample_rate` samples per second.
+/// `phase` is how far into the tone playback is, in periods for the square wave
+/// and in pattern bits for XO-CHIP patterns; it's advanced so that the next call
+/// continues seamlessly.
+pub fn synthesize(tone: &Tone, beep: &Beep, sample_rate: u32, phase: &mut f64, out: &mut [f32]) {
+    let (step, period) = match tone {
+        Tone::Square => (beep.frequency / sample_rate as f64, 1.0),
+        Tone::Pattern { rate, .. } => (rate / sample_rate as f64, 128.0),
+    };
+    let amplitude = beep.amplitude();
+    for sample in out.iter_mut() {
+        let high = match tone {
+            Tone::Square => *phase < 0.5,
+            Tone::Pattern { pattern, .. } => {
+                let bit = *phase as usize;
+                (pattern[bit / 8] >> (7 - bit % 8)) & 1 == 1
+            }
+        };
+        *sample = if high { amplitude } else { -amplitude };
+        *phase = (*phase + step) % period;
+    }
+}
+
+/// Something that plays the sound of the VM, such as the SDL audio device.
+/// A `Buzzer` tells it when to start and stop.
+pub trait AudioSink {
+    /// Starts sounding, with the last tone set
+    fn beep_on(&mut self);
+
+    /// Goes silent
+    fn beep_off(&mut self);
+
+    /// What to play from now on, always set before `beep_on`.
+    /// Sinks that can only buzz can leave it out.
+    fn set_tone(&mut self, _tone: Tone) {}
+}
+
+/// Plays nothing, for --mute or when there's no audio device
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn beep_on(&mut self) {}
+
+    fn beep_off(&mut self) {}
+}
+
+/// Turns the sound timer into calls to an `AudioSink`: it beeps when the timer
+/// starts and stops when it runs out.
+#[derive(Debug, Default)]
+pub struct Buzzer {
+    // What the sink is playing, None while it's silent
+    sounding: Option<Tone>,
+}
+
+impl Buzzer {
+    /// Tells `sink` about any change in what should be playing.
+    /// Called once per frame with the current `VirtualMachine::tone`.
+    pub fn update(&mut self, tone: Option<Tone>, sink: &mut dyn AudioSink) {
+        if tone == self.sounding {
+            return;
+        }
+        match tone {
+            Some(tone) => {
+                sink.set_tone(tone);
+                if self.sounding.is_none() {
+                    sink.beep_on();
+                }
+            }
+            None => sink.beep_off(),
+        }
+        self.sounding = tone;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::VirtualMachine;
+    use crate::rom::Cartridge;
+    use alloc::vec::Vec;
+
+    /// Keeps every call it gets
+    #[derive(Default)]
+    struct MockSink {
+        calls: Vec<&'static str>,
+    }
+
+    impl AudioSink for MockSink {
+        fn beep_on(&mut self) {
+            self.calls.push("on");
+        }
+
+        fn beep_off(&mut self) {
+            self.calls.push("off");
+        }
+
+        fn set_tone(&mut self, tone: Tone) {
+            self.calls.push(match tone {
+                Tone::Square => "square",
+                Tone::Pattern { .. } => "pattern",
+            });
+        }
+    }
+
+    #[test]
+    fn beeps_while_the_sound_timer_runs() {
+        // LD V0,3; LD ST,V0; JP 0x204
+        let rom = [0x60, 0x03, 0xF0, 0x18, 0x12, 0x04];
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&Cartridge::from_bytes(&rom).unwrap()).unwrap();
+        let mut buzzer = Buzzer::default();
+        let mut sink = MockSink::default();
+
+        vm.run_cycle().unwrap();
+        buzzer.update(vm.tone(), &mut sink);
+        assert!(sink.calls.is_empty());
+
+        // The timer goes from 0 to 3
+        vm.run_cycle().unwrap();
+        buzzer.update(vm.tone(), &mut sink);
+        assert_eq!(sink.calls, ["square", "on"]);
+
+        // 2, then 1: still beeping, with nothing new to say
+        for _ in 0..2 {
+            vm.tick_timers();
+            buzzer.update(vm.tone(), &mut sink);
+        }
+        assert_eq!(sink.calls, ["square", "on"]);
+
+        // It hits 0
+        vm.tick_timers();
+        buzzer.update(vm.tone(), &mut sink);
+        assert_eq!(sink.calls, ["square", "on", "off"]);
+        vm.tick_timers();
+        buzzer.update(vm.tone(), &mut sink);
+        assert_eq!(sink.calls, ["square", "on", "off"]);
+    }
+
+    #[test]
+    fn changes_tone_without_stopping() {
+        let pattern = Tone::Pattern { pattern: [0xF0; 16], rate: 4000.0 };
+        let mut buzzer = Buzzer::default();
+        let mut sink = MockSink::default();
+        buzzer.update(Some(Tone::Square), &mut sink);
+        buzzer.update(Some(pattern), &mut sink);
+        buzzer.update(None, &mut sink);
+        assert_eq!(sink.calls, ["square", "on", "pattern", "off"]);
+    }
+
+    #[test]
+    fn plays_patterns_most_significant_bit_first() {
+        let tone = Tone::Pattern { pattern: [0b1010_0000; 16], rate: 1.0 };
+        let beep = Beep { volume: 100, ..Beep::default() };
+        let mut phase = 0.0;
+        let mut out = [0.0; 4];
+        synthesize(&tone, &beep, 1, &mut phase, &mut out);
+        assert_eq!(out, [1.0, -1.0, 1.0, -1.0]);
+        assert_eq!(phase, 4.0);
+    }
+
+    #[test]
+    fn doubles_the_rate_every_48_pitches() {
+        assert!((playback_rate(64) - 4000.0).abs() < 0.01);
+        assert!((playback_rate(112) - 8000.0).abs() < 0.01);
+        assert!((playback_rate(16) - 2000.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn square_waves_repeat_at_the_beep_frequency() {
+        // 1000 Hz at 8000 samples per second: 4 samples high, then 4 low
+        let beep = Beep { frequency: 1000.0, volume: 50 };
+        let mut phase = 0.0;
+        let mut out = [0.0; 16];
+        synthesize(&Tone::Square, &beep, 8000, &mut phase, &mut out);
+        assert_eq!(out[..8], [0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5]);
+        assert_eq!(out[..8], out[8..]);
+    }
+}
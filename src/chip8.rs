@@ -1,10 +1,30 @@
 use crate::rom::Cartridge;
-use std::fmt;
-use rand::Rng;
+use crate::disasm;
+use crate::instruction::{self, Instruction};
+use crate::stats::{OpcodeStats, Stats};
+use crate::rewind::RewindBuffer;
+use crate::quirks::Quirks;
+use crate::profile::Profile;
+use crate::audio::{self, Tone};
+use crate::debugger::OpcodePattern;
+use crate::render::Framebuffer;
+use crate::error::Error;
+use crate::timing::FRAME_RATE;
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use log::trace;
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use std::io::{Read, Write};
 
 /// The fontset for the CHIP-8.
 /// Every character is 4 pixels wide and 5 pixels tall.
-const FONTSET: [u8; 80] = [
+/// Frontends may draw with it too, as the ROM browser does.
+pub const FONTSET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -23,8 +43,149 @@ const FONTSET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
-const SCREEN_WIDTH: usize  = 64;
-const SCREEN_HEIGHT: usize = 32;
+/// The SUPER-CHIP large fontset, for the digits 0 to 9 only.
+/// Every digit is 8 pixels wide and 10 pixels tall.
+const BIG_FONTSET: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+/// Where the fontset is loaded into memory, as most interpreters do.
+/// FX29 points into it, so ROMs relying on this address find the glyphs there.
+const FONTSET_ADDRESS: usize = 0x50;
+
+/// The large fontset is loaded into memory right after the small one, at 0xA0
+const BIG_FONTSET_ADDRESS: usize = FONTSET_ADDRESS + FONTSET.len();
+
+/// Room for a large font with all 16 hex digits, as some SUPER-CHIP
+/// and XO-CHIP interpreters have, even though the built-in one stops at 9
+const BIG_FONTSET_CAPACITY: usize = 16 * 10;
+
+/// Number of SUPER-CHIP RPL user flags
+pub const RPL_FLAG_COUNT: usize = 8;
+
+/// Where programs are loaded, and start running from, unless told otherwise
+pub const PROGRAM_START: u16 = 0x200;
+
+/// Screen size in the standard low resolution mode
+pub const LORES_WIDTH: usize  = 64;
+pub const LORES_HEIGHT: usize = 32;
+
+/// Screen size in the SUPER-CHIP high resolution mode
+pub const HIRES_WIDTH: usize  = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// The screen buffer is big enough for the high resolution mode.
+/// In low resolution only its top-left 64x32 area is used.
+pub const SCREEN_WIDTH: usize  = HIRES_WIDTH;
+pub const SCREEN_HEIGHT: usize = HIRES_HEIGHT;
+
+/// The first plane is the classic CHIP-8 screen, XO-CHIP adds a second one
+pub const PLANE_COUNT: usize = 2;
+
+/// One plane of the screen, one row per `u128`: bit X of row Y is the pixel at (X, Y)
+pub type Plane = [u128; SCREEN_HEIGHT];
+
+/// How many events are kept for `VirtualMachine::take_events` before the oldest are dropped
+pub const EVENT_CAPACITY: usize = 256;
+
+/// Something the running program did that a frontend may want to stop at.
+/// Collected while running, see `VirtualMachine::take_events`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chip8Event {
+    // A watched address was written to, see `VirtualMachine::add_watchpoint`,
+    // by the instruction `opcode` at `pc`. `old` is what was there before.
+    MemoryWrite { pc: u16, opcode: u16, addr: u16, old: u8, value: u8 },
+    // A watched register changed, see `VirtualMachine::add_register_watch`,
+    // by the instruction `opcode` at `pc`
+    RegisterChange { pc: u16, opcode: u16, register: Register, old: u16, value: u16 },
+}
+
+impl fmt::Display for Chip8Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Event::MemoryWrite { pc, opcode, addr, old, value } => write!(
+                f,
+                "Wrote {:#04x} to {:#05x} (was {:#04x}) by {:04X} at {:#05x}",
+                value, addr, old, opcode, pc
+            ),
+            Chip8Event::RegisterChange { pc, opcode, register, old, value } => write!(
+                f,
+                "Set {} to {:#04x} (was {:#04x}) by {:04X} at {:#05x}",
+                register, value, old, opcode, pc
+            ),
+        }
+    }
+}
+
+/// A register whose changes can be watched, see `VirtualMachine::add_register_watch`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    // One of V0 to VF
+    V(u8),
+    I,
+}
+
+impl Register {
+    /// Parses a register name such as `VA` or `I`, in either case
+    pub fn parse(name: &str) -> Option<Register> {
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("i") {
+            return Some(Register::I);
+        }
+        let digit = name.strip_prefix('V').or_else(|| name.strip_prefix('v'))?;
+        match u8::from_str_radix(digit, 16) {
+            Ok(x) if digit.len() == 1 => Some(Register::V(x)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+        }
+    }
+}
+
+/// The whole register state of `vm`, a few registers per line, as shown by the debug overlay,
+/// then the speed over the last frame in instructions per second:
+///
+/// ```text
+/// PC 0200 I 0000 SP 0
+/// DT 00 ST 00
+/// V0 00 V1 00 V2 00 V3 00
+/// V4 00 V5 00 V6 00 V7 00
+/// V8 00 V9 00 VA 00 VB 00
+/// VC 00 VD 00 VE 00 VF 00
+/// IPS 600
+/// ```
+pub fn debug_lines(vm: &VirtualMachine) -> Vec<String> {
+    let mut lines = vec![
+        format!("PC {:04X} I {:04X} SP {:X}", vm.pc, vm.I, vm.sp),
+        format!("DT {:02X} ST {:02X}", vm.delay_timer, vm.sound_timer),
+    ];
+    for (line, registers) in vm.V.chunks(4).enumerate() {
+        let registers: Vec<String> = registers
+            .iter()
+            .enumerate()
+            .map(|(i, v)| format!("V{:X} {:02X}", line * 4 + i, v))
+            .collect();
+        lines.push(registers.join(" "));
+    }
+    lines.push(format!("IPS {}", vm.stats.frame_instructions * FRAME_RATE as u64));
+    lines
+}
 
 #[derive(PartialEq)]
 /// Used by comparison opcodes
@@ -56,14 +217,15 @@ pub struct VirtualMachine {
     opcode: u16,
 
     // Represents the Chip-8 stack
-    pub stack: [u8; 16],
+    pub stack: [u16; 16],
 
     // Stack pointer
     pub sp: u16,
 
     /* Represents the 4KB of memory that
-    the CHIP-8 has. */
-    memory: [u8; 4096],
+    the CHIP-8 has, or the 64KB of XO-CHIP.
+    Addresses past the end wrap around. */
+    memory: Vec<u8>,
 
     /* CPU registers:
        15 general purpose registers (V0, V1, ..., VE)
@@ -77,11 +239,40 @@ pub struct VirtualMachine {
     // Program counter
     pc: u16,
 
-    // The CHIP-8 has a 64 x 32 screen
-    // The `graphics` array holds the state of every pixel
-    // If true, the pixel is white.
+    // Where `load_rom` loads programs, and so where they start running
+    load_address: u16,
+
+    // The CHIP-8 has a 64 x 32 screen, SUPER-CHIP adds a 128 x 64 mode
+    // The `graphics` array holds the state of every pixel, as one bitset per plane.
+    // Only XO-CHIP programs draw to the second plane.
+    // Only the `width()` x `height()` top-left area is in use.
     // pub graphics: [u8; 64 * 32],
-    pub graphics: [[u8; SCREEN_WIDTH]; SCREEN_HEIGHT],
+    // Frontends read it through `framebuffer`.
+    graphics: [Plane; PLANE_COUNT],
+
+    // Which pixels changed since the frontend last drew the screen,
+    // one bit per pixel, bit X of row Y for the pixel at (X, Y)
+    dirty: [u128; SCREEN_HEIGHT],
+
+    // Whether the SUPER-CHIP 128 x 64 mode is active
+    hires: bool,
+
+    // Set by 00FD, or by a 1NNN jumping to itself under `halt_on_spin`.
+    // A halted machine doesn't run any more instructions.
+    halted: bool,
+
+    // Whether it was the self-jump that halted the machine, see `is_spinning`
+    spinning: bool,
+
+    // Whether a 1NNN jumping to itself halts the machine, see `VirtualMachineBuilder::halt_on_spin`
+    halt_on_spin: bool,
+
+    // Whether the XO-CHIP extensions are enabled, as by `Profile::XoChip`
+    xo_chip: bool,
+
+    // The planes drawn to, cleared and scrolled, as selected by the XO-CHIP FN01.
+    // Always only the first plane when not emulating XO-CHIP.
+    plane_mask: u8,
 
     // If true, the contents of `graphics` will be drawn to screen
     pub draw_to_screen: bool,
@@ -95,598 +286,3023 @@ pub struct VirtualMachine {
 
     // `sound_timer` is the buzzer's timer
     // The buzzer sounds whenever this timer reaches zero
-    sound_timer: u8
+    sound_timer: u8,
+
+    // XO-CHIP sample pattern loaded by F002, played instead of the buzzer once set
+    audio_pattern: [u8; 16],
+
+    // XO-CHIP playback rate of `audio_pattern`, set by FX3A
+    pitch: u8,
+
+    // SUPER-CHIP RPL user flags, written by FX75 and read by FX85.
+    // Games keep high scores in them, so the frontend saves them across runs.
+    // They aren't part of `VmState`: rewinding or loading a state leaves them alone.
+    pub rpl_flags: [u8; RPL_FLAG_COUNT],
+
+    // Counts instructions, frames and unknown opcodes
+    pub stats: Stats,
+
+    // Snapshots taken before every cycle, if enabled through `enable_rewind`
+    history: Option<RewindBuffer>,
+
+    // Counts of every kind of instruction run, if enabled through `enable_opcode_stats`
+    opcode_stats: Option<OpcodeStats>,
+
+    // Source of the random numbers used by CXNN
+    rng: SmallRng,
+
+    // Which interpreter quirks to emulate
+    quirks: Quirks,
+
+    // Set at every timer tick. Used by the `dispwait` quirk, under which
+    // DXYN waits for the start of the next frame before drawing.
+    vblank: bool,
+
+    // Addresses to stop at before running the instruction there, see `should_break`
+    breakpoints: BTreeSet<u16>,
+    // Opcodes to stop at before running them, wherever they are
+    opcode_breakpoints: Vec<OpcodePattern>,
+
+    // Where execution last stopped at a breakpoint, so that resuming runs the instruction
+    // there. Breakpoints stay disarmed until the program counter moves off it, which
+    // FX0A waiting for a key doesn't.
+    resume_from: Option<u16>,
+
+    // Addresses whose writes are reported as events, see `add_watchpoint`
+    watchpoints: BTreeSet<u16>,
+    // Registers whose changes are reported as events, see `add_register_watch`
+    register_watches: BTreeSet<Register>,
+
+    // What happened since the frontend last asked, see `take_events`
+    events: Vec<Chip8Event>
+}
+
+// Allow non-snake-case naming of variables I and V.
+#[allow(non_snake_case)]
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// A copy of the emulated state of the virtual machine.
+/// Obtained through `VirtualMachine::snapshot` and given back
+/// to `VirtualMachine::restore`.
+pub struct VmState {
+    opcode: u16,
+    stack: [u16; 16],
+    sp: u16,
+    memory: Vec<u8>,
+    V: [u8; 16],
+    I: u16,
+    pc: u16,
+    #[cfg_attr(feature = "serde", serde(with = "screen_serde"))]
+    graphics: [Plane; PLANE_COUNT],
+    hires: bool,
+    halted: bool,
+    spinning: bool,
+    plane_mask: u8,
+    keypad: [u8; 16],
+    delay_timer: u8,
+    sound_timer: u8,
+    audio_pattern: [u8; 16],
+    pitch: u8
+}
+
+#[cfg(feature = "serde")]
+impl VmState {
+    /// Writes the state in bincode format, e.g. to a save file,
+    /// along with the SHA-1 of the ROM it was made from
+    pub fn save_to_writer<W: Write>(&self, rom_sha1: &str, w: W) -> Result<(), Error> {
+        bincode::serialize_into(w, &(rom_sha1, self)).map_err(Error::InvalidState)
+    }
+
+    /// Reads back a state written by `save_to_writer`.
+    /// Fails unless it was made from the ROM with SHA-1 `rom_sha1` on a machine
+    /// of the given `profile`, and holds a stack pointer that fits the stack.
+    pub fn load_from_reader<R: Read>(r: R, rom_sha1: &str, profile: Profile) -> Result<VmState, Error> {
+        let (saved_sha1, state): (String, VmState) = bincode::deserialize_from(r).map_err(Error::InvalidState)?;
+        if saved_sha1 != rom_sha1 {
+            return Err(invalid_state("it was saved from another ROM".to_string()));
+        }
+        if state.memory.len() != profile.memory_size() {
+            let message = format!("it has {} bytes of memory instead of {}", state.memory.len(), profile.memory_size());
+            return Err(invalid_state(message));
+        }
+        if state.sp as usize > state.stack.len() {
+            return Err(invalid_state(format!("its stack pointer {} is past the stack", state.sp)));
+        }
+        Ok(state)
+    }
+}
+
+/// A save state that could be read, but can't be loaded
+#[cfg(feature = "serde")]
+fn invalid_state(message: String) -> Error {
+    Error::InvalidState(Box::new(bincode::ErrorKind::Custom(message)))
+}
+
+/// Serde does not support arrays as big as the screen buffer,
+/// so it is (de)serialized as a flat sequence of rows instead, plane after plane.
+#[cfg(feature = "serde")]
+mod screen_serde {
+    use super::{Plane, PLANE_COUNT, SCREEN_HEIGHT};
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    type Screen = [Plane; PLANE_COUNT];
+
+    pub fn serialize<S: Serializer>(graphics: &Screen, serializer: S) -> Result<S::Ok, S::Error> {
+        let rows: Vec<u128> = graphics.iter().flatten().copied().collect();
+        rows.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Screen, D::Error> {
+        let rows = Vec::<u128>::deserialize(deserializer)?;
+        if rows.len() != SCREEN_HEIGHT * PLANE_COUNT {
+            return Err(D::Error::invalid_length(rows.len(), &"two planes of 64 rows"));
+        }
+        let mut graphics = [[0; SCREEN_HEIGHT]; PLANE_COUNT];
+        for (plane, chunk) in graphics.iter_mut().zip(rows.chunks(SCREEN_HEIGHT)) {
+            plane.copy_from_slice(chunk);
+        }
+        Ok(graphics)
+    }
+}
+
+impl Default for VirtualMachine {
+    fn default() -> VirtualMachine {
+        VirtualMachine::new()
+    }
+}
+
+/// Sets up a `VirtualMachine` one option at a time, starting from
+/// `VirtualMachine::builder()`. Options left unset keep the defaults of `VirtualMachine::new`.
+#[derive(Debug, Clone, Copy)]
+pub struct VirtualMachineBuilder {
+    quirks: Quirks,
+    seed: Option<u64>,
+    profile: Profile,
+    load_address: u16,
+    halt_on_spin: bool,
+}
+
+impl Default for VirtualMachineBuilder {
+    fn default() -> VirtualMachineBuilder {
+        VirtualMachineBuilder {
+            quirks: Quirks::default_set(),
+            seed: None,
+            profile: Profile::default(),
+            load_address: PROGRAM_START,
+            halt_on_spin: false,
+        }
+    }
+}
+
+impl VirtualMachineBuilder {
+    /// The interpreter quirks to emulate
+    pub fn quirks(mut self, quirks: Quirks) -> VirtualMachineBuilder {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Seeds the random number generator, making runs reproducible
+    pub fn seed(mut self, seed: u64) -> VirtualMachineBuilder {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// The platform to emulate, which decides how much memory there is
+    /// and whether XO-CHIP instructions are available
+    pub fn profile(mut self, profile: Profile) -> VirtualMachineBuilder {
+        self.profile = profile;
+        self
+    }
+
+    /// Where `load_rom` loads programs and starts running them, 0x200 by default
+    pub fn load_address(mut self, address: u16) -> VirtualMachineBuilder {
+        self.load_address = address;
+        self
+    }
+
+    /// Whether to halt when the program jumps to the very instruction doing the jump.
+    /// Many programs end in such a spin loop, which would otherwise run forever.
+    pub fn halt_on_spin(mut self, halt_on_spin: bool) -> VirtualMachineBuilder {
+        self.halt_on_spin = halt_on_spin;
+        self
+    }
+
+    /// Creates the machine. Fails if the load address is past the end of its memory.
+    /// Can be called again for a fresh machine with the same options.
+    pub fn build(&self) -> Result<VirtualMachine, Error> {
+        let memory_size = self.profile.memory_size();
+        if self.load_address as usize >= memory_size {
+            return Err(Error::LoadAddressOutOfRange { address: self.load_address, memory_size });
+        }
+        let mut vm = VirtualMachine::with_options(self.quirks, self.seed, self.profile);
+        vm.load_address = self.load_address;
+        vm.pc = self.load_address;
+        vm.halt_on_spin = self.halt_on_spin;
+        Ok(vm)
+    }
 }
 
 impl fmt::Display for VirtualMachine {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "OP: {:#04x}, PC: {:#04x}, I: {:#04x}\n", self.opcode, self.pc, self.I)
+        writeln!(f, "OP: {:#04x}, PC: {:#04x}, I: {:#04x}", self.opcode, self.pc, self.I)
     }
 }
 
 #[allow(dead_code)]
 impl VirtualMachine {
-    /// Creates and initializes all the variables within the virtual machine
+    /// Creates and initializes all the variables within the virtual machine,
+    /// with the quirks of `Quirks::default_set`.
+    /// The random number generator is seeded from the system's entropy.
     pub fn new() -> VirtualMachine {
+        VirtualMachine::with_options(Quirks::default_set(), None, Profile::default())
+    }
+
+    /// Starts setting up a virtual machine, see `VirtualMachineBuilder`
+    pub fn builder() -> VirtualMachineBuilder {
+        VirtualMachineBuilder::default()
+    }
+
+    /// Creates a virtual machine whose random number generator is seeded
+    /// with `seed`, so that runs with the same input are reproducible.
+    pub fn with_seed(seed: u64) -> VirtualMachine {
+        VirtualMachine::with_options(Quirks::default_set(), Some(seed), Profile::default())
+    }
+
+    /// Creates a virtual machine emulating the given `quirks` on the platform of `profile`,
+    /// which decides how much memory it has and whether XO-CHIP instructions are available.
+    /// Without a `seed`, the random number generator is seeded from the system's entropy.
+    /// There's no entropy to draw from without std, so there the seed is up to the caller,
+    /// and the same one (zero) is always used if none is given.
+    pub fn with_options(quirks: Quirks, seed: Option<u64>, profile: Profile) -> VirtualMachine {
+        let rng = match seed {
+            Some(seed) => SmallRng::seed_from_u64(seed),
+            #[cfg(feature = "std")]
+            None => SmallRng::from_entropy(),
+            #[cfg(not(feature = "std"))]
+            None => SmallRng::seed_from_u64(0)
+        };
         let mut vm = VirtualMachine {
             opcode: 0,
             I: 0,
             sp: 0,
             // The program counter starts at 0x200
             pc: 0x200,
+            load_address: PROGRAM_START,
             // Fill the stack with zeroes
             stack: [0; 16],
             // Clean the keypad state
             keypad: [0; 16],
             // Fill the memory with zeroes
-            memory: [0; 4096],
+            memory: vec![0; profile.memory_size()],
             // Clear display (all black)
-            graphics: [[0; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            graphics: [[0; SCREEN_HEIGHT]; PLANE_COUNT],
+            // Everything must be drawn at first
+            dirty: [u128::MAX; SCREEN_HEIGHT],
+            // Start in the standard resolution
+            hires: false,
+            halted: false,
+            spinning: false,
+            halt_on_spin: false,
+            xo_chip: profile == Profile::XoChip,
+            // Draw to the first plane only
+            plane_mask: 1,
             // Clear registers
             V: [0; 16],
             // There's nothing to draw to screen yet
             draw_to_screen: false,
             // Reset timers
             sound_timer: 0,
-            delay_timer: 0
+            delay_timer: 0,
+            audio_pattern: [0; 16],
+            // Plays 4000 bits per second
+            pitch: 64,
+            rpl_flags: [0; RPL_FLAG_COUNT],
+            // Start counting from scratch
+            stats: Stats::new(),
+            // Snapshotting every cycle is costly, so it starts disabled
+            history: None,
+            opcode_stats: None,
+            rng,
+            quirks,
+            vblank: true,
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: Vec::new(),
+            resume_from: None,
+            watchpoints: BTreeSet::new(),
+            register_watches: BTreeSet::new(),
+            events: Vec::new()
         };
 
-        // Load the fontset into memory
-        for (i, &byte) in FONTSET.iter().enumerate() {
-            // println!("FONTSET[{}] = {}", i, byte);
-            vm.memory[i] = byte;
-        }
+        // Load the fontsets into memory
+        vm.memory[FONTSET_ADDRESS..FONTSET_ADDRESS + FONTSET.len()].copy_from_slice(&FONTSET);
+        vm.memory[BIG_FONTSET_ADDRESS..BIG_FONTSET_ADDRESS + BIG_FONTSET.len()]
+            .copy_from_slice(&BIG_FONTSET);
 
         vm
     }
 
-    /// Reads a new opcode from memory
-    fn fetch_opcode(&self) -> u16 {
-        let first_byte = (self.memory[self.pc as usize] as u16) << 8; // Cast the memory position to u16 to avoid arith. overflow
-        let second_byte = (self.memory[self.pc as usize + 1_usize]) as u16;
-        first_byte | second_byte
+    /// Copies the current state of the machine
+    pub fn snapshot(&self) -> VmState {
+        VmState {
+            opcode: self.opcode,
+            stack: self.stack,
+            sp: self.sp,
+            memory: self.memory.clone(),
+            V: self.V,
+            I: self.I,
+            pc: self.pc,
+            graphics: self.graphics,
+            hires: self.hires,
+            halted: self.halted,
+            spinning: self.spinning,
+            plane_mask: self.plane_mask,
+            keypad: self.keypad,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch
+        }
     }
 
-    /// Clears the CHIP-80 screen
-    fn clear_screen(&mut self) {
-        self.graphics = [[0; SCREEN_WIDTH]; SCREEN_HEIGHT];
+    /// Brings the machine back to a previously taken snapshot
+    pub fn restore(&mut self, state: &VmState) {
+        self.opcode = state.opcode;
+        self.stack = state.stack;
+        self.sp = state.sp;
+        self.memory = state.memory.clone();
+        self.V = state.V;
+        self.I = state.I;
+        self.pc = state.pc;
+        self.graphics = state.graphics;
+        self.dirty = [u128::MAX; SCREEN_HEIGHT];
+        self.hires = state.hires;
+        self.halted = state.halted;
+        self.spinning = state.spinning;
+        self.plane_mask = state.plane_mask;
+        self.keypad = state.keypad;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.audio_pattern = state.audio_pattern;
+        self.pitch = state.pitch;
+        // The restored screen must be redrawn
         self.draw_to_screen = true;
     }
 
-    #[allow(non_snake_case)]
-    /// Returns the current values of X and VX
-    fn vx(&mut self) -> (u8, u8) {
-        let X = (self.opcode & 0x0F00) >> 8;
-        (X as u8, self.V[X as usize])
+    /// Starts keeping a snapshot before every cycle, up to `capacity`
+    /// of them, so that execution can be stepped backwards with `rewind`.
+    /// Copying 4KB of memory each cycle is expensive, so this is opt-in.
+    pub fn enable_rewind(&mut self, capacity: usize) {
+        self.history = Some(RewindBuffer::new(capacity));
     }
 
-    #[allow(non_snake_case)]
-    /// Returns the current values of Y and VY
-    fn vy(&mut self) -> (u8, u8) {
-        let Y = (self.opcode & 0x00F0) >> 4;
-        (Y as u8, self.V[Y as usize])
+    /// Starts counting how many times each kind of instruction runs, see `opcode_stats`.
+    /// Counting costs a lookup on every cycle, so this is opt-in.
+    pub fn enable_opcode_stats(&mut self) {
+        self.opcode_stats = Some(OpcodeStats::new());
     }
 
-    /// Returns both the current VX and the current VY
-    fn vx_vy(&mut self) -> ((u8, u8), (u8, u8)) {
-        (self.vx(), self.vy())
+    /// The counts of every kind of instruction run since `enable_opcode_stats`, if it was called
+    pub fn opcode_stats(&self) -> Option<&OpcodeStats> {
+        self.opcode_stats.as_ref()
     }
 
-    #[allow(non_snake_case)]
-    /// Executes a binary operation between VX and VY and attributes it to VX.
-    fn vx_vy_bin_op(&mut self, binop: BinOp) {
-        // let X = (self.opcode & 0x0F00) >> 8;
-        // let Y = (self.opcode & 0x00F0) >> 4;
-        // let VY = self.V[Y as usize];
-        let ((X, _), (_, VY)) = self.vx_vy();
-        match binop {
-            BinOp::Attrib => {
-                self.V[X as usize] = VY;
-            }
+    /// Stops snapshotting and discards the recorded history
+    pub fn disable_rewind(&mut self) {
+        self.history = None;
+    }
 
-            BinOp::Xor => {
-                self.V[X as usize] ^= VY;
-            }
+    /// Undoes the last executed cycle.
+    /// Returns false if rewinding is disabled or the history is exhausted.
+    pub fn rewind(&mut self) -> bool {
+        let state = match self.history.as_mut().and_then(|history| history.pop()) {
+            Some(state) => state,
+            None => return false,
+        };
+        self.restore(&state);
+        true
+    }
 
-            BinOp::And => {
-                self.V[X as usize] &= VY;
-            }
+    /// Address of the instruction about to be executed
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
 
-            BinOp::Or => {
-                self.V[X as usize] |= VY;
-            }
-        }
-        self.pc += 2;
+    /// How many subroutine calls deep the program is, 0 outside of any
+    pub fn sp(&self) -> u16 {
+        self.sp
     }
 
-    #[allow(non_snake_case)]
-    /// Skips an instruction dependending on if VX and NN are equal (or unequal).
-    /// Used by opcodes 3XNN and 4XNN.
-    /// `cmptype` defines whether to skip an instruction if VX == N or if VX != N.
-    fn compare_vx_and_nn(&mut self, cmptype: ComparisonType) {
-        // let X = (self.opcode & 0x0F00) >> 8;
-        // let VX = self.V[X as usize] as u16;
-        let (_, VX) = self.vx();
-        let NN = (self.opcode & 0x00FF) as u8;
-        if cmptype == ComparisonType::Equality {
-            // Compare if VX == NN
-            if VX == NN {
-                // VX == NN, so we skip the next instruction
-                self.pc += 4;
-            } else {
-                self.pc += 2;
-            }
-        } else {
-            // Compare if VX != NN
-            if VX != NN {
-                // VX != NN, so we skip the next instruction
-                self.pc += 4;
-            } else {
-                self.pc += 2;
-            }
+    /// The addresses of the CALL instructions the program is in, outermost first.
+    /// Each returns to the instruction after it.
+    pub fn call_stack(&self) -> Vec<u16> {
+        self.stack[..self.sp as usize].to_vec()
+    }
+
+    /// How the program got to the instruction at the program counter, one line per call,
+    /// innermost first, to report along with an error, e.g.
+    /// ```text
+    ///   at   0x20c: 0123  SYS 0x123
+    ///   from 0x208: 220C  CALL 0x20c
+    ///   from 0x200: 2208  CALL 0x208
+    /// ```
+    pub fn backtrace(&self) -> String {
+        let line = |label: &str, address: u16| {
+            let opcode = u16::from_be_bytes([self.read(address as usize), self.read(address as usize + 1)]);
+            format!("  {:<4} {:#05x}: {:04X}  {}\n", label, address, opcode, disasm::mnemonic(opcode))
+        };
+        let mut text = line("at", self.pc);
+        for &address in self.call_stack().iter().rev() {
+            text.push_str(&line("from", address));
         }
+        text
     }
 
-    #[allow(non_snake_case)]
-    fn draw_sprite(&mut self) {
+    /// The index register, I
+    pub fn index(&self) -> u16 {
+        self.I
+    }
 
-        // x := The contents of VX, where X is specified by the current opcode
-        // y := The contents of VY, where Y is specified by the current opcode
-        let ((_, x), (_, y)) = self.vx_vy();
-        let n = (self.opcode & 0x000F) as u8;
+    /// Overwrites the register VX, as a debugger would
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.V[x & 0xF] = value;
+    }
 
-        // Reset VF
-        self.V[0xF as usize] = 0;
+    /// Overwrites the index register I, as a debugger would
+    pub fn set_index(&mut self, value: u16) {
+        self.I = value;
+    }
 
-        for byte in 0..(n as usize) {
-            // Wrap around if overflown
-            let y = (self.V[y as usize] as usize + byte) % SCREEN_HEIGHT;
-            for bit in 0..8 {
-                let x = (self.V[x as usize] as usize + bit) % SCREEN_WIDTH;
-                let I = self.I as usize;
-                let color = (self.memory[I + byte] >> (7 - bit)) & 1;
-                self.V[0x0F] |= color & self.graphics[y][x];
-                self.graphics[y][x] ^= color;
-            }
-        }
+    /// Moves the program counter to `address`, wrapped into memory, as a debugger would
+    pub fn set_pc(&mut self, address: u16) {
+        self.pc = (address as usize % self.memory.len()) as u16;
+    }
 
-        self.draw_to_screen = true;
+    /// Stops execution before the instruction at `address`, see `should_break`
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
     }
 
-    pub fn load_rom(& mut self, cart: Cartridge)
-    {
-        for i in 0..cart.size {
-            self.memory[(i+512) as usize] = cart.data[i as usize];
+    /// Lets execution run through `address` again
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    /// Stops execution before any instruction `pattern` matches, see `should_break`
+    pub fn add_opcode_breakpoint(&mut self, pattern: OpcodePattern) {
+        if !self.opcode_breakpoints.contains(&pattern) {
+            self.opcode_breakpoints.push(pattern);
         }
     }
 
-    #[allow(non_snake_case)]
-    pub fn run_cycle(&mut self) {
-        self.opcode = self.fetch_opcode();
-        match self.opcode & 0xF000 {
-            0x0000 => {
-                /* Opcode's first byte is null, so
-                we must now only compare its last byte. */
-                match self.opcode & 0x000F {
-                    0x0000 => {
-                        p!(:"Opcode 00E0: Clears the screen");
-                        // Opcode 00E0: Clears the screen
-                        self.clear_screen();
-                        self.pc += 2;
-                    }
+    /// Lets execution run through the instructions `pattern` matches again
+    pub fn remove_opcode_breakpoint(&mut self, pattern: OpcodePattern) {
+        self.opcode_breakpoints.retain(|&other| other != pattern);
+    }
 
-                    0x000E => {
-                        p!(:"Opcode 0EE: Returns from subroutine");
-                        // Opcode 0EE: Returns from subroutine
-                        self.sp -= 1;
-                        let new_program_counter = self.stack[self.sp as usize];
-                        self.pc = new_program_counter as u16 + 2;
-                    }
+    /// Whether execution should stop before the next instruction, because there's
+    /// a breakpoint at it or on its opcode. Frontends check this before every `run_cycle`.
+    /// After stopping, it returns false until the program counter moves off the
+    /// instruction, so that execution can resume past the breakpoint.
+    pub fn should_break(&mut self) -> bool {
+        if self.resume_from == Some(self.pc) {
+            return false;
+        }
+        let on_opcode = !self.opcode_breakpoints.is_empty() && {
+            let opcode = self.fetch_opcode();
+            self.opcode_breakpoints.iter().any(|pattern| pattern.matches(opcode))
+        };
+        if !on_opcode && !self.breakpoints.contains(&self.pc) {
+            return false;
+        }
+        self.resume_from = Some(self.pc);
+        true
+    }
 
-                    op @ _ => {
-                        eprintln!("Unknown opcode [0x0000#04x{}]", op);
-                    }
-                }
-            }
+    /// Reports every write to `address` with a `Chip8Event::MemoryWrite`,
+    /// once the instruction doing it has run
+    pub fn add_watchpoint(&mut self, address: u16) {
+        self.watchpoints.insert(address);
+    }
 
-            0x1000 => {
-                p!(:"Opcode 1NNN: Jumps to address NNN");
-                // Opcode 1NNN: Jumps to address NNN
-                self.pc = self.opcode & 0x0FFF;
-            }
+    /// Stops reporting writes to `address`
+    pub fn remove_watchpoint(&mut self, address: u16) {
+        self.watchpoints.remove(&address);
+    }
 
-            0x2000 => {
-                p!(:"Opcode 2NNN: Calls subroutine located at NNN");
-                // Opcode 2NNN: Calls subroutine located at NNN
-                // TODO: make sure that `self.pc as u8` can't overflow
-                self.stack[self.sp as usize] = self.pc as u8;
-                self.sp += 1;
-                self.pc = self.opcode & 0x0FFF;
-            }
+    /// Reports every change to `register` with a `Chip8Event::RegisterChange`,
+    /// once the instruction making it has run
+    pub fn add_register_watch(&mut self, register: Register) {
+        self.register_watches.insert(register);
+    }
 
-            0x3000 => {
-                p!(:"Opcode 3XNN: Skips the next instruction if VX == NN.");
-                // Opcode 3XNN: Skips the next instruction if VX == NN.
-                self.compare_vx_and_nn(ComparisonType::Equality);
-            }
+    /// Stops reporting changes to `register`
+    pub fn remove_register_watch(&mut self, register: Register) {
+        self.register_watches.remove(&register);
+    }
 
-            0x4000 => {
-                p!(:"Opcode 4XNN: Skips the next instruction if VX != NN.");
-                // Opcode 4XNN: Skips the next instruction if VX != NN.
-                self.compare_vx_and_nn(ComparisonType::Inequality);
-            }
+    /// The events since the last call, oldest first.
+    /// Frontends check this after every `run_cycle`. Only the last
+    /// `EVENT_CAPACITY` are kept for frontends that don't.
+    pub fn take_events(&mut self) -> Vec<Chip8Event> {
+        core::mem::take(&mut self.events)
+    }
 
-            0x5000 => {
-                p!(:"Opcode 5XY0: Skips the next instruction if VX == VY");
-                // Opcode 5XY0: Skips the next instruction if VX == VY
-                // let X = (self.opcode & 0x0F00) >> 8;
-                // let VX = self.V[X as usize] as u16;
-                // let Y = (self.opcode & 0x00F0) >> 4;
-                // let VY = self.V[Y as usize] as u16;
-                let ((_, VX), (_, VY)) = self.vx_vy();
-                if VX == VY {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
+    /// Records `event` for `take_events`, dropping the oldest one if there are too many
+    fn push_event(&mut self, event: Chip8Event) {
+        if self.events.len() >= EVENT_CAPACITY {
+            self.events.remove(0);
+        }
+        self.events.push(event);
+    }
+
+    /// Reports the watched registers that differ from `registers` (V0 to VF) and `index` (I),
+    /// their values before the instruction `opcode` at `pc` ran
+    fn report_register_changes(&mut self, pc: u16, opcode: u16, registers: [u8; 16], index: u16) {
+        let watched: Vec<Register> = self.register_watches.iter().copied().collect();
+        for register in watched {
+            let (old, value) = match register {
+                Register::V(x) => {
+                    let x = (x & 0xF) as usize;
+                    (registers[x] as u16, self.V[x] as u16)
                 }
+                Register::I => (index, self.I),
+            };
+            if old != value {
+                self.push_event(Chip8Event::RegisterChange { pc, opcode, register, old, value });
             }
+        }
+    }
 
-            0x6000 => {
-                p!(:"Opcode 6XNN: sets VX to NN");
-                // Opcode 6XNN: sets VX to NN
-                // let X  = (self.opcode & 0x0F00) >> 8;
-                let (X, _) = self.vx();
-                let NN = (self.opcode & 0x00FF) as u8;
-                self.V[X as usize] = NN;
-                self.pc += 2;
-            }
+    /// Replaces the built-in font used by FX29 with `font`: 5 bytes for each
+    /// character, from 0 up to F. Shorter fonts leave the rest of the built-in one in place.
+    pub fn load_fontset(&mut self, font: &[u8]) -> Result<(), Error> {
+        if font.len() > FONTSET.len() {
+            return Err(Error::FontTooBig { size: font.len(), max: FONTSET.len() });
+        }
+        self.memory[FONTSET_ADDRESS..FONTSET_ADDRESS + font.len()].copy_from_slice(font);
+        Ok(())
+    }
 
-            0x7000 => {
-                p!(:"Opcode 7XNN: Adds NN to VX.");
-                // Opcode 7XNN: Adds NN to VX.
-                // let X  = (self.opcode & 0x0F00) >> 8;
-                let (X, VX) = self.vx();
-                let mut NN = (self.opcode & 0x00FF) as u16;
-                NN = if NN + (VX as u16) > 255 {
-                    // Wrap around if overflown
-                    NN % 256
-                } else {
-                    NN                    
-                };
+    /// Replaces the built-in large font used by FX30 with `font`:
+    /// 10 bytes for each digit, which may go on past 9 up to F.
+    pub fn load_big_fontset(&mut self, font: &[u8]) -> Result<(), Error> {
+        if font.len() > BIG_FONTSET_CAPACITY {
+            return Err(Error::FontTooBig { size: font.len(), max: BIG_FONTSET_CAPACITY });
+        }
+        self.memory[BIG_FONTSET_ADDRESS..BIG_FONTSET_ADDRESS + font.len()].copy_from_slice(font);
+        Ok(())
+    }
 
-                self.V[X as usize] += NN as u8;
-                self.pc += 2;
-            }
+    /// Copies up to `len` bytes of memory starting at `start`, for inspection.
+    /// Unlike the program's own accesses, the range doesn't wrap around:
+    /// it's cut short at the end of memory.
+    pub fn dump_memory(&self, start: u16, len: usize) -> Vec<u8> {
+        let start = (start as usize).min(self.memory.len());
+        let end = start.saturating_add(len).min(self.memory.len());
+        self.memory[start..end].to_vec()
+    }
 
-            0x8000 => {
-                match self.opcode & 0x000F {
-                    0x0000 => {
-                        p!(:"Opcode 8XY0: Sets VX to the value of VY");
-                        // Opcode 8XY0: Sets VX to the value of VY
-                        self.vx_vy_bin_op(BinOp::Attrib);
-                    }
+    /// Formats up to `len` bytes of memory from `start` as a hex dump, 16 bytes a line,
+    /// see `disasm::hex_dump`. Like `dump_memory`, it stops at the end of memory.
+    pub fn hexdump(&self, start: u16, len: usize) -> String {
+        disasm::hex_dump(start, &self.dump_memory(start, len))
+    }
 
-                    0x0001 => {
-                        p!(:"Opcode 8XY1: Sets VX to (VX | VY)");
-                        // Opcode 8XY1: Sets VX to (VX | VY)
-                        self.vx_vy_bin_op(BinOp::Or);
-                    }
+    /// Reads the byte at `address`, wrapping around past the end of memory
+    fn read(&self, address: usize) -> u8 {
+        self.memory[address % self.memory.len()]
+    }
 
-                    0x0002 => {
-                        p!(:"Opcode 8XY2: Sets VX to (VX & VY)");
-                        // Opcode 8XY2: Sets VX to (VX & VY)
-                        self.vx_vy_bin_op(BinOp::And);
-                    }
+    /// Fails unless the `len` bytes from I are all in memory, for the instructions that
+    /// read or write several bytes there: sprites, audio patterns, registers and digits.
+    /// These would otherwise be split over both ends of memory.
+    fn check_index_range(&self, len: usize) -> Result<(), Error> {
+        if self.I as usize + len > self.memory.len() {
+            return Err(Error::MemoryOutOfBounds { addr: self.I });
+        }
+        Ok(())
+    }
 
-                    0x0003 => {
-                        p!(:"Opcode 8XY3: Sets VX to (VX ^ VY)");
-                        // Opcode 8XY3: Sets VX to (VX ^ VY)
-                        self.vx_vy_bin_op(BinOp::Xor);
-                    }
+    /// Writes the byte at `address`, wrapping around past the end of memory.
+    /// Every write of the program goes through here, to be caught by watchpoints.
+    fn write(&mut self, address: usize, byte: u8) {
+        // Memory is at most 64KB, so addresses fit in 16 bits
+        let address = address % self.memory.len();
+        let old = core::mem::replace(&mut self.memory[address], byte);
+        if self.watchpoints.contains(&(address as u16)) {
+            let (pc, opcode) = (self.pc, self.opcode);
+            self.push_event(Chip8Event::MemoryWrite { pc, opcode, addr: address as u16, old, value: byte });
+        }
+    }
 
-                    0x0004 => {
-                        p!(:"Opcode 8XY4: Adds VY to VX.");
-                        // Opcode 8XY4: Adds VY to VX. An overflow flag is set if VX + VY > 255
-                        // let X = (self.opcode & 0x0F00) >> 8;
-                        // let VX = self.V[X as usize] as u16;
-                        // let Y = (self.opcode & 0x00F0) >> 4;
-                        // let VY = self.V[Y as usize] as u16;
-
-                        let ((X, VX), (_, VY)) = self.vx_vy();
-                        let sum = (VX + VY) as u16;
-                        if sum > 0xFF {
-                            self.V[0xF as usize] = 1;
-                        } else {
-                            self.V[0xF as usize] = 0;
-                        }
-                        self.V[X as usize] = (sum & 0xFF) as u8;
-                        self.pc += 2;
-                    }
+    /// Reads a new opcode from memory
+    fn fetch_opcode(&self) -> u16 {
+        let first_byte = (self.read(self.pc as usize) as u16) << 8; // Cast the memory position to u16 to avoid arith. overflow
+        let second_byte = self.read(self.pc as usize + 1_usize) as u16;
+        first_byte | second_byte
+    }
 
-                    0x0005 => {
-                        p!(:"Opcode 8XY5: Subtracts VY from VX.");
-                        // Opcode 8XY5: Subtracts VY from VX.
-                        // VF is set when there's been a borrow.
-                        // let X = (self.opcode & 0x0F00) >> 8;
-                        // let VX = self.V[X as usize] as u16;
-                        // let Y = (self.opcode & 0x00F0) >> 4;
-                        // let VY = self.V[Y as usize] as u16;
-                        let ((X, VX), (_, VY)) = self.vx_vy();
-                        // Set the borrow flag
-                        self.V[0xF as usize] = if VY > VX { 1 } else { 0 };
-
-                        self.V[X as usize] -= VY as u8;
-                        self.pc += 2;
-                    }
+    /// Moves past the current instruction and the one after it.
+    /// Under XO-CHIP, a four-byte F000 NNNN is skipped as a whole.
+    fn skip_next_instruction(&mut self) {
+        self.advance_pc(2);
+        let long = self.xo_chip && self.fetch_opcode() == 0xF000;
+        self.advance_pc(if long { 4 } else { 2 });
+    }
 
-                    0x0006 => {
-                        p!(:"Opcode 8XY6: Shifts VX right by one (div by 2)");
-                        // Opcode 8XY6: Shifts VX right by one (div by 2).
-                        // If the least-significant bit of VX is 1, then VF is set to 1, otherwise 0.
-                        // let X = (self.opcode & 0x0F00) >> 8;
-                        // let VX = self.V[X as usize];
-                        let (X, VX) = self.vx();
-                        // Save LSB in VF
-                        self.V[0xF as usize] = VX & 0x1;
-                        self.V[X as usize] >>= 1;
-                        self.pc += 2;
-                    }
+    /// Moves the program counter `n` bytes forward,
+    /// wrapping around past the end of memory like every other access
+    fn advance_pc(&mut self, n: u16) {
+        self.pc = ((self.pc as usize + n as usize) % self.memory.len()) as u16;
+    }
 
-                    0x0007 => {
-                        p!(:"Opcode 8XY7: Sets VX to (VY-VX)");
-                        // Opcode 8XY7: Sets VX to (VY-VX)
+    /// Describes the instruction about to be executed, numbered by how many
+    /// ran before it, along with the current register state, e.g.
+    /// `     42 0x200: 6A02  LD VA,0x02    | V=[00 .. 00] I=0000 SP=0 DT=0`
+    pub fn trace_line(&self) -> String {
+        let opcode = self.fetch_opcode();
+        let registers: Vec<String> = self.V.iter().map(|v| format!("{:02X}", v)).collect();
+        format!(
+            "{:>7} {:#05x}: {:04X}  {:<14}| V=[{}] I={:04X} SP={} DT={}",
+            self.stats.instructions,
+            self.pc,
+            opcode,
+            disasm::mnemonic(opcode),
+            registers.join(" "),
+            self.I,
+            self.sp,
+            self.delay_timer
+        )
+    }
 
-                        // So now instead of doing THIS:
-                        // let X = (self.opcode & 0x0F00) >> 8;
-                        // let VX = self.V[X as usize] as u16;
-                        // let Y = (self.opcode & 0x00F0) >> 4;
-                        // let VY = self.V[Y as usize] as u16;
+    /// Width of the screen in the current resolution
+    pub fn width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { LORES_WIDTH }
+    }
 
-                        // I just do this:
-                        let ((X, VX), (_, VY)) = self.vx_vy();
-                        // Set the borrow flag
-                        self.V[0xF as usize] = if VY > VX { 1 } else { 0 };
+    /// Height of the screen in the current resolution
+    pub fn height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { LORES_HEIGHT }
+    }
 
-                        self.V[X as usize] = VY - VX;
-                        self.pc += 2;
-                    }
-                    0x000E => {
-                        p!(:"Opcode 8XYE: Shifts VX left by one.");
-                        // Opcode 8XYE: Shifts VX left by one.
-                        // VX receives the value of the most significant bit before the shift.
-                        let (X, VX) = self.vx();
-                        self.V[0xF as usize] = VX & 0x80;
-                        self.V[X as usize] <<= 1;
-                    }
+    /// The screen in the current resolution, as frontends draw it
+    pub fn framebuffer(&self) -> Framebuffer<'_> {
+        Framebuffer::new(&self.graphics, &self.dirty, self.width(), self.height())
+    }
 
-                    op @ _ => {
-                        eprintln!("Unknown opcode [0x8000#04x{}]", op);
-                    }
-                }
-            }
+    /// Forgets which pixels changed, once the frontend has drawn them
+    pub fn clear_dirty(&mut self) {
+        self.dirty = [0; SCREEN_HEIGHT];
+    }
 
-            0x9000 => {
-                p!(:"Opcode 9XY0: Skips the next instruction if VX != VY.");
-                // Opcode 9XY0: Skips the next instruction if VX != VY.
-                let ((_, VX), (_, VY)) = self.vx_vy();
-                if VX != VY {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+    /// Flips the pixels of row `y` set in `bits` on the given planes, remembering
+    /// that they changed. Returns whether any of them was set before, i.e. collided.
+    fn xor_pixels(&mut self, y: usize, bits: u128, planes: u8) -> bool {
+        let mut collided = false;
+        for (i, plane) in self.graphics.iter_mut().enumerate() {
+            if planes & (1 << i) != 0 {
+                collided |= plane[y] & bits != 0;
+                plane[y] ^= bits;
+                self.dirty[y] |= bits;
             }
+        }
+        collided
+    }
 
-            0xA000 => {
-                p!(:"Opcode ANNN: Sets I to the address NNN");
-                // Opcode ANNN: Sets I to the address NNN
-                self.I = self.opcode & 0x0FFF;
-                self.pc += 2;
-            }
+    /// Replaces row `y` of `plane` with `row`, remembering which pixels changed
+    fn set_row(&mut self, plane: usize, y: usize, row: u128) {
+        self.dirty[y] |= self.graphics[plane][y] ^ row;
+        self.graphics[plane][y] = row;
+    }
 
-            0xB000 => {
-                p!(:"Opcode BNNN: Jumps to the address NNN + V0");
-                // Opcode BNNN: Jumps to the address NNN + V0
-                self.pc = (self.opcode & 0x0FFF) + (self.V[0] as u16);
-            }
+    /// The bits of a row that fall within the screen in the current resolution
+    fn row_mask(&self) -> u128 {
+        u128::MAX >> (SCREEN_WIDTH - self.width())
+    }
 
-            0xC000 => {
-                p!(:"Opcode CXNN: Sets VX to (random_byte &  NN).");
-                // Opcode CXNN: Sets VX to (random_byte &  NN).
-                let mut rng = rand::thread_rng();
-                let (X, _) = self.vx();
-                let NN = (self.opcode & 0x00FF) as u8;
-                self.V[X as usize] = rng.gen::<u8>() & NN;
-                self.pc += 2;
-            }
+    /// The planes selected by `plane_mask`, by index into `graphics`
+    fn selected_planes(&self) -> impl Iterator<Item = usize> {
+        let mask = self.plane_mask;
+        (0..PLANE_COUNT).filter(move |i| mask & (1 << i) != 0)
+    }
 
-            0xD000 => {
-                p!(:"Opcode DXYN: draw sprite at (VX, VY), w=8, h=N");
-                /*  Draws a sprite at coordinate (VX, VY) that has a width of 8 pixels and a height of N pixels.
+    /// Whether the SUPER-CHIP high resolution mode is active
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// What the speaker should be playing, if anything.
+    /// Programs that never loaded an XO-CHIP pattern get the classic buzzer.
+    pub fn tone(&self) -> Option<Tone> {
+        if self.sound_timer == 0 {
+            None
+        } else if self.xo_chip && self.audio_pattern != [0; 16] {
+            Some(Tone::Pattern {
+                pattern: self.audio_pattern,
+                rate: audio::playback_rate(self.pitch),
+            })
+        } else {
+            Some(Tone::Square)
+        }
+    }
+
+    /// How many instructions ran since the machine was created
+    pub fn cycle_count(&self) -> u64 {
+        self.stats.instructions
+    }
+
+    /// Whether the program ended, either through the SUPER-CHIP exit instruction, 00FD,
+    /// or by jumping to itself with `halt_on_spin` enabled
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Whether the program halted in a spin loop, see `VirtualMachineBuilder::halt_on_spin`.
+    /// Such programs usually leave something on screen, such as a final score.
+    pub fn is_spinning(&self) -> bool {
+        self.spinning
+    }
+
+    /// Presses or releases `key` on the hex keypad, 0x0 to 0xF
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.keypad[(key & 0xF) as usize] = pressed as u8;
+    }
+
+    /// The whole hex keypad as a bitmask, bit n set while key n is held
+    pub fn keys(&self) -> u16 {
+        (0..16).filter(|&key| self.keypad[key] != 0).fold(0, |mask, key| mask | 1 << key)
+    }
+
+    /// Sets the whole hex keypad at once from a bitmask, bit n pressing key n.
+    /// Suits frontends that keep their own key state, such as input recordings.
+    pub fn set_keys(&mut self, mask: u16) {
+        for (key, state) in self.keypad.iter_mut().enumerate() {
+            *state = (mask >> key & 1) as u8;
+        }
+    }
+
+    /// Switches between the low and high resolution modes.
+    /// The screen, in all of its planes, is cleared whenever the resolution changes.
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.graphics = [[0; SCREEN_HEIGHT]; PLANE_COUNT];
+        self.dirty = [u128::MAX; SCREEN_HEIGHT];
+        self.draw_to_screen = true;
+    }
+
+    /// Clears the selected planes of the CHIP-8 screen
+    fn clear_screen(&mut self) {
+        for plane in self.selected_planes() {
+            for y in 0..SCREEN_HEIGHT {
+                self.set_row(plane, y, 0);
+            }
+        }
+        self.draw_to_screen = true;
+    }
+
+    /// Scrolls the selected planes down by `rows`, blanking the rows at the top.
+    /// As in Octo, scrolling is measured in pixels of the current resolution,
+    /// so in low resolution the screen moves twice as far as in high resolution.
+    fn scroll_down(&mut self, rows: usize) {
+        let height = self.height();
+        for plane in self.selected_planes() {
+            for y in (0..height).rev() {
+                let moved = if y >= rows { self.graphics[plane][y - rows] } else { 0 };
+                self.set_row(plane, y, moved);
+            }
+        }
+        self.draw_to_screen = true;
+    }
+
+    /// Scrolls the selected planes right by `columns`, blanking the leftmost columns
+    fn scroll_right(&mut self, columns: usize) {
+        let (height, row_mask) = (self.height(), self.row_mask());
+        for plane in self.selected_planes() {
+            for y in 0..height {
+                // Bit X is the pixel in column X, so moving right is shifting left
+                self.set_row(plane, y, (self.graphics[plane][y] << columns) & row_mask);
+            }
+        }
+        self.draw_to_screen = true;
+    }
+
+    /// Scrolls the selected planes left by `columns`, blanking the rightmost columns
+    fn scroll_left(&mut self, columns: usize) {
+        let height = self.height();
+        for plane in self.selected_planes() {
+            for y in 0..height {
+                self.set_row(plane, y, self.graphics[plane][y] >> columns);
+            }
+        }
+        self.draw_to_screen = true;
+    }
+
+    #[allow(non_snake_case)]
+    /// Executes a binary operation between VX and VY and attributes it to VX.
+    fn vx_vy_bin_op(&mut self, binop: BinOp, x: u8, y: u8) {
+        let X = x as usize;
+        let VY = self.V[y as usize];
+        // The original COSMAC VIP interpreter clobbered VF on logical operations
+        let logical = !matches!(binop, BinOp::Attrib);
+        match binop {
+            BinOp::Attrib => {
+                self.V[X] = VY;
+            }
+
+            BinOp::Xor => {
+                self.V[X] ^= VY;
+            }
+
+            BinOp::And => {
+                self.V[X] &= VY;
+            }
+
+            BinOp::Or => {
+                self.V[X] |= VY;
+            }
+        }
+        if logical && self.quirks.vf_reset {
+            self.V[0xF] = 0;
+        }
+        self.advance_pc(2);
+    }
+
+    #[allow(non_snake_case)]
+    /// Skips an instruction dependending on if VX and NN are equal (or unequal).
+    /// Used by opcodes 3XNN and 4XNN.
+    /// `cmptype` defines whether to skip an instruction if VX == NN or if VX != NN.
+    fn compare_vx_and_nn(&mut self, cmptype: ComparisonType, x: u8, nn: u8) {
+        let VX = self.V[x as usize];
+        let NN = nn;
+        if cmptype == ComparisonType::Equality {
+            // Compare if VX == NN
+            if VX == NN {
+                // VX == NN, so we skip the next instruction
+                self.skip_next_instruction();
+            } else {
+                self.advance_pc(2);
+            }
+        } else {
+            // Compare if VX != NN
+            if VX != NN {
+                // VX != NN, so we skip the next instruction
+                self.skip_next_instruction();
+            } else {
+                self.advance_pc(2);
+            }
+        }
+    }
+
+    #[allow(non_snake_case)]
+    /// Draws the N-byte sprite at I to (VX, VY), setting VF on collision.
+    /// With N = 0, SUPER-CHIP draws a 16x16 sprite, two bytes per row, in high resolution,
+    /// and an 8x16 one in low resolution. XO-CHIP draws 16x16 sprites in both.
+    /// Under XO-CHIP, every selected plane gets its own sprite: the one for the first
+    /// plane is at I, and the one for the second plane follows it.
+    /// Fails if the sprites would run past the end of memory.
+    fn draw_sprite(&mut self, x: u8, y: u8, n: u8) -> Result<(), Error> {
+
+        // x := The contents of VX
+        // y := The contents of VY
+        let (x, y) = (self.V[x as usize], self.V[y as usize]);
+        let n = n as usize;
+
+        // Sprite size in pixels, and bytes per sprite row
+        let (sprite_width, sprite_height) = match n {
+            0 if self.hires || self.xo_chip => (16, 16),
+            0 => (8, 16),
+            n => (8, n),
+        };
+        let row_bytes = sprite_width / 8;
+        self.check_index_range(self.selected_planes().count() * sprite_height * row_bytes)?;
+
+        let (width, height) = (self.width(), self.height());
+
+        // The starting position always wraps around the screen
+        let x = x as usize % width;
+        let y = y as usize % height;
+
+        // Reset VF
+        self.V[0xF] = 0;
+
+        let mut sprite_address = self.I as usize;
+        for plane in self.selected_planes() {
+            for row in 0..sprite_height {
+                if self.quirks.clip && y + row >= height {
+                    break;
+                }
+                // Wrap around if overflown
+                let y = (y + row) % height;
+                // Lay the sprite row out as it lands on the screen row, then flip it at once
+                let mut bits = 0u128;
+                for col in 0..sprite_width {
+                    if self.quirks.clip && x + col >= width {
+                        break;
+                    }
+                    let byte = self.read(sprite_address + row * row_bytes + col / 8);
+                    if (byte >> (7 - col % 8)) & 1 == 1 {
+                        bits |= 1 << ((x + col) % width);
+                    }
+                }
+                if self.xor_pixels(y, bits, 1 << plane) {
+                    self.V[0x0F] = 1;
+                }
+            }
+            sprite_address += sprite_height * row_bytes;
+        }
+
+        self.draw_to_screen = true;
+        Ok(())
+    }
+
+    /// Copies the ROM into memory where programs start: 0x200,
+    /// unless the machine was built with another load address.
+    /// Fails if the ROM doesn't fit in the memory of this machine.
+    pub fn load_rom(&mut self, cart: &Cartridge) -> Result<(), Error>
+    {
+        self.load_rom_at(cart, self.load_address)
+    }
+
+    /// Copies the ROM into memory at `address` and starts running it from there,
+    /// as ETI-660 programs expect with 0x600.
+    /// Fails if the ROM doesn't fit in memory past `address`.
+    pub fn load_rom_at(&mut self, cart: &Cartridge, address: u16) -> Result<(), Error>
+    {
+        let start = address as usize;
+        let end = start + cart.size();
+        if end > self.memory.len() {
+            return Err(Error::RomDoesNotFit {
+                size: cart.size(),
+                available: self.memory.len().saturating_sub(start),
+                address,
+            });
+        }
+        self.memory[start..end].copy_from_slice(&cart.data);
+        self.pc = address;
+        Ok(())
+    }
+
+    /// Fetches, decodes and executes a single instruction.
+    /// Timers are not affected, see `tick_timers`.
+    /// Does nothing once the machine has halted.
+    /// If the program can't go on, such as on an unknown opcode, the error is
+    /// returned and the program counter is left on the offending instruction.
+    pub fn run_cycle(&mut self) -> Result<(), Error> {
+        if self.halted {
+            return Ok(());
+        }
+        if self.history.is_some() {
+            let state = self.snapshot();
+            if let Some(history) = self.history.as_mut() {
+                history.push(state);
+            }
+        }
+        self.opcode = self.fetch_opcode();
+        self.stats.instructions += 1;
+        let instruction = instruction::decode(self.opcode);
+        if let Some(opcode_stats) = self.opcode_stats.as_mut() {
+            opcode_stats.count(instruction);
+        }
+        // Registers are written all over execute, so watched ones are compared afterwards
+        let before = (!self.register_watches.is_empty()).then_some((self.pc, self.V, self.I));
+        let result = self.execute(instruction);
+        if let Some((pc, registers, index)) = before {
+            self.report_register_changes(pc, self.opcode, registers, index);
+        }
+        if self.resume_from != Some(self.pc) {
+            self.resume_from = None;
+        }
+        result
+    }
+
+    /// Runs the next `n` instructions with `run_cycle`, stopping at the first error
+    pub fn step(&mut self, n: u32) -> Result<(), Error> {
+        for _ in 0..n {
+            self.run_cycle()?;
+        }
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    /// Executes `instruction` as if it were the one at the program counter,
+    /// which is then moved on as the instruction says.
+    /// The XO-CHIP instructions are unknown opcodes unless emulating XO-CHIP.
+    pub fn execute(&mut self, instruction: Instruction) -> Result<(), Error> {
+        match instruction {
+            Instruction::ClearScreen => {
+                trace!("Opcode 00E0: Clears the screen");
+                // Opcode 00E0: Clears the screen
+                self.clear_screen();
+                self.advance_pc(2);
+            }
+
+            Instruction::Return => {
+                trace!("Opcode 0EE: Returns from subroutine");
+                // Opcode 0EE: Returns from subroutine
+                if self.sp == 0 {
+                    return Err(Error::StackUnderflow { pc: self.pc });
+                }
+                self.sp -= 1;
+                self.pc = self.stack[self.sp as usize];
+                self.advance_pc(2);
+            }
+
+            Instruction::ScrollDown(n) => {
+                trace!("Opcode 00CN: Scrolls the screen down by N pixels");
+                // Opcode 00CN (SUPER-CHIP): Scrolls the screen down by N pixels
+                self.scroll_down(n as usize);
+                self.advance_pc(2);
+            }
+
+            Instruction::ScrollRight => {
+                trace!("Opcode 00FB: Scrolls the screen right by 4 pixels");
+                // Opcode 00FB (SUPER-CHIP): Scrolls the screen right by 4 pixels
+                self.scroll_right(4);
+                self.advance_pc(2);
+            }
+
+            Instruction::ScrollLeft => {
+                trace!("Opcode 00FC: Scrolls the screen left by 4 pixels");
+                // Opcode 00FC (SUPER-CHIP): Scrolls the screen left by 4 pixels
+                self.scroll_left(4);
+                self.advance_pc(2);
+            }
+
+            Instruction::Exit => {
+                trace!("Opcode 00FD: Exits the interpreter");
+                // Opcode 00FD (SUPER-CHIP): Exits the interpreter.
+                // The program counter stays put, so the machine is left as it ended.
+                self.halted = true;
+            }
+
+            Instruction::LowRes => {
+                trace!("Opcode 00FE: Switches to the 64x32 low resolution mode");
+                // Opcode 00FE (SUPER-CHIP): Switches to the 64x32 low resolution mode
+                self.set_hires(false);
+                self.advance_pc(2);
+            }
+
+            Instruction::HighRes => {
+                trace!("Opcode 00FF: Switches to the 128x64 high resolution mode");
+                // Opcode 00FF (SUPER-CHIP): Switches to the 128x64 high resolution mode
+                self.set_hires(true);
+                self.advance_pc(2);
+            }
+
+            Instruction::Jump(address) => {
+                trace!("Opcode 1NNN: Jumps to address NNN");
+                // Opcode 1NNN: Jumps to address NNN
+                if self.halt_on_spin && address == self.pc {
+                    // Jumping to itself, the program would do nothing else from now on
+                    self.halted = true;
+                    self.spinning = true;
+                }
+                self.pc = address;
+            }
+
+            Instruction::Call(address) => {
+                trace!("Opcode 2NNN: Calls subroutine located at NNN");
+                // Opcode 2NNN: Calls subroutine located at NNN
+                if self.sp as usize == self.stack.len() {
+                    return Err(Error::StackOverflow { pc: self.pc });
+                }
+                self.stack[self.sp as usize] = self.pc;
+                self.sp += 1;
+                self.pc = address;
+            }
+
+            Instruction::SkipEqImm { x, nn } => {
+                trace!("Opcode 3XNN: Skips the next instruction if VX == NN.");
+                // Opcode 3XNN: Skips the next instruction if VX == NN.
+                self.compare_vx_and_nn(ComparisonType::Equality, x, nn);
+            }
+
+            Instruction::SkipNeImm { x, nn } => {
+                trace!("Opcode 4XNN: Skips the next instruction if VX != NN.");
+                // Opcode 4XNN: Skips the next instruction if VX != NN.
+                self.compare_vx_and_nn(ComparisonType::Inequality, x, nn);
+            }
+
+            Instruction::SkipEqReg { x, y } => {
+                trace!("Opcode 5XY0: Skips the next instruction if VX == VY");
+                // Opcode 5XY0: Skips the next instruction if VX == VY
+                if self.V[x as usize] == self.V[y as usize] {
+                    self.skip_next_instruction();
+                } else {
+                    self.advance_pc(2);
+                }
+            }
+
+            Instruction::SaveRange { x, y } if self.xo_chip => {
+                trace!("Opcode 5XY2: Stores VX..VY in memory, starting at I");
+                // Opcode 5XY2 (XO-CHIP): Stores VX, ..., VY in memory, starting at
+                // location I. Goes backwards through the registers if Y < X. I is unchanged.
+                let I = self.I as usize;
+                let registers = register_range(x, y);
+                self.check_index_range(registers.len())?;
+                for (offset, register) in registers.into_iter().enumerate() {
+                    self.write(I + offset, self.V[register]);
+                }
+                self.advance_pc(2);
+            }
+
+            Instruction::LoadRange { x, y } if self.xo_chip => {
+                trace!("Opcode 5XY3: Reads VX..VY from memory, starting at I");
+                // Opcode 5XY3 (XO-CHIP): Sets VX, ..., VY to the values in memory, starting
+                // at location I. Goes backwards through the registers if Y < X. I is unchanged.
+                let I = self.I as usize;
+                let registers = register_range(x, y);
+                self.check_index_range(registers.len())?;
+                for (offset, register) in registers.into_iter().enumerate() {
+                    self.V[register] = self.read(I + offset);
+                }
+                self.advance_pc(2);
+            }
+
+            Instruction::LoadImm { x, nn } => {
+                trace!("Opcode 6XNN: sets VX to NN");
+                // Opcode 6XNN: sets VX to NN
+                self.V[x as usize] = nn;
+                self.advance_pc(2);
+            }
+
+            Instruction::AddImm { x, nn } => {
+                trace!("Opcode 7XNN: Adds NN to VX.");
+                // Opcode 7XNN: Adds NN to VX.
+                // Wraps around if overflown, leaving VF alone
+                self.V[x as usize] = self.V[x as usize].wrapping_add(nn);
+                self.advance_pc(2);
+            }
+
+            Instruction::Move { x, y } => {
+                trace!("Opcode 8XY0: Sets VX to the value of VY");
+                // Opcode 8XY0: Sets VX to the value of VY
+                self.vx_vy_bin_op(BinOp::Attrib, x, y);
+            }
+
+            Instruction::Or { x, y } => {
+                trace!("Opcode 8XY1: Sets VX to (VX | VY)");
+                // Opcode 8XY1: Sets VX to (VX | VY)
+                self.vx_vy_bin_op(BinOp::Or, x, y);
+            }
+
+            Instruction::And { x, y } => {
+                trace!("Opcode 8XY2: Sets VX to (VX & VY)");
+                // Opcode 8XY2: Sets VX to (VX & VY)
+                self.vx_vy_bin_op(BinOp::And, x, y);
+            }
+
+            Instruction::Xor { x, y } => {
+                trace!("Opcode 8XY3: Sets VX to (VX ^ VY)");
+                // Opcode 8XY3: Sets VX to (VX ^ VY)
+                self.vx_vy_bin_op(BinOp::Xor, x, y);
+            }
+
+            Instruction::Add { x, y } => {
+                trace!("Opcode 8XY4: Adds VY to VX.");
+                // Opcode 8XY4: Adds VY to VX. An overflow flag is set if VX + VY > 255
+                let (VX, VY) = (self.V[x as usize], self.V[y as usize]);
+                let (sum, carry) = VX.overflowing_add(VY);
+                // The flag goes last, so that it wins when X is F
+                self.V[x as usize] = sum;
+                self.V[0xF] = carry as u8;
+                self.advance_pc(2);
+            }
+
+            Instruction::Sub { x, y } => {
+                trace!("Opcode 8XY5: Subtracts VY from VX.");
+                // Opcode 8XY5: Subtracts VY from VX.
+                // VF is set when there's no borrow, and cleared when there is.
+                let (VX, VY) = (self.V[x as usize], self.V[y as usize]);
+                self.V[x as usize] = VX.wrapping_sub(VY);
+                self.V[0xF] = (VX >= VY) as u8;
+                self.advance_pc(2);
+            }
+
+            Instruction::ShiftRight { x, y } => {
+                trace!("Opcode 8XY6: Shifts VX right by one (div by 2)");
+                // Opcode 8XY6: Shifts VY right by one (div by 2) and stores the result in VX.
+                // With the `shift` quirk, VX is shifted in place instead.
+                // VF receives the least-significant bit before the shift.
+                let value = if self.quirks.shift { self.V[x as usize] } else { self.V[y as usize] };
+                self.V[x as usize] = value >> 1;
+                // Save LSB in VF
+                self.V[0xF] = value & 0x1;
+                self.advance_pc(2);
+            }
+
+            Instruction::SubN { x, y } => {
+                trace!("Opcode 8XY7: Sets VX to (VY-VX)");
+                // Opcode 8XY7: Sets VX to (VY-VX)
+                // VF is set when there's no borrow, and cleared when there is.
+                let (VX, VY) = (self.V[x as usize], self.V[y as usize]);
+                self.V[x as usize] = VY.wrapping_sub(VX);
+                self.V[0xF] = (VY >= VX) as u8;
+                self.advance_pc(2);
+            }
+
+            Instruction::ShiftLeft { x, y } => {
+                trace!("Opcode 8XYE: Shifts VX left by one.");
+                // Opcode 8XYE: Shifts VY left by one and stores the result in VX.
+                // With the `shift` quirk, VX is shifted in place instead.
+                // VF receives the value of the most significant bit before the shift.
+                let value = if self.quirks.shift { self.V[x as usize] } else { self.V[y as usize] };
+                self.V[x as usize] = value << 1;
+                self.V[0xF] = value >> 7;
+                self.advance_pc(2);
+            }
+
+            Instruction::SkipNeReg { x, y } => {
+                trace!("Opcode 9XY0: Skips the next instruction if VX != VY.");
+                // Opcode 9XY0: Skips the next instruction if VX != VY.
+                if self.V[x as usize] != self.V[y as usize] {
+                    self.skip_next_instruction();
+                } else {
+                    self.advance_pc(2);
+                }
+            }
+
+            Instruction::LoadI(address) => {
+                trace!("Opcode ANNN: Sets I to the address NNN");
+                // Opcode ANNN: Sets I to the address NNN
+                self.I = address;
+                self.advance_pc(2);
+            }
+
+            Instruction::JumpV0(address) => {
+                trace!("Opcode BNNN: Jumps to the address NNN + V0");
+                // Opcode BNNN: Jumps to the address NNN + V0
+                // With the `jump` quirk, this is BXNN: jumps to XNN + VX.
+                let x = (address >> 8) as usize;
+                let offset = if self.quirks.jump { self.V[x] } else { self.V[0] };
+                self.pc = ((address as usize + offset as usize) % self.memory.len()) as u16;
+            }
+
+            Instruction::Random { x, nn } => {
+                trace!("Opcode CXNN: Sets VX to (random_byte &  NN).");
+                // Opcode CXNN: Sets VX to (random_byte &  NN).
+                self.V[x as usize] = self.rng.gen::<u8>() & nn;
+                self.advance_pc(2);
+            }
+
+            Instruction::Draw { x, y, n } => {
+                trace!("Opcode DXYN: draw sprite at (VX, VY), w=8, h=N");
+                /*  Draws a sprite at coordinate (VX, VY) that has a width of 8 pixels and a height of N pixels.
                 Each row of 8 pixels is read as bit-coded starting from memory location I.
                 The I value doesn’t change after the execution of this instruction.
                 As described above, VF is set to 1 if any screen pixels are flipped from set to unset when
-                the sprite is drawn, and to 0 if that doesn’t happen. */
-                self.draw_sprite();
-                self.pc += 2;
-            }
-
-            // Testing opcodes starting with EX___
-            0xE000 => {
-                match self.opcode & 0x00FF {
-
-                    0x009E => {
-                        p!(:"Opcode EX9E: Skips the next instruction if the key");
-                        // Opcode EX9E: Skips the next instruction if the key
-                        // stored in VX is pressed
-                        let (_, VX) = self.vx();
-                        if self.keypad[VX as usize] != 0 {
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-                    }
+                the sprite is drawn, and to 0 if that doesn’t happen.
+                With N = 0, a SUPER-CHIP 16x16 sprite is drawn instead. */
+                if self.quirks.dispwait && !self.vblank {
+                    // Wait for the next frame: try this instruction again later
+                    return Ok(());
+                }
+                self.draw_sprite(x, y, n)?;
+                self.vblank = false;
+                self.advance_pc(2);
+            }
 
-                    0x00A1 => {
-                        p!(:"Opcode EXA1: Skips the next instruction if the key stored in");
-                        // Opcode EXA1: Skips the next instruction if the key stored in
-                        // VX is not pressed.
-                        let (_, VX) = self.vx();
-                        if self.keypad[VX as usize] == 0 {
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-                    }
-                    op => {
-                        eprintln!("Unknown opcode EX#04x{}", op);
-                    }
+            Instruction::SkipKey { x } => {
+                trace!("Opcode EX9E: Skips the next instruction if the key");
+                // Opcode EX9E: Skips the next instruction if the key
+                // stored in VX is pressed. Only the low nibble of VX names a key.
+                if self.keypad[(self.V[x as usize] & 0xF) as usize] != 0 {
+                    self.skip_next_instruction();
+                } else {
+                    self.advance_pc(2);
                 }
             }
 
-            // Testing opcodes starting with FX___
-            0xF000 => {
-                match self.opcode & 0x00FF {
+            Instruction::SkipNotKey { x } => {
+                trace!("Opcode EXA1: Skips the next instruction if the key stored in");
+                // Opcode EXA1: Skips the next instruction if the key stored in
+                // VX is not pressed. Only the low nibble of VX names a key.
+                if self.keypad[(self.V[x as usize] & 0xF) as usize] == 0 {
+                    self.skip_next_instruction();
+                } else {
+                    self.advance_pc(2);
+                }
+            }
 
-                    0x0007 => {
-                        p!(:"Opcode FX07: Sets VX to the value of the delay timer");
-                        // Opcode FX07: Sets VX to the value of the delay timer
-                        let (X, _) = self.vx();
-                        self.V[X as usize] = self.delay_timer;
-                        self.pc += 2;
-                    }
+            Instruction::GetDelay { x } => {
+                trace!("Opcode FX07: Sets VX to the value of the delay timer");
+                // Opcode FX07: Sets VX to the value of the delay timer
+                self.V[x as usize] = self.delay_timer;
+                self.advance_pc(2);
+            }
 
-                    0x000A => {
-                        p!(:"Opcode FX0A: Wait for a key press, store the value of the key in Vx.");
-                        // Opcode FX0A: Wait for a key press, store the value of the key in Vx.
-                        let mut key_was_pressed = false;
-                        for i in 0..16 {
-                            if self.keypad[i as usize] != 0 {
-                                let (X, _) = self.vx();
-                                self.V[X as usize] = i;
-                                key_was_pressed = true;
-                                // TODO: break here?
-                            }
-                        }
-
-                        if key_was_pressed {
-                            self.pc += 2;
-                        } else {
-                            // A key was not pressed, so we try this operation again
-                            // TODO: make sure timers are not decreases when this case happens?
-                        }
+            Instruction::WaitKey { x } => {
+                trace!("Opcode FX0A: Wait for a key press, store the value of the key in Vx.");
+                // Opcode FX0A: Wait for a key press, store the value of the key in Vx.
+                let mut key_was_pressed = false;
+                for i in 0..16 {
+                    if self.keypad[i as usize] != 0 {
+                        self.V[x as usize] = i;
+                        key_was_pressed = true;
+                        // TODO: break here?
                     }
+                }
 
-                    0x0015 => {
-                        p!(:"Opcode FX15: Set the delay timer to VX");
-                        // Opcode FX15: Set the delay timer to VX
-                        let (_, VX) = self.vx();
-                        self.delay_timer = VX;
-                        self.pc += 2;
-                    }
+                if key_was_pressed {
+                    self.advance_pc(2);
+                } else {
+                    // A key was not pressed, so we try this operation again
+                }
+            }
 
-                    0x0018 => {
-                        p!(:"Opcode FX18: Set the sound timer to VX");
-                        // Opcode FX18: Set the sound timer to VX
-                        let (_, VX) = self.vx();
-                        self.sound_timer = VX;
-                        self.pc += 2;
-                    }
+            Instruction::SetDelay { x } => {
+                trace!("Opcode FX15: Set the delay timer to VX");
+                // Opcode FX15: Set the delay timer to VX
+                self.delay_timer = self.V[x as usize];
+                self.advance_pc(2);
+            }
 
-                    0x001E => {
-                        p!(:"Opcode FX1E: Adds VX to I.");
-                        // Opcode FX1E: Adds VX to I.
-                        // If the sum causes overflow, VF is set to one.
-                        // If not, VF is set to zero.
-                        let (_, VX) = self.vx();
-                        self.V[0xF as usize] = if self.I + (VX as u16) > 0xFFF 
-                                               { 1 } else { 0 };
-                        self.I  += VX as u16;
-                        self.pc += 2;
-                    }
+            Instruction::SetSound { x } => {
+                trace!("Opcode FX18: Set the sound timer to VX");
+                // Opcode FX18: Set the sound timer to VX
+                self.sound_timer = self.V[x as usize];
+                self.advance_pc(2);
+            }
 
-                    0x0029 => {
-                        p!(:"Opcode FX29: Sets I to the location of the sprite for the character in VX.");
-                        // Opcode FX29: Sets I to the location of the sprite for the character
-                        // in VX.
-                        let (_, VX) = self.vx();
-                        // TODO: Verify if the fonts must start getting loaded from 0x50.
-                        self.I   = (VX as u16) * 0x5;
-                        self.pc += 2; 
-                    }
+            Instruction::AddI { x } => {
+                trace!("Opcode FX1E: Adds VX to I.");
+                // Opcode FX1E: Adds VX to I.
+                // If the sum causes overflow, VF is set to one.
+                // If not, VF is set to zero.
+                let VX = self.V[x as usize];
+                self.V[0xF] = if self.I as u32 + VX as u32 > 0xFFF
+                                       { 1 } else { 0 };
+                self.I   = self.I.wrapping_add(VX as u16);
+                self.advance_pc(2);
+            }
 
-                    0x0033 => {
-                        p!(:"Opcode FX33: Stores the BCD representation of VX in mem. at I, I+1 and I+2.");
-                        // Opcode FX33: Stores the BCD representation of VX in memory locations
-                        // I, I+1 and I+2.
-                        // The hundreds digit will be stored at I
-                        // The tens digit will be stored at I+1
-                        // And the ones digit stored at I+2 
-                        let I = self.I;
-                        let (_, VX) = self.vx();
-                        let mut value = VX;
-                        // We'll place the values in reverse order
-                        // Ones place
-                        self.memory[(I+2) as usize] = value % 10;
-                        value /= 10;
-
-                        // Tens place
-                        self.memory[(I+1) as usize] = value % 10;
-                        value /= 10;
-
-                        // Hundreds place
-                        self.memory[I as usize] = value % 10;
-
-                        self.pc += 2;
-                    }
+            Instruction::LoadILong if self.xo_chip => {
+                trace!("Opcode F000 NNNN: Sets I to NNNN");
+                // Opcode F000 NNNN (XO-CHIP): Sets I to the 16-bit address NNNN,
+                // which takes up the two bytes after the opcode.
+                let high = self.read(self.pc as usize + 2) as u16;
+                let low = self.read(self.pc as usize + 3) as u16;
+                self.I   = (high << 8) | low;
+                self.advance_pc(4);
+            }
 
-                    0x0055 => {
-                        p!(:"Opcode FX55: Stores the value of V0..VX on the memory, starting at I.");
-                        // Opcode FX55: Stores the value of all registers, V0, V1, ..., VX
-                        // on the memory, starting at location I.
-                        let (X, _) = self.vx();
-                        let I = self.I as u8;
-                        for i in 0..=X {
-                            self.memory[(I+i) as usize] = self.V[i as usize];
-                        }
-                        // TODO (quirk?): do I += X+1 ?
-                        self.I += (X + 1) as u16;
-                        self.pc += 2;
-                    }
+            Instruction::Audio if self.xo_chip => {
+                trace!("Opcode F002: Loads the audio pattern from memory at I");
+                // Opcode F002 (XO-CHIP): Loads the 16-byte audio pattern from memory,
+                // starting at location I.
+                self.check_index_range(self.audio_pattern.len())?;
+                for i in 0..self.audio_pattern.len() {
+                    self.audio_pattern[i] = self.read(self.I as usize + i);
+                }
+                self.advance_pc(2);
+            }
 
-                    0x0065 => {
-                        p!(:"Opcode FX65: Reads V0..VX from memory, starting at I.");
-                        // Opcode FX65: Sets V0, V1, ... Vx to the values in memory, starting
-                        // at location I.
-                        let (X, _) = self.vx();
-                        let I = self.I as u8;
-                        for i in 0..=X {
-                            self.V[i as usize] = self.memory[(I + i) as usize];
-                        }
-                        // TODO: quirk -- do I += X+1
-                        self.I += (X+1) as u16;
-                        self.pc += 2;
-                    }
+            Instruction::Pitch { x } if self.xo_chip => {
+                trace!("Opcode FX3A: Sets the audio pitch to VX");
+                // Opcode FX3A (XO-CHIP): Sets the playback rate of the audio pattern to VX
+                self.pitch = self.V[x as usize];
+                self.advance_pc(2);
+            }
 
-                    op => {
-                        eprintln!("Unknown opcode FX#04x{}", op);
-                    }
+            Instruction::Plane(planes) if self.xo_chip => {
+                trace!("Opcode FN01: Selects the planes drawn to");
+                // Opcode FN01 (XO-CHIP): Selects the planes that drawing, clearing
+                // and scrolling affect. N is a bitmask of planes, from 0 to 3.
+                self.plane_mask = planes & 0b11;
+                self.advance_pc(2);
+            }
+
+            Instruction::Font { x } => {
+                trace!("Opcode FX29: Sets I to the location of the sprite for the character in VX.");
+                // Opcode FX29: Sets I to the location of the sprite for the character
+                // in VX.
+                self.I   = (FONTSET_ADDRESS + self.V[x as usize] as usize * 5) as u16;
+                self.advance_pc(2);
+            }
+
+            Instruction::BigFont { x } => {
+                trace!("Opcode FX30: Sets I to the location of the large sprite for the digit in VX.");
+                // Opcode FX30 (SUPER-CHIP): Sets I to the location of the 8x10 sprite
+                // for the digit in VX. The built-in large font only has the digits 0 to 9,
+                // see `load_big_fontset`.
+                self.I   = (BIG_FONTSET_ADDRESS + self.V[x as usize] as usize * 10) as u16;
+                self.advance_pc(2);
+            }
+
+            Instruction::Bcd { x } => {
+                trace!("Opcode FX33: Stores the BCD representation of VX in mem. at I, I+1 and I+2.");
+                // Opcode FX33: Stores the BCD representation of VX in memory locations
+                // I, I+1 and I+2.
+                // The hundreds digit will be stored at I
+                // The tens digit will be stored at I+1
+                // And the ones digit stored at I+2 
+                self.check_index_range(3)?;
+                let I = self.I as usize;
+                let mut value = self.V[x as usize];
+                // We'll place the values in reverse order
+                // Ones place
+                self.write(I + 2, value % 10);
+                value /= 10;
+
+                // Tens place
+                self.write(I + 1, value % 10);
+                value /= 10;
+
+                // Hundreds place
+                self.write(I, value % 10);
+
+                self.advance_pc(2);
+            }
+
+            Instruction::Store { x } => {
+                trace!("Opcode FX55: Stores the value of V0..VX on the memory, starting at I.");
+                // Opcode FX55: Stores the value of all registers, V0, V1, ..., VX
+                // on the memory, starting at location I.
+                self.check_index_range(x as usize + 1)?;
+                let I = self.I as usize;
+                for i in 0..=x as usize {
+                    self.write(I + i, self.V[i]);
+                }
+                if self.quirks.memory {
+                    self.I = self.I.wrapping_add((x + 1) as u16);
+                }
+                self.advance_pc(2);
+            }
+
+            Instruction::Load { x } => {
+                trace!("Opcode FX65: Reads V0..VX from memory, starting at I.");
+                // Opcode FX65: Sets V0, V1, ... Vx to the values in memory, starting
+                // at location I.
+                self.check_index_range(x as usize + 1)?;
+                let I = self.I as usize;
+                for i in 0..=x as usize {
+                    self.V[i] = self.read(I + i);
+                }
+                if self.quirks.memory {
+                    self.I = self.I.wrapping_add((x + 1) as u16);
                 }
+                self.advance_pc(2);
+            }
+
+            Instruction::SaveFlags { x } => {
+                trace!("Opcode FX75: Stores V0..VX in the RPL user flags");
+                // Opcode FX75 (SUPER-CHIP): Stores V0, V1, ..., VX in the RPL user flags.
+                // There are only eight flags, so X is capped at 7.
+                let count = (x as usize + 1).min(RPL_FLAG_COUNT);
+                self.rpl_flags[..count].copy_from_slice(&self.V[..count]);
+                self.advance_pc(2);
             }
 
-            op @ _ => {
-                eprintln!("Unknown opcode #08x{}", op);
+            Instruction::LoadFlags { x } => {
+                trace!("Opcode FX85: Reads V0..VX from the RPL user flags");
+                // Opcode FX85 (SUPER-CHIP): Sets V0, V1, ..., VX to the RPL user flags.
+                // There are only eight flags, so X is capped at 7.
+                let count = (x as usize + 1).min(RPL_FLAG_COUNT);
+                self.V[..count].copy_from_slice(&self.rpl_flags[..count]);
+                self.advance_pc(2);
             }
+
+            // 0NNN machine language routines, words that aren't instructions,
+            // and the XO-CHIP instructions when not emulating XO-CHIP
+            _ => return Err(self.unknown_opcode(instruction.encode())),
         }
+        Ok(())
+    }
+
+    /// Counts `opcode` as unknown and describes it
+    fn unknown_opcode(&mut self, opcode: u16) -> Error {
+        self.stats.unknown_opcodes += 1;
+        Error::UnknownOpcode { opcode, pc: self.pc }
+    }
+
+    /// Decrements the delay and sound timers.
+    /// Must be called 60 times per second, regardless of how many cycles are run.
+    pub fn tick_timers(&mut self) {
+        self.vblank = true;
+        self.stats.end_frame();
 
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
 
+        // The buzzer sounds while this is nonzero, see `audio::Buzzer`
         if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                // Buzz!
-            }
             self.sound_timer -= 1;
         }
     }
 }
+
+/// The registers from X to Y, both included, in that order, even if Y < X
+fn register_range(x: u8, y: u8) -> Vec<usize> {
+    let (x, y) = (x as usize, y as usize);
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    /// A machine with the quirks given and a fixed seed, about to run at 0x200
+    fn vm_with(quirks: Quirks) -> VirtualMachine {
+        VirtualMachine::with_options(quirks, Some(0), Profile::default())
+    }
+
+    fn vm() -> VirtualMachine {
+        vm_with(Quirks::default())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn state_round_trips_through_bytes() {
+        let mut vm = vm();
+        execute(&mut vm, 0x6342);
+        execute(&mut vm, 0xF329);
+        execute(&mut vm, 0xD005);
+        execute(&mut vm, 0xF315);
+        let state = vm.snapshot();
+
+        let mut bytes = Vec::new();
+        state.save_to_writer("ab12", &mut bytes).unwrap();
+        let loaded = VmState::load_from_reader(bytes.as_slice(), "ab12", Profile::Classic).unwrap();
+        assert!(loaded == state);
+
+        let mut restored = vm_with(Quirks::default());
+        restored.restore(&loaded);
+        assert_eq!(restored.pc, vm.pc);
+        assert_eq!(restored.V, vm.V);
+        assert_eq!(restored.framebuffer().pixel(0, 0), vm.framebuffer().pixel(0, 0));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn rejects_a_truncated_state() {
+        let mut bytes = Vec::new();
+        VirtualMachine::new().snapshot().save_to_writer("ab12", &mut bytes).unwrap();
+        bytes.truncate(100);
+        assert!(VmState::load_from_reader(bytes.as_slice(), "ab12", Profile::Classic).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn rejects_states_that_do_not_fit() {
+        let load = |state: &VmState, rom_sha1: &str, profile: Profile| {
+            let mut bytes = Vec::new();
+            state.save_to_writer("ab12", &mut bytes).unwrap();
+            VmState::load_from_reader(bytes.as_slice(), rom_sha1, profile)
+        };
+        let state = vm().snapshot();
+        assert!(load(&state, "ab12", Profile::Classic).is_ok());
+        // Another ROM, and another amount of memory
+        assert!(matches!(load(&state, "cd34", Profile::Classic), Err(Error::InvalidState(_))));
+        assert!(matches!(load(&state, "ab12", Profile::XoChip), Err(Error::InvalidState(_))));
+        let mut state = state;
+        state.memory.clear();
+        assert!(matches!(load(&state, "ab12", Profile::Classic), Err(Error::InvalidState(_))));
+
+        // A stack pointer past the stack would be out of bounds on the next RET
+        let mut state = vm().snapshot();
+        state.sp = 16;
+        assert!(load(&state, "ab12", Profile::Classic).is_ok());
+        state.sp = 17;
+        let err = load(&state, "ab12", Profile::Classic).err().unwrap();
+        assert_eq!(err.to_string(), "invalid save state: its stack pointer 17 is past the stack.");
+    }
+
+    /// A machine of the default quirks running `rom`
+    fn vm_running(rom: &[u8]) -> VirtualMachine {
+        let cart = Cartridge::from_bytes(rom).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&cart).unwrap();
+        vm
+    }
+
+    #[test]
+    fn rewinds_cycles() {
+        // LD V0,1; ADD V0,1 four times
+        let mut vm = vm_running(&[0x60, 0x01, 0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x70, 0x01]);
+        vm.enable_rewind(10);
+        for _ in 0..5 {
+            vm.run_cycle().unwrap();
+        }
+        assert_eq!(vm.pc, 0x20A);
+        assert_eq!(vm.V[0], 5);
+        for _ in 0..3 {
+            assert!(vm.rewind());
+        }
+        assert_eq!(vm.pc, 0x204);
+        assert_eq!(vm.V[0], 2);
+        // Only what was recorded can be undone
+        assert!(vm.rewind());
+        assert!(vm.rewind());
+        assert!(!vm.rewind());
+        assert_eq!(vm.pc, 0x200);
+    }
+
+    #[test]
+    fn does_not_rewind_unless_enabled() {
+        let mut vm = vm_running(&[0x60, 0x01]);
+        vm.run_cycle().unwrap();
+        assert!(!vm.rewind());
+        assert_eq!(vm.pc, 0x202);
+    }
+
+    /// The registers after each of 100 cycles of a program full of CXNN
+    fn random_run(seed: u64) -> Vec<[u8; 16]> {
+        // RND V0,0xff; RND V1,0x0f; RND V2,0xf0; LD V3,V0; JP 0x200
+        let rom = [0xC0, 0xFF, 0xC1, 0x0F, 0xC2, 0xF0, 0x83, 0x00, 0x12, 0x00];
+        let mut vm = VirtualMachine::with_seed(seed);
+        let cart = Cartridge::from_bytes(&rom).unwrap();
+        vm.load_rom(&cart).unwrap();
+        (0..100)
+            .map(|_| {
+                vm.run_cycle().unwrap();
+                vm.V
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        assert_eq!(random_run(7), random_run(7));
+        assert_ne!(random_run(7), random_run(8));
+    }
+
+    #[test]
+    fn random_numbers_are_masked() {
+        for registers in random_run(1234) {
+            assert_eq!(registers[1] & 0xF0, 0);
+            assert_eq!(registers[2] & 0x0F, 0);
+        }
+    }
+
+    /// Runs `opcode` as the next instruction of `vm`, leaving the program in memory as it was
+    fn execute(vm: &mut VirtualMachine, opcode: u16) {
+        let pc = vm.pc as usize;
+        let program = [vm.memory[pc], vm.memory[pc + 1]];
+        vm.memory[pc] = (opcode >> 8) as u8;
+        vm.memory[pc + 1] = opcode as u8;
+        vm.run_cycle().unwrap();
+        vm.memory[pc..pc + 2].copy_from_slice(&program);
+    }
+
+    /// The pixels lit on the screen, row by row
+    fn lit(vm: &VirtualMachine) -> Vec<(usize, usize)> {
+        let framebuffer = vm.framebuffer();
+        let mut pixels = Vec::new();
+        for y in 0..framebuffer.height() {
+            for x in 0..framebuffer.width() {
+                if framebuffer.pixel(x, y) != 0 {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn switches_resolution() {
+        let mut vm = VirtualMachine::new();
+        assert_eq!((vm.width(), vm.height()), (LORES_WIDTH, LORES_HEIGHT));
+        // HIGH
+        execute(&mut vm, 0x00FF);
+        assert!(vm.is_hires());
+        assert_eq!((vm.width(), vm.height()), (128, 64));
+        // LOW
+        execute(&mut vm, 0x00FE);
+        assert!(!vm.is_hires());
+        assert_eq!((vm.width(), vm.height()), (64, 32));
+        assert_eq!(vm.pc, 0x204);
+    }
+
+    #[test]
+    fn switching_resolution_clears_the_screen() {
+        let mut vm = VirtualMachine::new();
+        // The 0 glyph, with DRW V0,V0,5
+        vm.I = FONTSET_ADDRESS as u16;
+        execute(&mut vm, 0xD005);
+        assert!(!lit(&vm).is_empty());
+        execute(&mut vm, 0x00FF);
+        assert!(lit(&vm).is_empty());
+
+        vm.vblank = true;
+        execute(&mut vm, 0xD005);
+        assert!(!lit(&vm).is_empty());
+        execute(&mut vm, 0x00FE);
+        assert!(lit(&vm).is_empty());
+    }
+
+    /// A machine in high resolution showing the pixels at `pixels`
+    fn hires_with(pixels: &[(usize, usize)]) -> VirtualMachine {
+        let mut vm = vm();
+        execute(&mut vm, 0x00FF);
+        for &(x, y) in pixels {
+            vm.graphics[0][y] |= 1 << x;
+        }
+        vm
+    }
+
+    #[test]
+    fn scrolls_down() {
+        let mut vm = hires_with(&[(0, 0), (5, 10), (7, 60)]);
+        execute(&mut vm, 0x00C3);
+        // What goes past the bottom is lost
+        assert_eq!(lit(&vm), [(0, 3), (5, 13), (7, 63)]);
+        execute(&mut vm, 0x00C1);
+        assert_eq!(lit(&vm), [(0, 4), (5, 14)]);
+    }
+
+    #[test]
+    fn scrolls_right_and_left() {
+        let mut vm = hires_with(&[(0, 0), (60, 1), (125, 2)]);
+        execute(&mut vm, 0x00FB);
+        assert_eq!(lit(&vm), [(4, 0), (64, 1)]);
+        execute(&mut vm, 0x00FC);
+        execute(&mut vm, 0x00FC);
+        assert_eq!(lit(&vm), [(56, 1)]);
+    }
+
+    #[test]
+    fn scrolls_within_the_low_resolution_screen() {
+        let mut vm = vm();
+        vm.graphics[0][0] = 1 << 62;
+        vm.graphics[0][30] = 1;
+        execute(&mut vm, 0x00FB);
+        assert_eq!(lit(&vm), [(4, 30)]);
+        execute(&mut vm, 0x00C2);
+        assert!(lit(&vm).is_empty());
+    }
+
+    /// The rows of a 16x16 sprite: a diagonal from the top left along with the rightmost column
+    fn big_sprite() -> [u16; 16] {
+        let mut rows = [0; 16];
+        for (row, bits) in rows.iter_mut().enumerate() {
+            *bits = (0x8000 >> row) | 1;
+        }
+        rows
+    }
+
+    /// Draws `big_sprite` with DXY0 at (x, y) on a blank high resolution screen
+    fn draw_big_sprite(vm: &mut VirtualMachine, x: u8, y: u8) {
+        for (row, bits) in big_sprite().iter().enumerate() {
+            vm.memory[0x300 + 2 * row..0x302 + 2 * row].copy_from_slice(&bits.to_be_bytes());
+        }
+        execute(vm, 0x00FF);
+        vm.I = 0x300;
+        vm.V[0] = x;
+        vm.V[1] = y;
+        execute(vm, 0xD010);
+    }
+
+    /// Where the pixels of `big_sprite` drawn at (x, y) land, wrapping around the edges
+    fn big_sprite_pixels(x: usize, y: usize, clip: bool) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for (row, bits) in big_sprite().iter().enumerate() {
+            for column in 0..16 {
+                if bits & (0x8000 >> column) != 0 && !(clip && (x + column >= 128 || y + row >= 64)) {
+                    pixels.push(((x + column) % 128, (y + row) % 64));
+                }
+            }
+        }
+        pixels.sort_by_key(|&(x, y)| (y, x));
+        pixels
+    }
+
+    #[test]
+    fn draws_16x16_sprites() {
+        for (x, y) in [(0, 0), (37, 20), (112, 48)] {
+            let mut vm = vm();
+            draw_big_sprite(&mut vm, x, y);
+            assert_eq!(lit(&vm), big_sprite_pixels(x as usize, y as usize, false), "at ({}, {})", x, y);
+            assert_eq!(vm.V[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn big_sprites_wrap_or_clip_at_the_edges() {
+        let mut vm = vm();
+        draw_big_sprite(&mut vm, 120, 56);
+        assert_eq!(lit(&vm), big_sprite_pixels(120, 56, false));
+
+        let mut vm = vm_with(Quirks { clip: true, ..Quirks::default() });
+        draw_big_sprite(&mut vm, 120, 56);
+        assert_eq!(lit(&vm), big_sprite_pixels(120, 56, true));
+        // Only the top left 8x8 corner is left: half of the diagonal
+        assert_eq!(lit(&vm).len(), 8);
+    }
+
+    #[test]
+    fn rpl_flags_round_trip() {
+        let mut vm = vm();
+        vm.V[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        execute(&mut vm, 0xF775);
+        assert_eq!(vm.rpl_flags, [1, 2, 3, 4, 5, 6, 7, 8]);
+        vm.V = [0; 16];
+        execute(&mut vm, 0xF785);
+        assert_eq!(vm.V[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(vm.pc, 0x204);
+    }
+
+    #[test]
+    fn rpl_flags_stop_at_v7() {
+        let mut vm = vm();
+        for (i, register) in vm.V.iter_mut().enumerate() {
+            *register = i as u8 + 1;
+        }
+        execute(&mut vm, 0xFF75);
+        assert_eq!(vm.rpl_flags, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        vm.V = [0xFF; 16];
+        execute(&mut vm, 0xFC85);
+        assert_eq!(vm.V[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(vm.V[8..], [0xFF; 8]);
+    }
+
+    #[test]
+    fn big_font_digits() {
+        let mut vm = vm();
+        for digit in 0..10 {
+            vm.V[4] = digit;
+            execute(&mut vm, 0xF430);
+            let start = vm.I as usize;
+            let glyph = &BIG_FONTSET[digit as usize * 10..][..10];
+            assert_eq!(&vm.memory[start..start + 10], glyph, "digit {}", digit);
+        }
+    }
+
+    #[test]
+    fn small_font_digits() {
+        let mut vm = vm();
+        execute(&mut vm, 0xF430);
+        for digit in 0..16 {
+            vm.V[4] = digit;
+            execute(&mut vm, 0xF429);
+            let start = vm.I as usize;
+            let glyph = &FONTSET[digit as usize * 5..][..5];
+            assert_eq!(&vm.memory[start..start + 5], glyph, "digit {:X}", digit);
+        }
+    }
+
+    #[test]
+    fn exit_halts_the_machine() {
+        // LD V0,1; EXIT
+        let mut vm = vm_running(&[0x60, 0x01, 0x00, 0xFD]);
+        vm.run_cycle().unwrap();
+        assert!(!vm.is_halted());
+        vm.run_cycle().unwrap();
+        assert!(vm.is_halted());
+        assert_eq!(vm.pc, 0x202);
+        for _ in 0..5 {
+            vm.run_cycle().unwrap();
+        }
+        assert_eq!(vm.pc, 0x202);
+        assert_eq!(vm.stats.instructions, 2);
+        // Halted, but not by a spin loop, whatever is at the program counter now
+        vm.memory[0x202..0x204].copy_from_slice(&[0x12, 0x02]);
+        assert!(!vm.is_spinning());
+    }
+
+    /// An XO-CHIP machine, with no quirks, about to run at 0x200
+    fn xo_vm() -> VirtualMachine {
+        VirtualMachine::with_options(Quirks::default(), Some(0), Profile::XoChip)
+    }
+
+    /// Selects `planes` and draws the `n` rows of sprite data at 0x300 at (x, 0)
+    fn draw_on_planes(vm: &mut VirtualMachine, planes: u8, x: u8, n: u8) {
+        execute(vm, 0xF001 | (planes as u16) << 8);
+        vm.I = 0x300;
+        vm.V[0] = x;
+        vm.V[1] = 0;
+        execute(vm, 0xD010 | n as u16);
+    }
+
+    #[test]
+    fn draws_on_each_plane() {
+        let mut vm = xo_vm();
+        // A row for the first plane, then one for the second when drawing on both
+        vm.memory[0x300] = 0x80;
+        vm.memory[0x301] = 0x40;
+
+        draw_on_planes(&mut vm, 1, 0, 1);
+        draw_on_planes(&mut vm, 2, 10, 1);
+        draw_on_planes(&mut vm, 3, 20, 1);
+        let pixels = vm.framebuffer();
+        assert_eq!(pixels.pixel(0, 0), 1);
+        assert_eq!(pixels.pixel(10, 0), 2);
+        assert_eq!(pixels.pixel(20, 0), 1);
+        assert_eq!(pixels.pixel(21, 0), 2);
+        assert_eq!(lit(&vm).len(), 4);
+
+        // Both planes set on the same pixel
+        draw_on_planes(&mut vm, 2, 0, 1);
+        assert_eq!(vm.framebuffer().pixel(0, 0), 3);
+    }
+
+    #[test]
+    fn clears_only_the_selected_planes() {
+        let mut vm = xo_vm();
+        vm.memory[0x300] = 0xFF;
+        vm.memory[0x301] = 0xFF;
+        draw_on_planes(&mut vm, 3, 0, 1);
+        assert_eq!(vm.framebuffer().pixel(0, 0), 3);
+        execute(&mut vm, 0xF101);
+        execute(&mut vm, 0x00E0);
+        assert_eq!(vm.framebuffer().pixel(0, 0), 2);
+        // With no plane selected, drawing does nothing
+        draw_on_planes(&mut vm, 0, 0, 1);
+        assert_eq!(vm.framebuffer().pixel(0, 0), 2);
+    }
+
+    #[test]
+    fn loads_the_audio_pattern_and_pitch() {
+        let mut vm = xo_vm();
+        for i in 0..16 {
+            vm.memory[0x400 + i] = i as u8 * 0x11;
+        }
+        vm.I = 0x400;
+        execute(&mut vm, 0xF002);
+        assert_eq!(vm.audio_pattern[..4], [0x00, 0x11, 0x22, 0x33]);
+        assert_eq!(vm.I, 0x400);
+        vm.V[2] = 112;
+        execute(&mut vm, 0xF23A);
+        assert_eq!(vm.pitch, 112);
+
+        // Only heard while the sound timer runs
+        assert_eq!(vm.tone(), None);
+        vm.V[3] = 2;
+        execute(&mut vm, 0xF318);
+        assert_eq!(vm.tone(), Some(Tone::Pattern { pattern: vm.audio_pattern, rate: audio::playback_rate(112) }));
+    }
+
+    #[test]
+    fn a_blank_pattern_plays_the_buzzer() {
+        let mut vm = xo_vm();
+        vm.V[0] = 2;
+        execute(&mut vm, 0xF018);
+        assert_eq!(vm.tone(), Some(Tone::Square));
+    }
+
+    #[test]
+    fn draws_across_the_high_resolution_screen() {
+        let mut vm = vm();
+        execute(&mut vm, 0x00FF);
+        vm.I = FONTSET_ADDRESS as u16;
+        vm.V[0] = 100;
+        vm.V[1] = 50;
+        execute(&mut vm, 0xD015);
+        // The 0 glyph: a 4x5 box
+        let mut expected = Vec::new();
+        for y in 50..55 {
+            for x in 100..104 {
+                if y == 50 || y == 54 || x == 100 || x == 103 {
+                    expected.push((x, y));
+                }
+            }
+        }
+        assert_eq!(lit(&vm), expected);
+    }
+
+    #[test]
+    fn wraps_at_the_low_resolution_edge() {
+        let mut vm = vm();
+        vm.I = FONTSET_ADDRESS as u16;
+        vm.V[0] = 100;
+        vm.V[1] = 50;
+        execute(&mut vm, 0xD011);
+        // 100 % 64 and 50 % 32
+        assert_eq!(lit(&vm), [(36, 18), (37, 18), (38, 18), (39, 18)]);
+    }
+
+    /// An XO-CHIP machine, with no quirks, running `rom`
+    fn xo_vm_running(rom: &[u8]) -> VirtualMachine {
+        let mut vm = xo_vm();
+        vm.load_rom(&Cartridge::from_bytes(rom).unwrap()).unwrap();
+        vm
+    }
+
+    #[test]
+    fn long_index_load() {
+        // LD I,0x1234 (F000 1234); CLS
+        let mut vm = xo_vm_running(&[0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0]);
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.I, 0x1234);
+        assert_eq!(vm.pc, 0x204);
+    }
+
+    #[test]
+    fn skips_over_a_long_index_load() {
+        // SE V0,0; F000 1234; LD V1,1
+        let mut vm = xo_vm_running(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x61, 0x01]);
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.pc, 0x206);
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.V[1], 1);
+        assert_eq!(vm.I, 0);
+
+        // Elsewhere F000 is a two-byte word like any other
+        let mut vm = vm_running(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34]);
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.pc, 0x204);
+    }
+
+    /// Draws a single pixel at (x, y) with DXYN
+    fn draw_marker(vm: &mut VirtualMachine, x: u8, y: u8) {
+        vm.memory[0x300] = 0x80;
+        vm.I = 0x300;
+        vm.V[0] = x;
+        vm.V[1] = y;
+        execute(vm, 0xD011);
+    }
+
+    #[test]
+    fn scrolls_a_marker_in_each_direction() {
+        // SCD 5, SCR and SCL
+        let scrolls = [(0x00C5, (20, 15)), (0x00FB, (24, 10)), (0x00FC, (16, 10))];
+        for hires in [false, true] {
+            for &(scroll, position) in &scrolls {
+                let mut vm = vm();
+                if hires {
+                    execute(&mut vm, 0x00FF);
+                }
+                draw_marker(&mut vm, 20, 10);
+                execute(&mut vm, scroll);
+                assert_eq!(lit(&vm), [position], "{:04X} in {}", scroll, if hires { "hires" } else { "lores" });
+                assert!(vm.draw_to_screen);
+            }
+        }
+    }
+
+    #[test]
+    fn saves_register_ranges() {
+        let cases: [(u8, u8, &[u8]); 3] = [(2, 5, &[2, 3, 4, 5]), (5, 2, &[5, 4, 3, 2]), (7, 7, &[7])];
+        for (x, y, expected) in cases {
+            let mut vm = xo_vm();
+            for (i, register) in vm.V.iter_mut().enumerate() {
+                *register = i as u8;
+            }
+            vm.I = 0x400;
+            execute(&mut vm, 0x5002 | (x as u16) << 8 | (y as u16) << 4);
+            assert_eq!(&vm.memory[0x400..0x400 + expected.len()], expected, "5{:X}{:X}2", x, y);
+            assert_eq!(vm.memory[0x400 + expected.len()], 0);
+            assert_eq!(vm.I, 0x400);
+        }
+    }
+
+    #[test]
+    fn loads_register_ranges() {
+        let cases: [(u8, u8, &[usize]); 3] = [(2, 5, &[2, 3, 4, 5]), (5, 2, &[5, 4, 3, 2]), (7, 7, &[7])];
+        for (x, y, registers) in cases {
+            let mut vm = xo_vm();
+            vm.memory[0x400..0x404].copy_from_slice(&[10, 11, 12, 13]);
+            vm.I = 0x400;
+            execute(&mut vm, 0x5003 | (x as u16) << 8 | (y as u16) << 4);
+            for (offset, &register) in registers.iter().enumerate() {
+                assert_eq!(vm.V[register], 10 + offset as u8, "5{:X}{:X}3 into V{:X}", x, y, register);
+            }
+            let mut untouched = vm.V.iter().enumerate().filter(|(i, _)| !registers.contains(i));
+            assert!(untouched.all(|(_, &value)| value == 0));
+            assert_eq!(vm.I, 0x400);
+        }
+    }
+
+    #[test]
+    fn draws_a_solid_16x16_block() {
+        let mut vm = vm();
+        execute(&mut vm, 0x00FF);
+        vm.memory[0x300..0x320].fill(0xFF);
+        vm.I = 0x300;
+        vm.V[0] = 40;
+        vm.V[1] = 30;
+        execute(&mut vm, 0xD010);
+        let block: Vec<(usize, usize)> = (30..46).flat_map(|y| (40..56).map(move |x| (x, y))).collect();
+        assert_eq!(lit(&vm), block);
+
+        // Drawn again, every pixel collides
+        vm.vblank = true;
+        execute(&mut vm, 0xD010);
+        assert_eq!(vm.V[0xF], 1);
+        assert!(lit(&vm).is_empty());
+    }
+
+    #[test]
+    fn dxy0_is_8x16_in_low_resolution() {
+        let mut vm = vm();
+        vm.memory[0x300..0x320].fill(0xFF);
+        vm.I = 0x300;
+        execute(&mut vm, 0xD010);
+        let block: Vec<(usize, usize)> = (0..16).flat_map(|y| (0..8).map(move |x| (x, y))).collect();
+        assert_eq!(lit(&vm), block);
+    }
+
+    #[test]
+    fn big_roms_need_the_xo_chip_memory() {
+        let mut rom = vec![0; 8 * 1024];
+        rom[..2].copy_from_slice(&[0x12, 0x00]);
+        *rom.last_mut().unwrap() = 0xAB;
+        let cart = Cartridge::from_bytes(&rom).unwrap();
+
+        let mut classic = vm();
+        let result = classic.load_rom(&cart);
+        assert!(result.is_err());
+
+        let mut xo_chip = xo_vm();
+        xo_chip.load_rom(&cart).unwrap();
+        assert_eq!(xo_chip.memory.len(), 65536);
+        assert_eq!(xo_chip.memory[0x200 + 8191], 0xAB);
+        xo_chip.run_cycle().unwrap();
+        assert_eq!(xo_chip.pc, 0x200);
+    }
+
+    #[test]
+    fn roms_can_fill_the_classic_memory() {
+        let cart = Cartridge::from_bytes(&[0; 4096 - 0x200]).unwrap();
+        vm().load_rom(&cart).unwrap();
+        let cart = Cartridge::from_bytes(&[0; 4096 - 0x200 + 1]).unwrap();
+        assert!(vm().load_rom(&cart).is_err());
+    }
+
+    #[test]
+    fn runs_programs_loaded_at_0x600() {
+        let mut vm = vm();
+        // LD V0,0x42; JP 0x602
+        vm.load_rom_at(&Cartridge::from_bytes(&[0x60, 0x42, 0x16, 0x02]).unwrap(), 0x600).unwrap();
+        assert_eq!(vm.pc, 0x600);
+        assert_eq!(vm.memory[0x200], 0);
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.opcode, 0x6042);
+        assert_eq!(vm.V[0], 0x42);
+        assert_eq!(vm.pc, 0x602);
+    }
+
+    #[test]
+    fn load_address_must_leave_room_for_the_rom() {
+        let mut vm = vm();
+        let result = vm.load_rom_at(&Cartridge::from_bytes(&[0x60, 0x42, 0x00, 0xE0]).unwrap(), 0xFFE);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rpl_flags_keep_registers_across_a_reset() {
+        // LD V0,7; LD V1,8; LD V2,9; LD R,V2
+        let mut vm = vm_running(&[0x60, 0x07, 0x61, 0x08, 0x62, 0x09, 0xF2, 0x75]);
+        for _ in 0..4 {
+            vm.run_cycle().unwrap();
+        }
+        // Reset as the frontend does, with a new machine given the flags of the old one.
+        // LD V2,R
+        let mut reset = vm_running(&[0xF2, 0x85]);
+        reset.rpl_flags = vm.rpl_flags;
+        assert_eq!(reset.V[..3], [0, 0, 0]);
+        reset.run_cycle().unwrap();
+        assert_eq!(reset.V[..3], [7, 8, 9]);
+        assert_eq!(reset.rpl_flags[3..], [0; 5]);
+    }
+
+    #[test]
+    fn long_index_reaches_the_top_of_memory() {
+        // LD I,0xFFF0 (F000 FFF0); LD V1,[I]
+        let mut vm = xo_vm_running(&[0xF0, 0x00, 0xFF, 0xF0, 0xF1, 0x65]);
+        vm.memory[0xFFF0] = 0xAB;
+        vm.memory[0xFFF1] = 0xCD;
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.I, 0xFFF0);
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.V[..2], [0xAB, 0xCD]);
+    }
+
+    #[test]
+    fn drawing_on_plane_2_leaves_plane_1_alone() {
+        // PLANE 2 (F201); DRW V0,V1,5
+        let mut vm = xo_vm_running(&[0xF2, 0x01, 0xD0, 0x15]);
+        // The 0 glyph on the first plane, half covered by the 8 glyph on the second
+        vm.I = FONTSET_ADDRESS as u16;
+        execute(&mut vm, 0xD005);
+        let screen = vm.framebuffer();
+        let before: Vec<u8> = (0..5).flat_map(|y| (0..8).map(move |x| screen.pixel(x, y))).collect();
+        vm.pc = 0x200;
+        vm.V[0] = 2;
+        vm.I = FONTSET_ADDRESS as u16 + 8 * 5;
+        vm.run_cycle().unwrap();
+        vm.run_cycle().unwrap();
+
+        let screen = vm.framebuffer();
+        for y in 0..5 {
+            for x in 0..8 {
+                let plane_1 = before[y * 8 + x];
+                let plane_2 = if x >= 2 && FONTSET[8 * 5 + y] & (0x80 >> (x - 2)) != 0 { 2 } else { 0 };
+                assert_eq!(screen.pixel(x, y), plane_1 | plane_2, "pixel ({}, {})", x, y);
+            }
+        }
+        // Nothing was drawn over on the second plane
+        assert_eq!(vm.V[0xF], 0);
+    }
+
+    #[test]
+    fn a_single_pixel_flip_marks_one_dirty_pixel() {
+        let mut vm = vm();
+        // Everything needs drawing at first
+        assert_eq!(vm.framebuffer().dirty_count(), 64 * 32);
+        vm.clear_dirty();
+        draw_marker(&mut vm, 10, 20);
+        let screen = vm.framebuffer();
+        assert_eq!(screen.dirty_count(), 1);
+        assert!(screen.is_dirty(10, 20));
+        assert!(!screen.is_dirty(11, 20));
+
+        vm.clear_dirty();
+        assert_eq!(vm.framebuffer().dirty_count(), 0);
+        // Turning it back off is a change too
+        draw_marker(&mut vm, 10, 20);
+        assert_eq!(vm.framebuffer().dirty_count(), 1);
+        assert!(lit(&vm).is_empty());
+
+        // Switching resolution redraws the whole screen
+        execute(&mut vm, 0x00FF);
+        assert_eq!(vm.framebuffer().dirty_count(), 128 * 64);
+    }
+
+    #[test]
+    fn xors_packed_rows() {
+        let mut vm = xo_vm();
+        vm.clear_dirty();
+        assert!(!vm.xor_pixels(3, 0b1010, 1));
+        assert_eq!(vm.graphics[0][3], 0b1010);
+        assert_eq!(vm.graphics[1][3], 0);
+        // Only the overlapping bit collides, and flips back off
+        assert!(vm.xor_pixels(3, 0b0110, 1));
+        assert_eq!(vm.graphics[0][3], 0b1100);
+        assert_eq!(vm.dirty[3], 0b1110);
+
+        // Both planes at once, past the low resolution width too
+        let far = 1 << 127;
+        assert!(!vm.xor_pixels(0, far, 3));
+        assert_eq!([vm.graphics[0][0], vm.graphics[1][0]], [far, far]);
+        // No plane selected: nothing changes
+        assert!(!vm.xor_pixels(5, 1, 0));
+        assert_eq!(vm.dirty[5], 0);
+    }
+
+    #[test]
+    fn sets_packed_rows() {
+        let mut vm = vm();
+        vm.graphics[0][7] = 0b1100;
+        vm.clear_dirty();
+        vm.set_row(0, 7, 0b0110);
+        assert_eq!(vm.graphics[0][7], 0b0110);
+        assert_eq!(vm.dirty[7], 0b1010);
+        assert_eq!(vm.row_mask(), u64::MAX as u128);
+        execute(&mut vm, 0x00FF);
+        assert_eq!(vm.row_mask(), u128::MAX);
+    }
+
+    #[test]
+    fn marks_where_a_sprite_was_drawn() {
+        let mut vm = vm();
+        vm.clear_dirty();
+        vm.I = FONTSET_ADDRESS as u16;
+        vm.V[0] = 20;
+        vm.V[1] = 10;
+        execute(&mut vm, 0xD015);
+        let screen = vm.framebuffer();
+        let dirty: Vec<(usize, usize)> =
+            (0..32).flat_map(|y| (0..64).map(move |x| (x, y))).filter(|&(x, y)| screen.is_dirty(x, y)).collect();
+        // Only the pixels of the 0 glyph that turned on
+        assert_eq!(dirty, lit(&vm));
+        assert_eq!(screen.dirty_count(), 14);
+        assert!(dirty.iter().all(|&(x, y)| (20..24).contains(&x) && (10..15).contains(&y)));
+
+        vm.clear_dirty();
+        assert_eq!(vm.framebuffer().dirty_count(), 0);
+        // Clearing the screen only changes the pixels that were on
+        execute(&mut vm, 0x00E0);
+        assert_eq!(vm.framebuffer().dirty_count(), 14);
+    }
+
+    #[test]
+    fn dumps_what_the_program_stored() {
+        // LD V0,0xAA; LD V1,0xBB; LD I,0xFFE; LD [I],V1
+        let mut vm = vm_running(&[0x60, 0xAA, 0x61, 0xBB, 0xAF, 0xFE, 0xF1, 0x55]);
+        for _ in 0..4 {
+            vm.run_cycle().unwrap();
+        }
+        assert_eq!(vm.dump_memory(0x200, 4), [0x60, 0xAA, 0x61, 0xBB]);
+        assert_eq!(vm.dump_memory(0xFFE, 2), [0xAA, 0xBB]);
+        // Cut short at the end of memory rather than wrapping
+        assert_eq!(vm.dump_memory(0xFFE, 0x100), [0xAA, 0xBB]);
+        assert!(vm.dump_memory(0xFFFF, 16).is_empty());
+        assert!(vm.dump_memory(0x200, 0).is_empty());
+    }
+
+    #[test]
+    fn builder_defaults_match_new() {
+        let vm = VirtualMachine::builder().build().unwrap();
+        assert_eq!(vm.quirks, Quirks::default_set());
+        assert!(!vm.xo_chip);
+        assert_eq!(vm.memory.len(), 4096);
+        assert_eq!(vm.load_address, PROGRAM_START);
+        assert_eq!(vm.pc, PROGRAM_START);
+        let new = VirtualMachine::new();
+        assert_eq!((new.quirks, new.xo_chip, new.pc), (vm.quirks, vm.xo_chip, vm.pc));
+    }
+
+    #[test]
+    fn builder_sets_each_option() {
+        let quirks = Quirks { shift: true, jump: true, ..Quirks::default() };
+        let builder = VirtualMachine::builder()
+            .quirks(quirks)
+            .seed(7)
+            .profile(Profile::XoChip)
+            .load_address(0x600);
+        let vm = builder.build().unwrap();
+        assert_eq!(vm.quirks, quirks);
+        assert!(vm.xo_chip);
+        assert_eq!(vm.memory.len(), 65536);
+        assert_eq!((vm.load_address, vm.pc), (0x600, 0x600));
+
+        // Building again gives the same random numbers
+        let (mut first, mut second) = (builder.build().unwrap(), builder.build().unwrap());
+        execute(&mut first, 0xC0FF);
+        execute(&mut second, 0xC0FF);
+        assert_eq!(first.V[0], second.V[0]);
+    }
+
+    #[test]
+    fn builder_rejects_a_load_address_past_memory() {
+        let result = VirtualMachine::builder().load_address(0xFFFF).build();
+        assert!(matches!(result, Err(Error::LoadAddressOutOfRange { address: 0xFFFF, memory_size: 4096 })));
+        // Fine with the XO-CHIP memory
+        assert!(VirtualMachine::builder().profile(Profile::XoChip).load_address(0xFFFF).build().is_ok());
+    }
+
+    /// Runs until `should_break` or `limit` cycles, returning how many ran
+    fn run_to_break(vm: &mut VirtualMachine, limit: usize) -> usize {
+        let mut cycles = 0;
+        while cycles < limit && !vm.should_break() {
+            vm.run_cycle().unwrap();
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        // ADD V0,1; ADD V1,1; ADD V2,1; JP 0x200
+        let mut vm = vm_running(&[0x70, 0x01, 0x71, 0x01, 0x72, 0x01, 0x12, 0x00]);
+        vm.add_breakpoint(0x204);
+        assert_eq!(run_to_break(&mut vm, 100), 2);
+        assert_eq!(vm.pc(), 0x204);
+        // Stopped before running it
+        assert_eq!(vm.V[..3], [1, 1, 0]);
+
+        // Resuming runs past it, around the loop and back to it
+        assert_eq!(run_to_break(&mut vm, 100), 4);
+        assert_eq!(vm.pc(), 0x204);
+        assert_eq!(vm.V[..3], [2, 2, 1]);
+
+        vm.remove_breakpoint(0x204);
+        assert_eq!(run_to_break(&mut vm, 100), 100);
+    }
+
+    #[test]
+    fn stops_at_opcode_breakpoints() {
+        // LD V0,1; LD V1,2; LD F,V0; DRW V0,V1,5; LD V0,K
+        let mut vm = vm_running(&[0x60, 0x01, 0x61, 0x02, 0xF0, 0x29, 0xD0, 0x15, 0xF0, 0x0A]);
+        vm.add_opcode_breakpoint(OpcodePattern::parse("DXYN").unwrap());
+        vm.add_opcode_breakpoint(OpcodePattern::parse("F?0A").unwrap());
+        // Right before the first sprite is drawn
+        assert_eq!(run_to_break(&mut vm, 100), 3);
+        assert_eq!(vm.pc(), 0x206);
+        assert_eq!(vm.framebuffer().pixel(3, 2), 0);
+        // Then before the keypad is read
+        assert_eq!(run_to_break(&mut vm, 100), 1);
+        assert_eq!(vm.pc(), 0x208);
+        // The top of the 1 at (1, 2)
+        assert_eq!(vm.framebuffer().pixel(3, 2), 1);
+        // Resuming with no key held waits there, without stopping again
+        assert_eq!(run_to_break(&mut vm, 100), 100);
+        assert_eq!(vm.pc(), 0x208);
+
+        // Along with an address breakpoint, whichever comes first
+        let mut vm = vm_running(&[0x60, 0x01, 0x61, 0x02, 0xF0, 0x29, 0xD0, 0x15, 0xF0, 0x0A]);
+        vm.add_opcode_breakpoint(OpcodePattern::parse("DXYN").unwrap());
+        vm.add_breakpoint(0x202);
+        assert_eq!(run_to_break(&mut vm, 100), 1);
+        assert_eq!(vm.pc(), 0x202);
+        vm.remove_breakpoint(0x202);
+        assert_eq!(run_to_break(&mut vm, 100), 2);
+        assert_eq!(vm.pc(), 0x206);
+        vm.remove_opcode_breakpoint(OpcodePattern::parse("D???").unwrap());
+        assert!(!vm.should_break());
+        vm.run_cycle().unwrap();
+        assert!(!vm.should_break());
+    }
+
+    #[test]
+    fn stops_on_unknown_opcodes() {
+        // LD V0,1; 0x8008
+        let mut vm = vm_running(&[0x60, 0x01, 0x80, 0x08]);
+        vm.run_cycle().unwrap();
+        let result = vm.run_cycle();
+        assert!(matches!(result, Err(Error::UnknownOpcode { opcode: 0x8008, pc: 0x202 })));
+        // Left on the offending instruction
+        assert_eq!(vm.pc(), 0x202);
+        assert!(vm.run_cycle().is_err());
+    }
+
+    #[test]
+    fn watches_a_digit_written_by_fx33() {
+        // LD V0,234; LD I,0x300; LD B,V0
+        let mut vm = vm_running(&[0x60, 0xEA, 0xA3, 0x00, 0xF0, 0x33]);
+        vm.memory[0x301] = 0x77;
+        vm.add_watchpoint(0x301);
+        vm.run_cycle().unwrap();
+        vm.run_cycle().unwrap();
+        assert!(vm.take_events().is_empty());
+
+        vm.run_cycle().unwrap();
+        let events = vm.take_events();
+        let write = Chip8Event::MemoryWrite { pc: 0x204, opcode: 0xF033, addr: 0x301, old: 0x77, value: 3 };
+        assert_eq!(events, [write]);
+        assert_eq!(write.to_string(), "Wrote 0x03 to 0x301 (was 0x77) by F033 at 0x204");
+        assert!(vm.take_events().is_empty());
+
+        // Unwatched, the same write goes unreported
+        vm.remove_watchpoint(0x301);
+        vm.pc = 0x204;
+        vm.run_cycle().unwrap();
+        assert!(vm.take_events().is_empty());
+    }
+
+    #[test]
+    fn watches_the_part_of_fx55_crossing_into_a_range() {
+        // LD V0,1; LD V1,2; LD V2,3; LD V3,4; LD I,0x2fe; LD [I],V3
+        let mut vm = vm_running(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x63, 0x04, 0xA2, 0xFE, 0xF3, 0x55]);
+        for address in 0x300..=0x30F {
+            vm.add_watchpoint(address);
+        }
+        vm.step(5).unwrap();
+        assert!(vm.take_events().is_empty());
+
+        // Only the two bytes past the boundary are reported, in the order they were written
+        vm.run_cycle().unwrap();
+        let (pc, opcode) = (0x20A, 0xF355);
+        assert_eq!(
+            vm.take_events(),
+            [
+                Chip8Event::MemoryWrite { pc, opcode, addr: 0x300, old: 0, value: 3 },
+                Chip8Event::MemoryWrite { pc, opcode, addr: 0x301, old: 0, value: 4 },
+            ]
+        );
+        assert_eq!(vm.dump_memory(0x2FE, 4), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn watches_register_changes() {
+        // LD V3,5; LD V3,5; ADD V3,1; LD I,0x300; LD VA,1
+        let mut vm = vm_running(&[0x63, 0x05, 0x63, 0x05, 0x73, 0x01, 0xA3, 0x00, 0x6A, 0x01]);
+        vm.add_register_watch(Register::parse("v3").unwrap());
+        vm.add_register_watch(Register::parse("I").unwrap());
+        vm.run_cycle().unwrap();
+        let set = Chip8Event::RegisterChange { pc: 0x200, opcode: 0x6305, register: Register::V(3), old: 0, value: 5 };
+        assert_eq!(vm.take_events(), [set]);
+        assert_eq!(set.to_string(), "Set V3 to 0x05 (was 0x00) by 6305 at 0x200");
+
+        // Writing the same value isn't a change
+        vm.run_cycle().unwrap();
+        assert!(vm.take_events().is_empty());
+        vm.run_cycle().unwrap();
+        assert_eq!(
+            vm.take_events(),
+            [Chip8Event::RegisterChange { pc: 0x204, opcode: 0x7301, register: Register::V(3), old: 5, value: 6 }]
+        );
+        vm.run_cycle().unwrap();
+        assert_eq!(
+            vm.take_events(),
+            [Chip8Event::RegisterChange { pc: 0x206, opcode: 0xA300, register: Register::I, old: 0, value: 0x300 }]
+        );
+        // VA isn't watched
+        vm.run_cycle().unwrap();
+        assert!(vm.take_events().is_empty());
+
+        assert_eq!(Register::parse("VF"), Some(Register::V(0xF)));
+        for name in ["V", "V10", "VG", "X", ""] {
+            assert_eq!(Register::parse(name), None);
+        }
+    }
+
+    #[test]
+    fn keeps_only_the_latest_events() {
+        // ADD V0,1; JP 0x200
+        let mut vm = vm_running(&[0x70, 0x01, 0x12, 0x00]);
+        vm.add_register_watch(Register::V(0));
+        vm.step(2 * 300).unwrap();
+        let events = vm.take_events();
+        assert_eq!(events.len(), EVENT_CAPACITY);
+        assert_eq!(
+            events.last(),
+            Some(&Chip8Event::RegisterChange { pc: 0x200, opcode: 0x7001, register: Register::V(0), old: 43, value: 44 })
+        );
+    }
+
+    #[test]
+    fn lists_the_registers_for_the_overlay() {
+        // LD VA,0x2B; LD I,0x345; LD DT,VA; CALL 0x20A; .. ; LD VF,1
+        let mut vm = vm_running(&[0x6A, 0x2B, 0xA3, 0x45, 0xFA, 0x15, 0x22, 0x0A, 0x00, 0x00, 0x6F, 0x01]);
+        vm.step(5).unwrap();
+        vm.tick_timers();
+        assert_eq!(
+            debug_lines(&vm),
+            [
+                "PC 020C I 0345 SP 1",
+                "DT 2A ST 00",
+                "V0 00 V1 00 V2 00 V3 00",
+                "V4 00 V5 00 V6 00 V7 00",
+                "V8 00 V9 00 VA 2B VB 00",
+                "VC 00 VD 00 VE 00 VF 01",
+                "IPS 300",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_debugger_can_set_registers() {
+        // ADD V3,1
+        let mut vm = vm_running(&[0x73, 0x01, 0x73, 0x01]);
+        vm.set_register(3, 0xFE);
+        vm.set_index(0x345);
+        vm.run_cycle().unwrap();
+        assert_eq!((vm.V[3], vm.index()), (0xFF, 0x345));
+        // Back to the first ADD, and wrapped into memory
+        vm.set_pc(0x200);
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.V[3], 0);
+        vm.set_pc(0x1200);
+        assert_eq!(vm.pc(), 0x200);
+    }
+
+    #[test]
+    fn dumps_memory_as_hex() {
+        let mut vm = vm();
+        for (i, byte) in vm.memory[0x300..0x320].iter_mut().enumerate() {
+            *byte = (i as u8).wrapping_mul(0x11);
+        }
+        assert_eq!(
+            vm.hexdump(0x304, 20),
+            "0x304: 44 55 66 77 88 99 AA BB CC DD EE FF 10 21 32 43\n0x314: 54 65 76 87\n"
+        );
+        assert_eq!(vm.hexdump(0x300, 0), "");
+
+        // Cut short at the end of memory, rather than wrapping around
+        vm.memory[0xFFC..].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(vm.hexdump(0xFFC, 0x40), "0xffc: DE AD BE EF\n");
+        assert_eq!(vm.hexdump(0xFFFF, 0xFFFF), "");
+    }
+
+    #[test]
+    fn traces_each_instruction() {
+        // LD VA,0x02; LD DT,VA
+        let mut vm = vm_running(&[0x6A, 0x02, 0xFA, 0x15]);
+        let zeros = ["00"; 16].join(" ");
+        assert_eq!(
+            vm.trace_line(),
+            format!("      0 0x200: 6A02  LD VA,0x02    | V=[{}] I=0000 SP=0 DT=0", zeros)
+        );
+        vm.run_cycle().unwrap();
+        let registers = "00 00 00 00 00 00 00 00 00 00 02 00 00 00 00 00";
+        assert_eq!(
+            vm.trace_line(),
+            format!("      1 0x202: FA15  LD DT,VA      | V=[{}] I=0000 SP=0 DT=0", registers)
+        );
+        vm.run_cycle().unwrap();
+        assert!(vm.trace_line().ends_with("I=0000 SP=0 DT=2"));
+    }
+
+    #[test]
+    fn font_points_at_the_a_glyph() {
+        // LD V5,0xA; LD F,V5
+        let mut vm = vm_running(&[0x65, 0x0A, 0xF5, 0x29]);
+        vm.run_cycle().unwrap();
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.I, 0x50 + 0xA * 5);
+        assert_eq!(vm.dump_memory(vm.I, 5), [0xF0, 0x90, 0xF0, 0x90, 0x90]);
+    }
+
+    #[test]
+    fn font_uses_a_custom_fontset() {
+        let mut vm = vm();
+        let font: Vec<u8> = (0..80).collect();
+        vm.load_fontset(&font).unwrap();
+        vm.V[2] = 0xC;
+        execute(&mut vm, 0xF229);
+        assert_eq!(vm.dump_memory(vm.I, 5), [60, 61, 62, 63, 64]);
+
+        // Shorter fonts keep the rest of the built-in one
+        let mut vm = vm_with(Quirks::default());
+        vm.load_fontset(&[0xFF; 5]).unwrap();
+        vm.V[2] = 1;
+        execute(&mut vm, 0xF229);
+        assert_eq!(vm.dump_memory(vm.I - 5, 10), [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x20, 0x60, 0x20, 0x20, 0x70]);
+
+        assert!(matches!(vm.load_fontset(&[0; 81]), Err(Error::FontTooBig { size: 81, max: 80 })));
+    }
+
+    #[test]
+    fn big_font_uses_a_custom_fontset() {
+        let mut vm = vm();
+        let font: Vec<u8> = (0..160).collect();
+        vm.load_big_fontset(&font).unwrap();
+        // Past 9, up to F
+        vm.V[2] = 0xF;
+        execute(&mut vm, 0xF230);
+        assert_eq!(vm.dump_memory(vm.I, 10), font[150..]);
+        assert!(matches!(vm.load_big_fontset(&[0; 161]), Err(Error::FontTooBig { size: 161, max: 160 })));
+    }
+
+    #[test]
+    fn runs_off_the_end_of_memory() {
+        let mut vm = vm();
+        // ADD V0,1 at 0xFFE, wrapping around to JP 0xFFE at 0
+        vm.memory[0xFFE..].copy_from_slice(&[0x70, 0x01]);
+        vm.memory[..2].copy_from_slice(&[0x1F, 0xFE]);
+        vm.pc = 0xFFE;
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.V[0], 1);
+        assert_eq!(vm.pc, 0);
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.pc, 0xFFE);
+
+        // An opcode split across the end and the start
+        vm.memory[0xFFF] = 0x12;
+        vm.pc = 0xFFF;
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.opcode, 0x121F);
+    }
+
+    #[test]
+    fn jumps_with_v0_wrap_around_memory() {
+        let mut vm = vm();
+        // B FFF with V0 = 0xFF lands on 0x10FE, past the end of classic memory
+        vm.V[0] = 0xFF;
+        vm.execute(Instruction::JumpV0(0xFFF)).unwrap();
+        assert_eq!(vm.pc(), 0x0FE);
+    }
+
+    #[test]
+    fn bcd_near_the_top_of_memory() {
+        let mut vm = vm();
+        vm.V[0] = 123;
+        vm.I = 0xFFD;
+        execute(&mut vm, 0xF033);
+        assert_eq!(vm.dump_memory(0xFFD, 3), [1, 2, 3]);
+
+        // The last digit would land past the end
+        vm.I = 0xFFE;
+        vm.V[0] = 255;
+        let pc = vm.pc as usize;
+        vm.memory[pc..pc + 2].copy_from_slice(&[0xF0, 0x33]);
+        let result = vm.run_cycle();
+        assert!(matches!(result, Err(Error::MemoryOutOfBounds { addr: 0xFFE })));
+        assert_eq!(vm.dump_memory(0xFFD, 3), [1, 2, 3]);
+        assert_eq!(vm.memory[0], 0);
+    }
+
+    #[test]
+    fn sprites_and_audio_patterns_near_the_top_of_memory() {
+        let mut vm = vm();
+        vm.I = 0xFFC;
+        vm.execute(Instruction::Draw { x: 0, y: 0, n: 4 }).unwrap();
+        // The last row would come from past the end
+        let result = vm.execute(Instruction::Draw { x: 0, y: 0, n: 5 });
+        assert!(matches!(result, Err(Error::MemoryOutOfBounds { addr: 0xFFC })));
+
+        let mut vm = xo_vm();
+        let top = vm.memory.len() - 8;
+        vm.I = top as u16;
+        let result = vm.execute(Instruction::Audio);
+        assert!(matches!(result, Err(Error::MemoryOutOfBounds { addr }) if addr as usize == top));
+    }
+
+    #[test]
+    fn halts_on_a_self_jump() {
+        // LD V0,1; JP 0x202
+        let rom = Cartridge::from_bytes(&[0x60, 0x01, 0x12, 0x02]).unwrap();
+        let mut vm = VirtualMachine::builder().halt_on_spin(true).build().unwrap();
+        vm.load_rom(&rom).unwrap();
+        vm.run_cycle().unwrap();
+        assert!(!vm.is_halted());
+        vm.run_cycle().unwrap();
+        assert!(vm.is_halted());
+        assert!(vm.is_spinning());
+        assert_eq!(vm.pc, 0x202);
+        // Nothing runs anymore
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.stats.instructions, 2);
+        // It stays spinning if the jump is overwritten, and across snapshots
+        vm.memory[0x202..0x204].copy_from_slice(&[0x00, 0xE0]);
+        assert!(vm.is_spinning());
+        let state = vm.snapshot();
+        let mut restored = VirtualMachine::new();
+        restored.restore(&state);
+        assert!(restored.is_spinning());
+
+        // Without the option, it spins on
+        let mut vm = vm_running(&[0x60, 0x01, 0x12, 0x02]);
+        for _ in 0..3 {
+            vm.run_cycle().unwrap();
+        }
+        assert!(!vm.is_halted());
+        assert_eq!(vm.stats.instructions, 3);
+    }
+
+    #[test]
+    fn other_jumps_do_not_halt() {
+        // JP 0x204; CLS; JP 0x202
+        let rom = Cartridge::from_bytes(&[0x12, 0x04, 0x00, 0xE0, 0x12, 0x02]).unwrap();
+        let mut vm = VirtualMachine::builder().halt_on_spin(true).build().unwrap();
+        vm.load_rom(&rom).unwrap();
+        for _ in 0..5 {
+            vm.run_cycle().unwrap();
+        }
+        assert!(!vm.is_halted());
+    }
+
+    #[test]
+    fn counts_the_instructions_run() {
+        // ADD V0,1; JP 0x200
+        let mut vm = vm_running(&[0x70, 0x01, 0x12, 0x00]);
+        assert_eq!(vm.cycle_count(), 0);
+        vm.step(5).unwrap();
+        assert_eq!(vm.cycle_count(), 5);
+        assert_eq!(vm.V[0], 3);
+        vm.step(0).unwrap();
+        assert_eq!(vm.cycle_count(), 5);
+
+        // Stops at the unknown opcode, which is counted
+        let mut vm = vm_running(&[0x70, 0x01, 0x80, 0x08, 0x70, 0x01]);
+        assert!(vm.step(3).is_err());
+        assert_eq!(vm.cycle_count(), 2);
+        assert_eq!(vm.V[0], 1);
+    }
+
+    #[test]
+    fn load_and_add_immediate() {
+        let mut vm = vm();
+        vm.execute(Instruction::LoadImm { x: 1, nn: 0xFE }).unwrap();
+        vm.execute(Instruction::AddImm { x: 1, nn: 5 }).unwrap();
+        // Wraps around, leaving VF alone
+        assert_eq!(vm.V[1], 0x03);
+        assert_eq!(vm.V[0xF], 0);
+        assert_eq!(vm.pc, 0x204);
+    }
+
+    #[test]
+    fn logical_operations() {
+        let mut vm = vm();
+        vm.V[0] = 0b1100;
+        vm.V[1] = 0b1010;
+        vm.execute(Instruction::Or { x: 0, y: 1 }).unwrap();
+        assert_eq!(vm.V[0], 0b1110);
+        vm.execute(Instruction::And { x: 0, y: 1 }).unwrap();
+        assert_eq!(vm.V[0], 0b1010);
+        vm.execute(Instruction::Xor { x: 0, y: 1 }).unwrap();
+        assert_eq!(vm.V[0], 0);
+        vm.execute(Instruction::Move { x: 2, y: 1 }).unwrap();
+        assert_eq!(vm.V[2], 0b1010);
+    }
+
+    #[test]
+    fn logical_operations_reset_vf_with_the_quirk() {
+        let mut vm = vm_with(Quirks { vf_reset: true, ..Quirks::default() });
+        vm.V[0xF] = 1;
+        vm.execute(Instruction::Or { x: 0, y: 1 }).unwrap();
+        assert_eq!(vm.V[0xF], 0);
+    }
+
+    #[test]
+    fn add_sets_the_carry() {
+        let mut vm = vm();
+        vm.V[0] = 0xFF;
+        vm.V[1] = 0x02;
+        vm.execute(Instruction::Add { x: 0, y: 1 }).unwrap();
+        assert_eq!((vm.V[0], vm.V[0xF]), (0x01, 1));
+        vm.execute(Instruction::Add { x: 0, y: 1 }).unwrap();
+        assert_eq!((vm.V[0], vm.V[0xF]), (0x03, 0));
+    }
+
+    #[test]
+    fn add_into_vf_keeps_the_flag() {
+        let mut vm = vm();
+        vm.V[0xF] = 0xFF;
+        vm.V[1] = 0x01;
+        vm.execute(Instruction::Add { x: 0xF, y: 1 }).unwrap();
+        assert_eq!(vm.V[0xF], 1);
+        vm.V[0xF] = 0x01;
+        vm.execute(Instruction::Add { x: 0xF, y: 1 }).unwrap();
+        assert_eq!(vm.V[0xF], 0);
+    }
+
+    #[test]
+    fn sub_sets_vf_when_there_is_no_borrow() {
+        let mut vm = vm();
+        vm.V[0] = 5;
+        vm.V[1] = 3;
+        vm.execute(Instruction::Sub { x: 0, y: 1 }).unwrap();
+        assert_eq!((vm.V[0], vm.V[0xF]), (2, 1));
+        vm.V[0] = 3;
+        vm.execute(Instruction::Sub { x: 0, y: 1 }).unwrap();
+        assert_eq!((vm.V[0], vm.V[0xF]), (0, 1));
+        vm.V[0] = 2;
+        vm.execute(Instruction::Sub { x: 0, y: 1 }).unwrap();
+        assert_eq!((vm.V[0], vm.V[0xF]), (0xFF, 0));
+    }
+
+    #[test]
+    fn sub_into_vf_keeps_the_flag() {
+        let mut vm = vm();
+        vm.V[0xF] = 5;
+        vm.V[1] = 3;
+        vm.execute(Instruction::Sub { x: 0xF, y: 1 }).unwrap();
+        assert_eq!(vm.V[0xF], 1);
+        vm.V[0xF] = 2;
+        vm.execute(Instruction::SubN { x: 0xF, y: 1 }).unwrap();
+        assert_eq!(vm.V[0xF], 1);
+    }
+
+    #[test]
+    fn subn_sets_vf_when_there_is_no_borrow() {
+        let mut vm = vm();
+        vm.V[0] = 3;
+        vm.V[1] = 5;
+        vm.execute(Instruction::SubN { x: 0, y: 1 }).unwrap();
+        assert_eq!((vm.V[0], vm.V[0xF]), (2, 1));
+        vm.V[0] = 5;
+        vm.execute(Instruction::SubN { x: 0, y: 1 }).unwrap();
+        assert_eq!((vm.V[0], vm.V[0xF]), (0, 1));
+        vm.V[0] = 6;
+        vm.execute(Instruction::SubN { x: 0, y: 1 }).unwrap();
+        assert_eq!((vm.V[0], vm.V[0xF]), (0xFF, 0));
+    }
+
+    #[test]
+    fn shifts_follow_the_shift_quirk() {
+        let mut vm = vm();
+        vm.V[0] = 0x00;
+        vm.V[1] = 0x81;
+        vm.execute(Instruction::ShiftRight { x: 0, y: 1 }).unwrap();
+        assert_eq!((vm.V[0], vm.V[0xF]), (0x40, 1));
+        vm.execute(Instruction::ShiftLeft { x: 0, y: 1 }).unwrap();
+        assert_eq!((vm.V[0], vm.V[0xF]), (0x02, 1));
+
+        let mut vm = vm_with(Quirks { shift: true, ..Quirks::default() });
+        vm.V[0] = 0x03;
+        vm.V[1] = 0x80;
+        vm.execute(Instruction::ShiftRight { x: 0, y: 1 }).unwrap();
+        assert_eq!((vm.V[0], vm.V[0xF]), (0x01, 1));
+        vm.execute(Instruction::ShiftLeft { x: 0, y: 1 }).unwrap();
+        assert_eq!((vm.V[0], vm.V[0xF]), (0x02, 0));
+    }
+
+    #[test]
+    fn skips() {
+        let mut vm = vm();
+        vm.V[0] = 7;
+        vm.V[1] = 7;
+        vm.execute(Instruction::SkipEqImm { x: 0, nn: 7 }).unwrap();
+        assert_eq!(vm.pc, 0x204);
+        vm.execute(Instruction::SkipNeImm { x: 0, nn: 7 }).unwrap();
+        assert_eq!(vm.pc, 0x206);
+        vm.execute(Instruction::SkipEqReg { x: 0, y: 1 }).unwrap();
+        assert_eq!(vm.pc, 0x20A);
+        vm.execute(Instruction::SkipNeReg { x: 0, y: 1 }).unwrap();
+        assert_eq!(vm.pc, 0x20C);
+    }
+
+    #[test]
+    fn skip_key_reads_the_low_nibble_of_vx() {
+        let mut vm = vm();
+        vm.V[0] = 0x13;
+        vm.set_key(3, true);
+        vm.execute(Instruction::SkipKey { x: 0 }).unwrap();
+        assert_eq!(vm.pc, 0x204);
+        vm.execute(Instruction::SkipNotKey { x: 0 }).unwrap();
+        assert_eq!(vm.pc, 0x206);
+        vm.V[0] = 0xFF;
+        vm.execute(Instruction::SkipKey { x: 0 }).unwrap();
+        assert_eq!(vm.pc, 0x208);
+        vm.execute(Instruction::SkipNotKey { x: 0 }).unwrap();
+        assert_eq!(vm.pc, 0x20C);
+    }
+
+    #[test]
+    fn jumps_calls_and_returns() {
+        let mut vm = vm();
+        vm.execute(Instruction::Call(0x300)).unwrap();
+        assert_eq!((vm.pc, vm.sp), (0x300, 1));
+        vm.execute(Instruction::Return).unwrap();
+        assert_eq!((vm.pc, vm.sp), (0x202, 0));
+        vm.execute(Instruction::Jump(0x400)).unwrap();
+        assert_eq!(vm.pc, 0x400);
+        vm.V[0] = 0x10;
+        vm.execute(Instruction::JumpV0(0x300)).unwrap();
+        assert_eq!(vm.pc, 0x310);
+    }
+
+    #[test]
+    fn stack_faults() {
+        let mut vm = vm();
+        assert!(matches!(vm.execute(Instruction::Return), Err(Error::StackUnderflow { pc: 0x200 })));
+        for _ in 0..16 {
+            vm.execute(Instruction::Call(0x200)).unwrap();
+        }
+        assert!(matches!(vm.execute(Instruction::Call(0x200)), Err(Error::StackOverflow { pc: 0x200 })));
+    }
+
+    #[test]
+    fn traces_the_calls_back_from_a_fault() {
+        // Three calls deep, each skipping a word of padding, down to an unknown opcode
+        let rom = [0x22, 0x04, 0x00, 0x00, 0x22, 0x08, 0x00, 0x00, 0x22, 0x0C, 0x00, 0x00, 0x01, 0x23];
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&Cartridge::from_bytes(&rom).unwrap()).unwrap();
+        assert!(vm.call_stack().is_empty());
+        let err = vm.step(4).unwrap_err();
+        assert!(matches!(err, Error::UnknownOpcode { opcode: 0x0123, pc: 0x20C }));
+        assert_eq!(vm.call_stack(), [0x200, 0x204, 0x208]);
+
+        let lines: Vec<String> = vm.backtrace().lines().map(String::from).collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("  at   0x20c: 0123  "), "{}", lines[0]);
+        assert_eq!(lines[1], format!("  from 0x208: 220C  {}", disasm::mnemonic(0x220C)));
+        assert_eq!(lines[2], format!("  from 0x204: 2208  {}", disasm::mnemonic(0x2208)));
+        assert_eq!(lines[3], format!("  from 0x200: 2204  {}", disasm::mnemonic(0x2204)));
+    }
+
+    #[test]
+    fn timers_and_index() {
+        let mut vm = vm();
+        vm.V[0] = 30;
+        vm.execute(Instruction::SetDelay { x: 0 }).unwrap();
+        vm.execute(Instruction::SetSound { x: 0 }).unwrap();
+        vm.tick_timers();
+        vm.execute(Instruction::GetDelay { x: 1 }).unwrap();
+        assert_eq!(vm.V[1], 29);
+        assert_eq!(vm.sound_timer, 29);
+        vm.execute(Instruction::LoadI(0x300)).unwrap();
+        vm.execute(Instruction::AddI { x: 0 }).unwrap();
+        assert_eq!(vm.I, 0x31E);
+    }
+
+    #[test]
+    fn bcd_store_and_load() {
+        let mut vm = vm();
+        vm.I = 0x300;
+        vm.V[0] = 254;
+        vm.execute(Instruction::Bcd { x: 0 }).unwrap();
+        assert_eq!(vm.dump_memory(0x300, 3), [2, 5, 4]);
+        vm.execute(Instruction::Load { x: 2 }).unwrap();
+        assert_eq!(vm.V[..3], [2, 5, 4]);
+        vm.execute(Instruction::Store { x: 2 }).unwrap();
+        // I only moves with the memory quirk
+        assert_eq!(vm.I, 0x300);
+
+        let mut vm = vm_with(Quirks { memory: true, ..Quirks::default() });
+        vm.I = 0x300;
+        vm.execute(Instruction::Store { x: 2 }).unwrap();
+        assert_eq!(vm.I, 0x303);
+    }
+
+    #[test]
+    fn draw_reports_collisions() {
+        let mut vm = vm();
+        vm.I = FONTSET_ADDRESS as u16;
+        vm.execute(Instruction::Draw { x: 0, y: 0, n: 5 }).unwrap();
+        assert_eq!(vm.V[0xF], 0);
+        assert_eq!(vm.framebuffer().pixel(0, 0), 1);
+        vm.vblank = true;
+        vm.execute(Instruction::Draw { x: 0, y: 0, n: 5 }).unwrap();
+        assert_eq!(vm.V[0xF], 1);
+        assert_eq!(vm.framebuffer().pixel(0, 0), 0);
+    }
+
+    #[test]
+    fn xo_chip_instructions_are_unknown_elsewhere() {
+        let mut vm = vm();
+        let result = vm.execute(Instruction::Plane(2));
+        assert!(matches!(result, Err(Error::UnknownOpcode { opcode: 0xF201, pc: 0x200 })));
+    }
+
+    #[test]
+    fn lookalikes_of_other_opcodes_are_unknown() {
+        let mut vm = vm();
+        // 0x01E0 is a 0NNN machine language routine, which ends like 00E0 but isn't CLS
+        let result = vm.execute(instruction::decode(0x01E0));
+        assert!(matches!(result, Err(Error::UnknownOpcode { opcode: 0x01E0, pc: 0x200 })));
+        // Only 9XY0 is an instruction
+        vm.V[1] = 1;
+        let result = vm.execute(instruction::decode(0x9011));
+        assert!(matches!(result, Err(Error::UnknownOpcode { opcode: 0x9011, pc: 0x200 })));
+    }
+}
@@ -1,85 +1,875 @@
 
-use clap::{Arg, App, AppSettings};
-use std::io::{Error, ErrorKind};
+use clap::{Arg, App, AppSettings, ArgMatches};
+use lascaoito::audio::{Beep, BUZZER_FREQUENCY, DEFAULT_VOLUME, MAX_VOLUME};
+use lascaoito::error::Error;
+use std::time::Duration;
+use lascaoito::rewind::DEFAULT_REWIND_DEPTH;
+use lascaoito::quirks::Quirks;
+use lascaoito::config::FileConfig;
+use lascaoito::debugger::OpcodePattern;
+use lascaoito::profile::Profile;
+use lascaoito::chip8::{Register, VirtualMachineBuilder, PROGRAM_START};
+use lascaoito::render::{self, DEFAULT_PALETTE, MAX_GHOSTING};
+use lascaoito::timing::{DEFAULT_TURBO, FRAME_RATE};
+use std::path::PathBuf;
+use crate::keyboard::KeyMap;
+use crate::gamepad::ButtonMap;
+
+/// The longest delay accepted, in milliseconds. Any longer and the emulator
+/// would run at less than a frame per second, which no game is playable at.
+const MAX_DELAY: f64 = 1000.0;
+
+/// Where the execution trace gets written to
+#[derive(Debug)]
+pub enum TraceOutput {
+	Stderr,
+	File(String),
+}
+
+const QUIRKS_HELP: &str = "Activate CPU quirks. May improve compatibility in some ROMs. \
+Takes a comma-separated list such as --quirks=shift,clip. A bare --quirks enables shift and memory.";
+
+/// The options taken on the command line
+pub fn app() -> App<'static, 'static> {
+	App::new("lascaoito")
+		.settings(&[AppSettings::ColoredHelp])
+		.after_help("If you find any bugs, please file an issue at github.com/vrmiguel/lascaoito.")
+		.version_message("Display version information.")
+		.version("0.1.0")
+		.author("Vinicius R. Miguel <vinicius.miguel at unifesp.br>")
+		.about("CHIP-8 emulator")
+		.arg(
+			Arg::with_name("scale")
+				.short("s")
+				.long("scale")
+				.value_name("SCALE")
+				.help("Sets the video scale factor, from 1 to 255. Defaults to 5.")
+				.takes_value(true))
+		.arg(
+			Arg::with_name("filename")
+				.value_name("ROM")
+				.help("The ROM file to be played. It may be gzipped, or inside a zip archive, written as pack.zip:NAME.ch8 to pick one of several ROMs in it. Given a directory, the ROMs in it are listed to pick from with 2, 8 and 5; F1 goes back to the list.")
+				.required_unless_one(&["test", "assemble"])
+				.takes_value(true))
+		.arg(
+			Arg::with_name("test")
+				.long("test")
+				.help("Runs a built-in test ROM instead of a ROM file. It draws two checkered bars with \"C8\" between them, then beeps.")
+				.conflicts_with("filename"))
+		.arg(
+			Arg::with_name("disassemble")
+				.long("disassemble")
+				.help("Print the ROM as an assembly listing, one instruction per line starting from the load address, then exit.")
+				.conflicts_with_all(&["test", "headless", "tui"]))
+		.arg(
+			Arg::with_name("rom-info")
+				.long("rom-info")
+				.help("Print the size, SHA-1 and likely machine of the ROM, whether it fits in classic memory, and its first instructions, then exit.")
+				.conflicts_with_all(&["headless", "tui", "disassemble"]))
+		.arg(
+			Arg::with_name("assemble")
+				.long("assemble")
+				.value_names(&["SOURCE", "ROM"])
+				.number_of_values(2)
+				.help("Assemble SOURCE into the ROM file ROM, then exit. The source has one instruction per line, written as --disassemble shows them, with labels such as \"loop:\", DB and DW for raw bytes and words, and comments after \";\".")
+				.conflicts_with_all(&["filename", "test", "headless", "tui", "disassemble", "rom-info"]))
+		.arg(
+			Arg::with_name("opcode-stats")
+				.long("opcode-stats")
+				.help("Count how many times each kind of instruction runs, and show the counts along with the other statistics."))
+		.arg(
+			Arg::with_name("quirks")
+				.short("q")
+				.long("quirks")
+				.value_name("QUIRKS")
+				.min_values(0)
+				.require_equals(true)
+				.help(QUIRKS_HELP))
+		.arg(
+			Arg::with_name("delay")
+				.short("d")
+				.long("delay")
+				.help("Extra time to wait after each frame, in milliseconds, up to 1000. Fractions such as 0.5 are accepted. Defaults to 0. Frames still run as many instructions (see --cycles), so everything slows down: a delay of 16.7 halves the speed, timers included.")
+				.value_name("DELAY"))
+		.arg(
+			Arg::with_name("fg")
+				.long("fg")
+				.help("Color of lit pixels, in hex, such as #00fa00 (the default).")
+				.value_name("COLOR"))
+		.arg(
+			Arg::with_name("bg")
+				.long("bg")
+				.help("Color of unlit pixels, in hex, such as #000000 (the default).")
+				.value_name("COLOR"))
+		.arg(
+			Arg::with_name("theme")
+				.long("theme")
+				.help("Colors of unlit and lit pixels, by name: classic-green (the default), amber, white or gameboy. --bg and --fg take precedence.")
+				.value_name("THEME"))
+		.arg(
+			Arg::with_name("invert")
+				.long("invert")
+				.help("Swap the colors of unlit and lit pixels, for ROMs made for the opposite polarity. F6 toggles this while running."))
+		.arg(
+			Arg::with_name("ghosting")
+				.long("ghosting")
+				.help("Fade unlit pixels out over this many frames, from 1 to 60, instead of at once. Smooths out the flicker of games that redraw their sprites on every frame.")
+				.value_name("FRAMES"))
+		.arg(
+			Arg::with_name("fg2")
+				.long("fg2")
+				.help("Color of pixels lit only on the second XO-CHIP plane, in hex. Defaults to #0064fa.")
+				.value_name("COLOR"))
+		.arg(
+			Arg::with_name("blend")
+				.long("blend")
+				.help("Color of pixels lit on both XO-CHIP planes, in hex. Defaults to #fafafa.")
+				.value_name("COLOR"))
+		.arg(
+			Arg::with_name("profile")
+				.long("profile")
+				.help("The platform to emulate: classic (CHIP-8 and SUPER-CHIP, the default) or xochip, whose programs have 64KB of memory and can draw in four colors on two planes.")
+				.value_name("PROFILE"))
+		.arg(
+			Arg::with_name("load-address")
+				.long("load-address")
+				.help("Where to load the ROM and start running it, such as 0x600 for ETI-660 programs. Defaults to 0x200.")
+				.value_name("ADDRESS"))
+		.arg(
+			Arg::with_name("xo-chip")
+				.long("xo-chip")
+				.help("Same as --profile=xochip.")
+				.conflicts_with("profile"))
+		.arg(
+			Arg::with_name("ips")
+				.long("ips")
+				.help("Instructions executed per second. Classic games expect around 600 (the default), SCHIP ones 1800 or more.")
+				.value_name("IPS"))
+		.arg(
+			Arg::with_name("cycles")
+				.long("cycles")
+				.alias("cycles-per-frame")
+				.help("Instructions executed per 60Hz frame. Another way to set --ips: 10 cycles per frame are 600 instructions per second.")
+				.value_name("CYCLES")
+				.conflicts_with("ips"))
+		.arg(
+			Arg::with_name("turbo")
+				.long("turbo")
+				.help("How many times faster to run while Tab is held. Defaults to 8.")
+				.value_name("FACTOR"))
+		.arg(
+			Arg::with_name("rom-db")
+				.long("rom-db")
+				.help("A TOML file with the quirks and speed to use for specific ROMs, keyed by SHA-1.")
+				.value_name("FILE"))
+		.arg(
+			Arg::with_name("rewind-depth")
+				.long("rewind-depth")
+				.help("How many frames can be rewound by holding Backspace. Zero disables rewinding.")
+				.value_name("FRAMES"))
+		.arg(
+			Arg::with_name("trace")
+				.long("trace")
+				.help("Log every executed instruction, numbered and disassembled, along with the register state. Writes to stderr unless a file is given with --trace=FILE.")
+				.value_name("FILE")
+				.min_values(0)
+				.require_equals(true))
+		.arg(
+			Arg::with_name("config")
+				.long("config")
+				.help("Read settings from this TOML file instead of ~/.config/lascaoito.toml. Options given on the command line take precedence.")
+				.value_name("FILE"))
+		.arg(
+			Arg::with_name("headless")
+				.long("headless")
+				.help("Run without a window, then print the screen as text along with the registers. Requires --cycles.")
+				.requires("cycles"))
+		.arg(
+			Arg::with_name("cycles")
+				.long("cycles")
+				.help("How many instructions to run in headless mode, unless the program ends earlier.")
+				.value_name("CYCLES")
+				.requires("headless"))
+		.arg(
+			Arg::with_name("tui")
+				.long("tui")
+				.help("Draw the screen in the terminal instead of a window, such as over SSH. There's no keypad input; Ctrl-C quits.")
+				.conflicts_with("headless"))
+		.arg(
+			Arg::with_name("mute")
+				.long("mute")
+				.help("Play no sound."))
+		.arg(
+			Arg::with_name("beep-freq")
+				.long("beep-freq")
+				.help("Pitch of the buzzer, in Hz. Defaults to 440.")
+				.value_name("HZ"))
+		.arg(
+			Arg::with_name("beep-volume")
+				.long("beep-volume")
+				.help("Loudness of the sound, from 0 to 100. Defaults to 20; larger values are taken as 100.")
+				.value_name("VOLUME"))
+		.arg(
+			Arg::with_name("halt-on-spin")
+				.long("halt-on-spin")
+				.help("Stop running the program once it jumps to itself, as many programs do when they end."))
+		.arg(
+			Arg::with_name("keys")
+				.long("keys")
+				.help("The keyboard keys for the hex keys 0 to F, as a comma-separated list of 16 SDL key names. Defaults to X,1,2,3,Q,W,E,A,S,D,Z,C,4,R,F,V, the left side of a QWERTY keyboard.")
+				.value_name("KEYS"))
+		.arg(
+			Arg::with_name("buttons")
+				.long("buttons")
+				.help("The game controller buttons for the hex keys 0 to F, as a comma-separated list of 16 SDL button names, empty for none. Defaults to ,,,,b,dpup,a,dpleft,dpdown,dpright,,,,,, which puts the D-pad on 5, 7, 8 and 9.")
+				.value_name("BUTTONS"))
+		.arg(
+			Arg::with_name("break")
+				.long("break")
+				.help("Pause before running the instruction at any of these addresses, given as a comma-separated list such as 0x200,0x2a4, or by repeating --break. The registers are printed on stderr. In headless mode, stop there.")
+				.value_name("ADDRESSES")
+				.multiple(true)
+				.number_of_values(1))
+		.arg(
+			Arg::with_name("break-op")
+				.long("break-op")
+				.help("Pause before running any instruction matching one of these opcode patterns, wherever it is, such as DXYN to stop at the next sprite drawn or F?0A at the next keypad wait. X, Y, N, K and ? match any nibble. A mask and value in hex, such as F0FF/F00A, are accepted too. Takes a comma-separated list, or repeat --break-op.")
+				.value_name("PATTERNS")
+				.multiple(true)
+				.number_of_values(1))
+		.arg(
+			Arg::with_name("watch")
+				.long("watch")
+				.help("Pause after any instruction that writes to one of these addresses, given as a comma-separated list such as 0x300,0x301, or by repeating --watch. Ranges such as 0x300..0x30f include both ends. Each write is printed on stderr with the value it replaced. In headless mode, stop there.")
+				.value_name("ADDRESSES")
+				.multiple(true)
+				.number_of_values(1))
+		.arg(
+			Arg::with_name("watch-reg")
+				.long("watch-reg")
+				.help("Pause after any instruction that changes one of these registers, given as a comma-separated list such as V3,VA,I, or by repeating --watch-reg. Each change is printed on stderr with the value it replaced. In headless mode, stop there.")
+				.value_name("REGISTERS")
+				.multiple(true)
+				.number_of_values(1))
+		.arg(
+			Arg::with_name("debug")
+				.long("debug")
+				.help("Read debugger commands from stdin while running, such as break 0x220, step, regs or mem 0x300 32. Type help for the list.")
+				.conflicts_with_all(&["headless", "tui"]))
+		.arg(
+			Arg::with_name("dump")
+				.long("dump")
+				.help("Print this range of memory as a hex dump once the program stops, such as 0x200..0x23f, which includes both ends. Mostly useful with --headless.")
+				.value_name("RANGE"))
+		.arg(
+			Arg::with_name("seed")
+				.long("seed")
+				.help("Seeds the random number generator, making runs reproducible.")
+				.value_name("SEED"))
+		.arg(
+			Arg::with_name("record")
+				.long("record")
+				.help("Record the keypad of every frame to this file, along with the seed, to be replayed with --playback. Stepping, rewinding, fast-forwarding and resetting are off meanwhile.")
+				.value_name("FILE")
+				.conflicts_with_all(&["headless", "tui", "playback"]))
+		.arg(
+			Arg::with_name("playback")
+				.long("playback")
+				.help("Replay the keypad recorded with --record, running exactly as the recorded run did. The keyboard takes over once the recording ends.")
+				.value_name("FILE")
+				.conflicts_with_all(&["headless", "tui", "seed"]))
+		// .arg(
+		// 	Arg::with_name("verbosity")
+		// 		.short("v")
+		// 		.long("verbose")
+		// 		.help("Show additional information when running."))
+}
 
 #[derive(Debug)]
 pub struct Config {
 	pub scale: u8,
-	pub quirks: bool,
-	pub delay: u8,
-	pub filename: String,
+	// Quirks and IPS are only set if given explicitly,
+	// so that the ROM database can fill them in otherwise
+	pub quirks: Option<Quirks>,
+	pub delay: Duration,
+	// None when running the built-in test ROM, with --test
+	pub filename: Option<String>,
+	pub rewind_depth: usize,
+	pub trace: Option<TraceOutput>,
+	pub seed: Option<u64>,
+	// Where to write the keypad of every frame to, given with --record
+	pub record: Option<String>,
+	// The recording to replay, given with --playback
+	pub playback: Option<String>,
+	// With --headless, how many instructions to run without opening a window
+	pub headless_cycles: Option<u64>,
+	// Whether to draw the screen in the terminal instead of a window, given with --tui
+	pub tui: bool,
+	// Whether to print the ROM as an assembly listing instead of running it, given with --disassemble
+	pub disassemble: bool,
+	// Whether to print what's known about the ROM instead of running it, given with --rom-info
+	pub rom_info: bool,
+	// The assembly source and the ROM to write it to, given with --assemble
+	pub assemble: Option<(String, String)>,
+	// Whether to count how many times each kind of instruction runs, given with --opcode-stats
+	pub opcode_stats: bool,
+	pub ips: Option<u32>,
+	// Speed multiplier while Tab is held
+	pub turbo: u32,
+	pub rom_db: Option<String>,
+	// Pixel colors, indexed by the planes the pixel is set on
+	pub palette: [[u8; 3]; 4],
+	// Whether unlit and lit pixels swap colors, given with --invert
+	pub invert: bool,
+	// How many frames unlit pixels take to fade out, given with --ghosting
+	pub ghosting: Option<u8>,
+	pub profile: Profile,
+	// Where the ROM is loaded and starts running
+	pub load_address: u16,
+	pub mute: bool,
+	// Pitch and loudness of the buzzer
+	pub beep: Beep,
+	// Whether a program jumping to itself is treated as ended
+	pub halt_on_spin: bool,
+	pub keymap: KeyMap,
+	pub buttons: ButtonMap,
+	// Addresses to pause at, given with --break
+	pub breakpoints: Vec<u16>,
+	// Opcodes to pause at, given with --break-op
+	pub opcode_breakpoints: Vec<OpcodePattern>,
+	// Addresses to pause after writing to, given with --watch
+	pub watchpoints: Vec<u16>,
+	// Registers to pause after changing, given with --watch-reg
+	pub register_watches: Vec<Register>,
+	// Whether to read debugger commands from stdin, given with --debug
+	pub debug: bool,
+	// Where the memory printed on exit starts and how long it is, given with --dump
+	pub dump: Option<(u16, usize)>,
 	// pub verbose: bool
 }
 
 impl Config {
-	pub fn new () -> Result<Config, std::io::Error> {
-		let matches = App::new("lascaoito")
-			.settings(&[AppSettings::ColoredHelp])
-            .after_help("If you find any bugs, please file an issue at github.com/vrmiguel/lascaoito.")
-            .version_message("Display version information.")
-			.version("0.1.0")
-			.author("Vinicius R. Miguel <vinicius.miguel at unifesp.br>")
-			.about("CHIP-8 emulator")
-			.arg(
-				Arg::with_name("scale")
-					.short("s")
-					.long("scale")
-					.value_name("SCALE")
-					.help("Sets the video scale factor.")
-					.takes_value(true))
-			.arg(
-				Arg::with_name("filename")
-					.value_name("ROM")
-					.help("The ROM file to be played.")
-					.required(true)
-					.takes_value(true))
-			.arg(
-				Arg::with_name("quirks")
-					.short("q")
-					.long("quirks")
-					.help("Activate CPU quirks. May improve compatibility in some ROMs."))
-			.arg(
-				Arg::with_name("delay")
-					.short("d")
-					.long("delay")
-					.help("The time between cycles, in milliseconds. Usually between 0 and 10.")
-					.value_name("DELAY"))
-			// .arg(
-			// 	Arg::with_name("verbosity")
-			// 		.short("v")
-			// 		.long("verbose")
-			// 		.help("Show additional information when running."))
-			.get_matches();
-
-		// This .unwrap() will always be Ok since filename is a required argument
-		let rom_filename = matches.value_of("filename").unwrap();
+	pub fn new () -> Result<Config, Error> {
+		let matches = app().get_matches();
+
+		// A missing default config file is fine, but not one asked for explicitly
+		let file = match matches.value_of("config") {
+			Some(path) => FileConfig::load(&PathBuf::from(path))?,
+			None => FileConfig::load_default()?
+		};
+
+		Config::merge(&matches, file)
+	}
+
+	/// The options given on the command line, taking precedence over the settings of the configuration `file`
+	pub fn merge(matches: &ArgMatches, file: FileConfig) -> Result<Config, Error> {
+		// Only missing with --test
+		let rom_filename = matches.value_of("filename").map(str::to_string);
 		
-		let cycle_delay = matches.value_of("delay").unwrap_or("1");
-		let cycle_delay = cycle_delay.parse::<u8>();
-		if cycle_delay.is_err() {
-			return Err(Error::new(ErrorKind::Other, "invalid argument passed on to -d/--delay."));
-		}
-		let cycle_delay = cycle_delay.unwrap();
 
-		let scale_factor = matches.value_of("scale").unwrap_or("5");
-		let scale_factor = scale_factor.parse::<u8>();
-		if scale_factor.is_err() {
-			return Err(Error::new(ErrorKind::Other, "invalid argument passed on to -s/--scale."));
+		let cycle_delay = match matches.value_of("delay") {
+			Some(delay) => delay.parse::<f64>().map_err(|_| invalid("-d/--delay", delay))?,
+			None => file.delay.unwrap_or(0.0)
+		};
+		// The delay is in milliseconds and can't be negative
+		if !(0.0..=MAX_DELAY).contains(&cycle_delay) {
+			return Err(invalid("-d/--delay", cycle_delay));
 		}
-		let scale_factor = scale_factor.unwrap();
+		let cycle_delay = Duration::from_secs_f64(cycle_delay / 1000.0);
+
+		let scale_factor = match matches.value_of("scale") {
+			Some(scale) => scale.parse::<u8>().map_err(|_| invalid("-s/--scale", scale))?,
+			None => file.scale.unwrap_or(5)
+		};
 		if scale_factor == 0 {
-			return Err(Error::new(ErrorKind::Other, "scale_factor must be bigger than zero."));
+			return Err(invalid("-s/--scale", scale_factor));
+		}
+
+		let cycles_to_ips = |cycles: u32| match cycles.checked_mul(FRAME_RATE) {
+			Some(ips) if ips > 0 => Ok(ips),
+			_ => Err(invalid("--cycles", cycles))
+		};
+		let ips = match matches.value_of("ips") {
+			Some(ips) => match ips.parse::<u32>() {
+				Ok(ips) => Some(ips),
+				Err(_) => return Err(invalid("--ips", ips))
+			},
+			None => match matches.value_of("cycles") {
+				Some(cycles) => Some(cycles.parse::<u32>().map_err(|_| invalid("--cycles", cycles)).and_then(cycles_to_ips)?),
+				// In the file too, ips takes precedence over cycles
+				None => match (file.ips, file.cycles) {
+					(Some(ips), _) => Some(ips),
+					(None, Some(cycles)) => Some(cycles_to_ips(cycles)?),
+					(None, None) => None
+				}
+			}
+		};
+		if ips == Some(0) {
+			return Err(invalid("--ips", 0));
+		}
+
+		let turbo = match matches.value_of("turbo") {
+			Some(turbo) => turbo.parse::<u32>().map_err(|_| invalid("--turbo", turbo))?,
+			None => file.turbo.unwrap_or(DEFAULT_TURBO)
+		};
+		if turbo == 0 {
+			return Err(invalid("--turbo", turbo));
+		}
+
+		let rom_db = matches.value_of("rom-db").map(str::to_string).or(file.rom_db);
+
+		let rewind_depth = match matches.value_of("rewind-depth") {
+			Some(depth) => depth.parse::<usize>().map_err(|_| invalid("--rewind-depth", depth))?,
+			None => file.rewind_depth.unwrap_or(DEFAULT_REWIND_DEPTH)
+		};
+
+		let trace = if matches.is_present("trace") {
+			match matches.value_of("trace") {
+				Some(path) => Some(TraceOutput::File(path.to_string())),
+				None => Some(TraceOutput::Stderr)
+			}
+		} else {
+			None
+		};
+
+		let seed = match matches.value_of("seed") {
+			Some(seed) => match seed.parse::<u64>() {
+				Ok(seed) => Some(seed),
+				Err(_) => return Err(invalid("--seed", seed))
+			},
+			None => file.seed
+		};
+
+		let record = matches.value_of("record").map(str::to_string);
+		let playback = matches.value_of("playback").map(str::to_string);
+
+		// A theme sets the unlit and lit colors, then each of --bg, --fg, --fg2 and --blend
+		// replaces one color. What's on the command line is applied last, over the file.
+		let mut palette = DEFAULT_PALETTE;
+		let sources = [
+			(file.theme.as_deref(), [file.bg.as_deref(), file.fg.as_deref(), file.fg2.as_deref(), file.blend.as_deref()]),
+			(matches.value_of("theme"), [matches.value_of("bg"), matches.value_of("fg"), matches.value_of("fg2"), matches.value_of("blend")])
+		];
+		for (theme, colors) in sources {
+			if let Some(theme) = theme {
+				palette[..2].copy_from_slice(&render::theme(theme)?);
+			}
+			for ((color, hex), flag) in palette.iter_mut().zip(colors).zip(["--bg", "--fg", "--fg2", "--blend"]) {
+				if let Some(hex) = hex {
+					*color = parse_color(hex).ok_or_else(|| invalid(flag, hex))?;
+				}
+			}
 		}
 
+		let invert = matches.is_present("invert") || file.invert.unwrap_or(false);
+
+		let ghosting = match matches.value_of("ghosting") {
+			Some(frames) => match frames.parse::<u8>() {
+				Ok(frames) if (1..=MAX_GHOSTING).contains(&frames) => Some(frames),
+				_ => return Err(invalid("--ghosting", frames)),
+			},
+			None => None
+		};
+
+		let profile = if matches.is_present("xo-chip") {
+			Profile::XoChip
+		} else {
+			match matches.value_of("profile").or(file.profile.as_deref()) {
+				Some(name) => Profile::parse(name)?,
+				None => Profile::default()
+			}
+		};
+
+		let headless_cycles = match matches.value_of("cycles") {
+			Some(cycles) => match cycles.parse::<u64>() {
+				Ok(cycles) => Some(cycles),
+				Err(_) => return Err(invalid("--cycles", cycles))
+			},
+			None => None
+		};
+
+		let tui = matches.is_present("tui");
+		let disassemble = matches.is_present("disassemble");
+		let rom_info = matches.is_present("rom-info");
+		let assemble = matches.values_of("assemble").map(|mut files| {
+			let source = files.next().unwrap_or_default().to_string();
+			(source, files.next().unwrap_or_default().to_string())
+		});
+		let opcode_stats = matches.is_present("opcode-stats");
+
+		let load_address = match matches.value_of("load-address") {
+			Some(address) => match parse_address(address) {
+				Some(address) => address,
+				None => return Err(invalid("--load-address", address))
+			},
+			None => file.load_address.unwrap_or(PROGRAM_START)
+		};
+
+		let mute = matches.is_present("mute") || file.mute.unwrap_or(false);
+		let frequency = match matches.value_of("beep-freq") {
+			Some(frequency) => frequency.parse::<f64>().map_err(|_| invalid("--beep-freq", frequency))?,
+			None => file.beep_freq.unwrap_or(BUZZER_FREQUENCY)
+		};
+		if !(frequency > 0.0 && frequency.is_finite()) {
+			return Err(invalid("--beep-freq", frequency));
+		}
+		let volume = match matches.value_of("beep-volume") {
+			Some(volume) => volume.parse::<u32>().map_err(|_| invalid("--beep-volume", volume))?,
+			None => file.beep_volume.unwrap_or(DEFAULT_VOLUME as u32)
+		};
+		let beep = Beep { frequency, volume: volume.min(MAX_VOLUME as u32) as u8 };
+
+		let halt_on_spin = matches.is_present("halt-on-spin") || file.halt_on_spin.unwrap_or(false);
+
+		let keymap = match matches.value_of("keys").or(file.keys.as_deref()) {
+			Some(list) => KeyMap::parse(list)?,
+			None => KeyMap::default()
+		};
+
+		let buttons = match matches.value_of("buttons").or(file.buttons.as_deref()) {
+			Some(list) => ButtonMap::parse(list)?,
+			None => ButtonMap::default()
+		};
+
+		let breakpoints = match matches.values_of("break") {
+			Some(lists) => parse_addresses("--break", lists)?,
+			None => Vec::new()
+		};
+
+		let opcode_breakpoints = match matches.values_of("break-op") {
+			Some(lists) => lists.flat_map(|list| list.split(','))
+				.map(|pattern| OpcodePattern::parse(pattern).ok_or_else(|| invalid("--break-op", pattern.trim())))
+				.collect::<Result<_, _>>()?,
+			None => Vec::new()
+		};
+
+		let watchpoints = match matches.values_of("watch") {
+			Some(lists) => parse_address_ranges("--watch", lists)?,
+			None => Vec::new()
+		};
+
+		let register_watches = match matches.values_of("watch-reg") {
+			Some(lists) => lists.flat_map(|list| list.split(','))
+				.map(|name| Register::parse(name).ok_or_else(|| invalid("--watch-reg", name.trim())))
+				.collect::<Result<_, _>>()?,
+			None => Vec::new()
+		};
+
+		let debug = matches.is_present("debug");
+
+		let dump = match matches.value_of("dump") {
+			Some(range) => match parse_range(range) {
+				Some((start, end)) => Some((start, (end - start) as usize + 1)),
+				None => return Err(invalid("--dump", range))
+			},
+			None => None
+		};
+
 		// let verbose = matches.
 
-		// TODO: read quirks
+		let quirks = if matches.is_present("quirks") {
+			match matches.value_of("quirks") {
+				Some(list) => Some(Quirks::parse(list)?),
+				None => Some(Quirks::default_set())
+			}
+		} else {
+			match file.quirks {
+				Some(list) => Some(Quirks::parse(&list)?),
+				None => None
+			}
+		};
 
 		Ok(Config {
 			delay: cycle_delay, 
 			scale: scale_factor, 
-			filename: rom_filename.to_string(), 
-			quirks: false
+			filename: rom_filename,
+			quirks,
+			rewind_depth,
+			trace,
+			seed,
+			record,
+			playback,
+			headless_cycles,
+			tui,
+			disassemble,
+			rom_info,
+			assemble,
+			opcode_stats,
+			ips,
+			turbo,
+			rom_db,
+			palette,
+			invert,
+			ghosting,
+			profile,
+			load_address,
+			mute,
+			beep,
+			halt_on_spin,
+			keymap,
+			buttons,
+			breakpoints,
+			opcode_breakpoints,
+			watchpoints,
+			register_watches,
+			debug,
+			dump
 		})
 	}
-}
\ No newline at end of file
+}
+
+impl Config {
+	/// A builder for the VM these options describe. The quirks are left to the
+	/// caller, which may take them from the ROM database when not given here.
+	pub fn builder(&self) -> VirtualMachineBuilder {
+		let builder = VirtualMachineBuilder::default()
+			.profile(self.profile)
+			.load_address(self.load_address)
+			.halt_on_spin(self.halt_on_spin);
+		match self.seed {
+			Some(seed) => builder.seed(seed),
+			None => builder
+		}
+	}
+}
+
+/// Parses an address written either in hex, such as `0x600`, or in decimal
+fn parse_address(address: &str) -> Option<u16> {
+	match address.strip_prefix("0x").or_else(|| address.strip_prefix("0X")) {
+		Some(hex) => u16::from_str_radix(hex, 16).ok(),
+		None => address.parse::<u16>().ok()
+	}
+}
+
+/// Parses the comma-separated lists of addresses given to the option `flag`, once or more
+fn parse_addresses<'a>(flag: &'static str, lists: impl Iterator<Item = &'a str>) -> Result<Vec<u16>, Error> {
+	lists.flat_map(|list| list.split(','))
+		.map(str::trim)
+		.map(|address| parse_address(address).ok_or_else(|| invalid(flag, address)))
+		.collect()
+}
+
+/// Like `parse_addresses`, where each address may also be an inclusive range such as `0x300..0x30f`
+fn parse_address_ranges<'a>(flag: &'static str, lists: impl Iterator<Item = &'a str>) -> Result<Vec<u16>, Error> {
+	let mut addresses = Vec::new();
+	for item in lists.flat_map(|list| list.split(',')).map(str::trim) {
+		let (start, end) = match item.split_once("..") {
+			Some(_) => parse_range(item).ok_or_else(|| invalid(flag, item))?,
+			None => {
+				let address = parse_address(item).ok_or_else(|| invalid(flag, item))?;
+				(address, address)
+			}
+		};
+		addresses.extend(start..=end);
+	}
+	Ok(addresses)
+}
+
+/// Parses an inclusive range of addresses such as `0x300..0x30f` into its first and last address
+fn parse_range(range: &str) -> Option<(u16, u16)> {
+	let (start, end) = range.split_once("..")?;
+	let (start, end) = (parse_address(start.trim())?, parse_address(end.trim())?);
+	if start > end {
+		return None;
+	}
+	Some((start, end))
+}
+
+/// Parses an RGB color written as hex, such as `#00fa00` or `00fa00`
+pub fn parse_color(hex: &str) -> Option<[u8; 3]> {
+	let digits = hex.strip_prefix('#').unwrap_or(hex);
+	if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+		return None;
+	}
+	let mut color = [0; 3];
+	for (i, channel) in color.iter_mut().enumerate() {
+		*channel = u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).ok()?;
+	}
+	Some(color)
+}
+
+/// The error for a bad `value` given to the option `flag`
+fn invalid(flag: &'static str, value: impl ToString) -> Error {
+	Error::InvalidArgument { flag, value: value.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The configuration for the command line `args`, after the ROM, with the configuration file `toml`
+	fn config(args: &[&str], toml: &str) -> Result<Config, Error> {
+		let args = ["lascaoito", "game.ch8"].iter().chain(args.iter());
+		let matches = app().get_matches_from_safe(args).unwrap();
+		Config::merge(&matches, FileConfig::parse(toml).unwrap())
+	}
+
+	#[test]
+	fn the_command_line_takes_precedence_over_the_file() {
+		let toml = "scale = 10\ndelay = 2.0\nquirks = \"clip\"\nips = 900\nfg = \"#ffffff\"\n";
+		let cfg = config(&["--scale", "3", "--quirks=shift"], toml).unwrap();
+		assert_eq!(cfg.scale, 3);
+		assert_eq!(cfg.quirks, Some(Quirks::parse("shift").unwrap()));
+		// What the command line leaves out comes from the file
+		assert_eq!(cfg.delay, Duration::from_millis(2));
+		assert_eq!(cfg.ips, Some(900));
+		assert_eq!(cfg.palette[1], [0xFF, 0xFF, 0xFF]);
+		assert_eq!(cfg.filename.as_deref(), Some("game.ch8"));
+	}
+
+	#[test]
+	fn defaults_without_either() {
+		let cfg = config(&[], "").unwrap();
+		assert_eq!(cfg.scale, 5);
+		assert_eq!(cfg.quirks, None);
+		assert_eq!(cfg.ips, None);
+		assert_eq!(cfg.delay, Duration::ZERO);
+	}
+
+	#[test]
+	fn checks_settings_from_the_file_too() {
+		assert!(config(&[], "quirks = \"shfit\"\n").is_err());
+		assert!(config(&[], "delay = -1.0\n").is_err());
+	}
+
+	#[test]
+	fn checks_the_scale_and_delay_bounds() {
+		assert!(matches!(config(&["--scale", "0"], ""), Err(Error::InvalidArgument { flag: "-s/--scale", .. })));
+		assert!(matches!(config(&[], "scale = 0\n"), Err(Error::InvalidArgument { flag: "-s/--scale", .. })));
+		assert!(config(&["--scale", "256"], "").is_err());
+		assert_eq!(config(&["--scale", "255"], "").unwrap().scale, 255);
+
+		assert_eq!(config(&["--delay", "1000"], "").unwrap().delay, Duration::from_secs(1));
+		for delay in &["--delay=1000.5", "--delay=-0.5", "--delay=NaN", "--delay=inf"] {
+			assert!(matches!(config(&[delay], ""), Err(Error::InvalidArgument { flag: "-d/--delay", .. })));
+		}
+	}
+
+	#[test]
+	fn parses_hex_colors() {
+		assert_eq!(parse_color("#ff0000"), Some([0xFF, 0x00, 0x00]));
+		assert_eq!(parse_color("ff0000"), Some([0xFF, 0x00, 0x00]));
+		assert_eq!(parse_color("00FA7b"), Some([0x00, 0xFA, 0x7B]));
+		assert_eq!(parse_color("#ff00"), None);
+		assert_eq!(parse_color("red"), None);
+		assert_eq!(parse_color("#gg0000"), None);
+		assert_eq!(parse_color("+f0000"), None);
+	}
+
+	#[test]
+	fn rejects_bad_colors() {
+		assert!(config(&["--fg", "green"], "").is_err());
+		let cfg = config(&["--bg", "#102030"], "").unwrap();
+		assert_eq!(cfg.palette[0], [0x10, 0x20, 0x30]);
+	}
+
+	#[test]
+	fn cycles_per_frame_set_the_rate() {
+		assert_eq!(config(&["--cycles", "10"], "").unwrap().ips, Some(600));
+		assert_eq!(config(&["--cycles-per-frame", "12"], "").unwrap().ips, Some(720));
+		assert_eq!(config(&["--ips", "700"], "").unwrap().ips, Some(700));
+		assert!(config(&["--cycles", "0"], "").is_err());
+		assert!(config(&["--cycles", "100000000"], "").is_err());
+
+		// From the file, where ips wins over cycles as on the command line
+		assert_eq!(config(&[], "cycles = 15\n").unwrap().ips, Some(900));
+		assert_eq!(config(&[], "cycles = 15\nips = 700\n").unwrap().ips, Some(700));
+		assert_eq!(config(&["--cycles", "10"], "ips = 700\n").unwrap().ips, Some(600));
+		assert!(matches!(config(&[], "cycles = 0\n"), Err(Error::InvalidArgument { flag: "--cycles", .. })));
+	}
+
+	#[test]
+	fn themes_set_the_colors() {
+		let cfg = config(&["--theme", "gameboy"], "").unwrap();
+		assert_eq!(cfg.palette[..2], [[0x0F, 0x38, 0x0F], [0x9B, 0xBC, 0x0F]]);
+		assert_eq!(cfg.palette[2..], DEFAULT_PALETTE[2..]);
+		// Explicit colors take precedence, and the command line over the file
+		let cfg = config(&["--fg", "#102030"], "theme = \"amber\"\n").unwrap();
+		assert_eq!(cfg.palette[..2], [[0, 0, 0], [0x10, 0x20, 0x30]]);
+		let cfg = config(&["--theme", "white"], "theme = \"amber\"\nfg = \"#102030\"\n").unwrap();
+		assert_eq!(cfg.palette[1], [0xFF, 0xFF, 0xFF]);
+		assert!(matches!(config(&["--theme", "sepia"], ""), Err(Error::UnknownTheme(_))));
+	}
+
+	#[test]
+	fn inverts_from_either() {
+		assert!(!config(&[], "").unwrap().invert);
+		assert!(config(&["--invert"], "").unwrap().invert);
+		assert!(config(&[], "invert = true\n").unwrap().invert);
+	}
+
+	#[test]
+	fn parses_the_ghosting() {
+		assert_eq!(config(&[], "").unwrap().ghosting, None);
+		assert_eq!(config(&["--ghosting", "4"], "").unwrap().ghosting, Some(4));
+		assert_eq!(config(&["--ghosting=60"], "").unwrap().ghosting, Some(60));
+		for frames in &["--ghosting=0", "--ghosting=61", "--ghosting=-1", "--ghosting=some"] {
+			assert!(matches!(config(&[frames], ""), Err(Error::InvalidArgument { flag: "--ghosting", .. })));
+		}
+	}
+
+	#[test]
+	fn parses_the_beep() {
+		let cfg = config(&["--beep-freq", "880", "--beep-volume", "60"], "").unwrap();
+		assert_eq!(cfg.beep, Beep { frequency: 880.0, volume: 60 });
+		assert_eq!(config(&[], "").unwrap().beep, Beep::default());
+		// Too loud is turned down, rather than rejected
+		assert_eq!(config(&["--beep-volume", "250"], "").unwrap().beep.volume, MAX_VOLUME);
+		for freq in &["--beep-freq=0", "--beep-freq=-440", "--beep-freq=inf", "--beep-freq=loud"] {
+			assert!(matches!(config(&[freq], ""), Err(Error::InvalidArgument { flag: "--beep-freq", .. })));
+		}
+		assert!(matches!(config(&["--beep-volume=-1"], ""), Err(Error::InvalidArgument { flag: "--beep-volume", .. })));
+	}
+
+	#[test]
+	fn breakpoints_can_be_repeated() {
+		let cfg = config(&["--break", "0x2a4", "--break=0x200,0x20e", "--watch", "0x300"], "").unwrap();
+		assert_eq!(cfg.breakpoints, [0x2A4, 0x200, 0x20E]);
+		assert_eq!(cfg.watchpoints, [0x300]);
+		assert!(matches!(config(&["--break", "0x200", "--break", "there"], ""), Err(Error::InvalidArgument { flag: "--break", .. })));
+	}
+
+	#[test]
+	fn breaks_on_opcode_patterns() {
+		let cfg = config(&["--break-op", "DXYN", "--break-op=F?0A, F0FF/F029", "--break", "0x200"], "").unwrap();
+		let patterns: Vec<String> = cfg.opcode_breakpoints.iter().map(ToString::to_string).collect();
+		assert_eq!(patterns, ["D???", "F?0A", "F?29"]);
+		assert_eq!(cfg.breakpoints, [0x200]);
+		assert!(config(&[], "").unwrap().opcode_breakpoints.is_empty());
+		for pattern in &["--break-op=DXY", "--break-op=DXYN,", "--break-op=0x200"] {
+			assert!(matches!(config(&[pattern], ""), Err(Error::InvalidArgument { flag: "--break-op", .. })));
+		}
+	}
+
+	#[test]
+	fn watches_ranges() {
+		let cfg = config(&["--watch", "0x300..0x303", "--watch=0x310,0x320 .. 0x321"], "").unwrap();
+		assert_eq!(cfg.watchpoints, [0x300, 0x301, 0x302, 0x303, 0x310, 0x320, 0x321]);
+		assert_eq!(config(&["--watch", "0xffff..0xffff"], "").unwrap().watchpoints, [0xFFFF]);
+		for range in &["0x303..0x300", "0x300..", "..0x300", "0x300..0x10000"] {
+			assert!(matches!(config(&["--watch", range], ""), Err(Error::InvalidArgument { flag: "--watch", .. })));
+		}
+		// Only watchpoints take ranges
+		assert!(config(&["--break", "0x200..0x204"], "").is_err());
+	}
+
+	#[test]
+	fn watches_registers() {
+		let cfg = config(&["--watch-reg", "V3, va", "--watch-reg=I"], "").unwrap();
+		assert_eq!(cfg.register_watches, [Register::V(3), Register::V(0xA), Register::I]);
+		assert!(config(&[], "").unwrap().register_watches.is_empty());
+		for list in &["--watch-reg=V10", "--watch-reg=0x300", "--watch-reg=V3,"] {
+			assert!(matches!(config(&[list], ""), Err(Error::InvalidArgument { flag: "--watch-reg", .. })));
+		}
+	}
+
+	#[test]
+	fn parses_the_dump_range() {
+		assert_eq!(config(&["--dump", "0x200..0x23f"], "").unwrap().dump, Some((0x200, 0x40)));
+		assert_eq!(config(&["--dump=0..0xffff"], "").unwrap().dump, Some((0, 0x10000)));
+		assert_eq!(config(&[], "").unwrap().dump, None);
+		for range in &["--dump=0x200", "--dump=0x240..0x200", "--dump=here..there"] {
+			assert!(matches!(config(&[range], ""), Err(Error::InvalidArgument { flag: "--dump", .. })));
+		}
+	}
+}
@@ -0,0 +1,115 @@
+use crate::error::Error;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Settings read from a TOML configuration file, such as
+///
+/// ```toml
+/// scale = 10
+/// delay = 0.5
+/// quirks = "shift,memory"
+/// ips = 900
+/// fg = "#ffffff"
+/// ```
+///
+/// Every setting is optional, and any one given on the command line takes precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FileConfig {
+    pub scale: Option<u8>,
+    // In milliseconds
+    pub delay: Option<f64>,
+    // Comma-separated, as given to --quirks
+    pub quirks: Option<String>,
+    pub ips: Option<u32>,
+    // Instructions per 60Hz frame, as given to --cycles, unless ips is set
+    pub cycles: Option<u32>,
+    // Hex colors, as given to --fg, --bg, --fg2 and --blend
+    pub fg: Option<String>,
+    pub bg: Option<String>,
+    pub fg2: Option<String>,
+    pub blend: Option<String>,
+    // One of the themes given to --theme, which the colors above take precedence over
+    pub theme: Option<String>,
+    pub invert: Option<bool>,
+    // "classic" or "xochip", as given to --profile
+    pub profile: Option<String>,
+    pub turbo: Option<u32>,
+    pub seed: Option<u64>,
+    pub rom_db: Option<String>,
+    pub rewind_depth: Option<usize>,
+    // TOML accepts hex integers, such as 0x600
+    pub load_address: Option<u16>,
+    pub mute: Option<bool>,
+    // In Hz and percent, as given to --beep-freq and --beep-volume
+    pub beep_freq: Option<f64>,
+    pub beep_volume: Option<u32>,
+    pub halt_on_spin: Option<bool>,
+    // Comma-separated SDL key names for the hex keys 0 to F, as given to --keys
+    pub keys: Option<String>,
+    // Comma-separated SDL controller button names for the hex keys 0 to F, as given to --buttons
+    pub buttons: Option<String>,
+}
+
+impl FileConfig {
+    /// Parses the contents of a configuration file
+    pub fn parse(contents: &str) -> Result<FileConfig, Error> {
+        toml::from_str(contents).map_err(Error::InvalidConfig)
+    }
+
+    /// Reads the configuration file at `path`
+    pub fn load(path: &PathBuf) -> Result<FileConfig, Error> {
+        let contents = fs::read_to_string(path)
+            .map_err(|source| Error::ConfigRead { path: path.clone(), source })?;
+        FileConfig::parse(&contents)
+    }
+
+    /// Reads the configuration file at the default location, if there's one.
+    /// A missing file simply means that the defaults are used.
+    pub fn load_default() -> Result<FileConfig, Error> {
+        let path = match default_path() {
+            Some(path) => path,
+            None => return Ok(FileConfig::default()),
+        };
+        match FileConfig::load(&path) {
+            Err(Error::ConfigRead { source, .. }) if source.kind() == ErrorKind::NotFound => {
+                Ok(FileConfig::default())
+            }
+            result => result,
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/lascaoito.toml`, falling back to `~/.config/lascaoito.toml`
+pub fn default_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("lascaoito.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_config_file() {
+        let config = FileConfig::parse("scale = 10\ndelay = 0.5\nquirks = \"shift,memory\"\nfg = \"#ffffff\"\nrewind-depth = 60\n").unwrap();
+        assert_eq!(config.scale, Some(10));
+        assert_eq!(config.delay, Some(0.5));
+        assert_eq!(config.quirks.as_deref(), Some("shift,memory"));
+        assert_eq!(config.fg.as_deref(), Some("#ffffff"));
+        assert_eq!(config.rewind_depth, Some(60));
+        assert_eq!(config.ips, None);
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        assert!(FileConfig::parse("scael = 10\n").is_err());
+        assert!(FileConfig::parse("scale = \"big\"\n").is_err());
+    }
+}
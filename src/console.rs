@@ -0,0 +1,211 @@
+use crate::debugger::{OpcodePattern, StepKind};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// What the debugger console understands, as printed when a command is wrong
+pub const USAGE: &str = "\
+Commands:
+  break ADDR        pause before running the instruction at ADDR (b)
+  delete ADDR       remove the breakpoint at ADDR (d)
+  breakop PATTERN   pause before running any opcode matching PATTERN, such as DXYN or F?0A (bo)
+  deleteop PATTERN  remove the breakpoint on PATTERN (do)
+  pause             pause the emulation
+  step [N]          pause, then run N instructions, 1 by default (s)
+  over              pause, then run the next instruction and any subroutine it calls
+  out               pause, then run until the current subroutine returns
+  continue          resume the emulation (c)
+  regs              print the registers (r)
+  mem ADDR [LEN]    print LEN bytes of memory from ADDR, 16 by default (m)
+  set REG VALUE     set V0 to VF, I or PC to VALUE
+  quit              leave the emulator (q)
+  help              print this list (h)
+Numbers are decimal, or hex with a 0x prefix.";
+
+/// How many bytes `mem` prints when not told
+pub const DEFAULT_MEM_LEN: u16 = 16;
+
+/// A register `set` can change
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Register {
+    V(usize),
+    I,
+    Pc,
+}
+
+/// A command typed into the debugger console
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Break(u16),
+    Delete(u16),
+    BreakOpcode(OpcodePattern),
+    DeleteOpcode(OpcodePattern),
+    Pause,
+    // Run this many instructions, pausing first if running
+    Step(u32),
+    // Step over or out of a subroutine, pausing first if running
+    Finish(StepKind),
+    Continue,
+    Regs,
+    Mem { start: u16, len: u16 },
+    Set(Register, u16),
+    Quit,
+    Help,
+}
+
+/// Parses a line typed into the console, e.g. `break 0x220` or `set v3 0xff`.
+/// On failure, says what's wrong with it.
+pub fn parse(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, args) = match words.split_first() {
+        Some((name, args)) => (name.to_ascii_lowercase(), args),
+        None => return Err("no command given".to_string()),
+    };
+    let command = match (name.as_str(), args) {
+        ("break" | "b", [address]) => Command::Break(number(address)?),
+        ("delete" | "d", [address]) => Command::Delete(number(address)?),
+        ("breakop" | "bo", [pattern]) => Command::BreakOpcode(opcode_pattern(pattern)?),
+        ("deleteop" | "do", [pattern]) => Command::DeleteOpcode(opcode_pattern(pattern)?),
+        ("pause", []) => Command::Pause,
+        ("step" | "s", []) => Command::Step(1),
+        ("step" | "s", [count]) => Command::Step(number(count)? as u32),
+        ("over", []) => Command::Finish(StepKind::Over),
+        ("out", []) => Command::Finish(StepKind::Out),
+        ("continue" | "c", []) => Command::Continue,
+        ("regs" | "r", []) => Command::Regs,
+        ("mem" | "m", [start]) => Command::Mem { start: number(start)?, len: DEFAULT_MEM_LEN },
+        ("mem" | "m", [start, len]) => Command::Mem { start: number(start)?, len: number(len)? },
+        ("set", [register, value]) => {
+            let register = parse_register(register)?;
+            let value = number(value)?;
+            if matches!(register, Register::V(_)) && value > 0xFF {
+                return Err(format!("{} doesn't fit in a register", value));
+            }
+            Command::Set(register, value)
+        }
+        ("quit" | "q", []) => Command::Quit,
+        ("help" | "h", []) => Command::Help,
+        (
+            "break" | "b" | "delete" | "d" | "breakop" | "bo" | "deleteop" | "do" | "pause" | "step" | "s" | "over" | "out" | "continue" | "c" | "regs" | "r" | "mem" | "m"
+            | "set" | "quit" | "q" | "help" | "h",
+            _,
+        ) => return Err(format!("wrong arguments for {}", name)),
+        _ => return Err(format!("unknown command \"{}\"", name)),
+    };
+    Ok(command)
+}
+
+/// Parses a number written either in hex, such as `0x600`, or in decimal
+fn number(text: &str) -> Result<u16, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse::<u16>(),
+    };
+    parsed.map_err(|_| format!("\"{}\" isn't a number from 0 to 0xffff", text))
+}
+
+/// Parses an opcode pattern, see `OpcodePattern::parse`
+fn opcode_pattern(text: &str) -> Result<OpcodePattern, String> {
+    OpcodePattern::parse(text).ok_or_else(|| format!("\"{}\" isn't an opcode pattern such as DXYN or F0FF/F00A", text))
+}
+
+/// Parses `v0` to `vf`, `i` or `pc`, in any case
+fn parse_register(text: &str) -> Result<Register, String> {
+    let lower = text.to_ascii_lowercase();
+    match lower.as_str() {
+        "i" => Ok(Register::I),
+        "pc" => Ok(Register::Pc),
+        _ => match lower.strip_prefix('v').map(|x| usize::from_str_radix(x, 16)) {
+            Some(Ok(x)) if x < 16 => Ok(Register::V(x)),
+            _ => Err(format!("unknown register \"{}\"", text)),
+        },
+    }
+}
+
+/// Reads commands from stdin on a thread of its own, to be taken from the returned
+/// channel between frames. Lines that aren't commands get the usage printed on stdout.
+/// The thread ends with stdin, or once the receiver is dropped.
+#[cfg(feature = "std")]
+pub fn spawn() -> std::sync::mpsc::Receiver<Command> {
+    use std::io::BufRead;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse(&line) {
+                Ok(command) => {
+                    if sender.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(message) => println!("Error: {}\n{}", message, USAGE),
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_command() {
+        let cases = [
+            ("break 0x220", Command::Break(0x220)),
+            ("b 544", Command::Break(0x220)),
+            ("delete 0x220", Command::Delete(0x220)),
+            ("breakop DXYN", Command::BreakOpcode(OpcodePattern::parse("DXYN").unwrap())),
+            ("bo F0FF/F00A", Command::BreakOpcode(OpcodePattern::parse("F?0A").unwrap())),
+            ("deleteop f?0a", Command::DeleteOpcode(OpcodePattern::parse("F?0A").unwrap())),
+            ("pause", Command::Pause),
+            ("step", Command::Step(1)),
+            ("s 10", Command::Step(10)),
+            ("over", Command::Finish(StepKind::Over)),
+            ("out", Command::Finish(StepKind::Out)),
+            ("continue", Command::Continue),
+            ("c", Command::Continue),
+            ("regs", Command::Regs),
+            ("mem 0x300 32", Command::Mem { start: 0x300, len: 32 }),
+            ("m 0x300", Command::Mem { start: 0x300, len: DEFAULT_MEM_LEN }),
+            ("set v3 0xff", Command::Set(Register::V(3), 0xFF)),
+            ("set VF 1", Command::Set(Register::V(15), 1)),
+            ("set i 0x300", Command::Set(Register::I, 0x300)),
+            ("set pc 0x200", Command::Set(Register::Pc, 0x200)),
+            ("quit", Command::Quit),
+            ("help", Command::Help),
+        ];
+        for (line, command) in cases {
+            assert_eq!(parse(line), Ok(command), "{}", line);
+        }
+    }
+
+    #[test]
+    fn ignores_case_and_spacing() {
+        assert_eq!(parse("  BREAK   0X2A4 "), Ok(Command::Break(0x2A4)));
+        assert_eq!(parse("Set\tvA 0x10"), Ok(Command::Set(Register::V(10), 0x10)));
+    }
+
+    #[test]
+    fn says_what_is_wrong() {
+        assert_eq!(parse(""), Err("no command given".to_string()));
+        assert_eq!(parse("jump 0x200"), Err("unknown command \"jump\"".to_string()));
+        assert_eq!(parse("break"), Err("wrong arguments for break".to_string()));
+        assert_eq!(parse("out 2"), Err("wrong arguments for out".to_string()));
+        assert_eq!(parse("regs now"), Err("wrong arguments for regs".to_string()));
+        assert_eq!(parse("mem 0x300 32 1"), Err("wrong arguments for mem".to_string()));
+        assert_eq!(parse("break 0x10000"), Err("\"0x10000\" isn't a number from 0 to 0xffff".to_string()));
+        assert_eq!(parse("step -1"), Err("\"-1\" isn't a number from 0 to 0xffff".to_string()));
+        assert_eq!(parse("set v3 256"), Err("256 doesn't fit in a register".to_string()));
+        assert_eq!(parse("bo D?"), Err("\"D?\" isn't an opcode pattern such as DXYN or F0FF/F00A".to_string()));
+        assert_eq!(parse("deleteop"), Err("wrong arguments for deleteop".to_string()));
+        assert_eq!(parse("set vg 1"), Err("unknown register \"vg\"".to_string()));
+        assert_eq!(parse("set v10 1"), Err("unknown register \"v10\"".to_string()));
+    }
+}
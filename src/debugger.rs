@@ -0,0 +1,307 @@
+use crate::chip8::VirtualMachine;
+use crate::input::InputEvent;
+use core::fmt;
+
+/// How many instructions stepping over or out of a subroutine runs at most,
+/// so that one that never returns doesn't run on forever
+pub const STEP_LIMIT: u32 = 100_000;
+
+/// Whether the emulation runs on its own or waits for the user,
+/// who can still step through it one instruction at a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulatorState {
+    Running,
+    Paused,
+}
+
+impl EmulatorState {
+    /// Follows the state through a frame's `events`: `Pause` toggles it and
+    /// each `Step` while paused asks for one more instruction.
+    /// Returns the state the frame ends in along with how many steps were asked for.
+    pub fn advance(self, events: &[InputEvent]) -> (EmulatorState, u32) {
+        events.iter().fold((self, 0), |(state, steps), event| match (event, state) {
+            (InputEvent::Pause, EmulatorState::Running) => (EmulatorState::Paused, steps),
+            (InputEvent::Pause, EmulatorState::Paused) => (EmulatorState::Running, steps),
+            (InputEvent::Step, EmulatorState::Paused) => (state, steps + 1),
+            _ => (state, steps),
+        })
+    }
+
+    /// How many instructions to run this frame, `budget` being a whole frame's worth
+    /// and `steps` what `advance` returned
+    pub fn cycles(self, budget: u32, steps: u32) -> u32 {
+        match self {
+            EmulatorState::Running => budget,
+            EmulatorState::Paused => steps,
+        }
+    }
+
+    pub fn is_paused(self) -> bool {
+        self == EmulatorState::Paused
+    }
+}
+
+/// Running a whole subroutine at once rather than an instruction at a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepKind {
+    // Run the next instruction, and all of the subroutine if it calls one
+    Over,
+    // Run the rest of the current subroutine
+    Out,
+}
+
+/// Where stepping over or out of a subroutine stops: once the program is back
+/// to a given call depth, tracked by the stack pointer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepTarget {
+    // Stop once the stack pointer is this low
+    depth: u16,
+    // Instructions left before giving up
+    remaining: u32,
+}
+
+/// What became of stepping after an instruction, see `StepTarget::after_cycle`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepProgress {
+    Running,
+    Reached,
+    // The subroutine ran `STEP_LIMIT` instructions without returning
+    GaveUp,
+}
+
+impl StepTarget {
+    /// Steps over the instruction at the program counter: stops right after it,
+    /// or, if it calls a subroutine, once the subroutine has returned
+    pub fn over(vm: &VirtualMachine) -> StepTarget {
+        StepTarget { depth: vm.sp(), remaining: STEP_LIMIT }
+    }
+
+    /// Steps out of the subroutine the program is in: stops once it has returned.
+    /// None outside of any subroutine.
+    pub fn out(vm: &VirtualMachine) -> Option<StepTarget> {
+        let depth = vm.sp().checked_sub(1)?;
+        Some(StepTarget { depth, remaining: STEP_LIMIT })
+    }
+
+    /// `over` or `out`, as `kind` says
+    pub fn new(kind: StepKind, vm: &VirtualMachine) -> Option<StepTarget> {
+        match kind {
+            StepKind::Over => Some(StepTarget::over(vm)),
+            StepKind::Out => StepTarget::out(vm),
+        }
+    }
+
+    /// Gives up after `limit` instructions instead of `STEP_LIMIT`
+    pub fn with_limit(self, limit: u32) -> StepTarget {
+        StepTarget { remaining: limit, ..self }
+    }
+
+    /// To be called after each instruction run while stepping
+    pub fn after_cycle(&mut self, vm: &VirtualMachine) -> StepProgress {
+        self.remaining = self.remaining.saturating_sub(1);
+        if vm.sp() <= self.depth {
+            StepProgress::Reached
+        } else if self.remaining == 0 {
+            StepProgress::GaveUp
+        } else {
+            StepProgress::Running
+        }
+    }
+}
+
+/// Opcodes to break on, whatever their address: those whose bits under `mask` equal `value`,
+/// such as every DXYN for "pause the next time a sprite is drawn"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodePattern {
+    mask: u16,
+    value: u16,
+}
+
+impl OpcodePattern {
+    /// Parses either four nibbles, each a hex digit or a wildcard among X, Y, N, K and ?,
+    /// such as `DXYN` or `F?0A`, or a mask and value in hex, such as `F0FF/F00A`.
+    /// None if it's neither, or the value has bits outside of the mask.
+    pub fn parse(text: &str) -> Option<OpcodePattern> {
+        let text = text.trim();
+        if let Some((mask, value)) = text.split_once('/') {
+            let (mask, value) = (hex(mask)?, hex(value)?);
+            return if value & !mask == 0 { Some(OpcodePattern { mask, value }) } else { None };
+        }
+        if text.chars().count() != 4 {
+            return None;
+        }
+        let mut pattern = OpcodePattern { mask: 0, value: 0 };
+        for c in text.chars() {
+            let (mask, value) = match c.to_digit(16) {
+                Some(digit) => (0xF, digit as u16),
+                None if matches!(c.to_ascii_uppercase(), 'X' | 'Y' | 'N' | 'K' | '?') => (0, 0),
+                None => return None,
+            };
+            pattern.mask = pattern.mask << 4 | mask;
+            pattern.value = pattern.value << 4 | value;
+        }
+        Some(pattern)
+    }
+
+    /// Whether `opcode` is one of those the pattern stands for
+    pub fn matches(self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+/// Parses a number in hex, with or without a 0x prefix
+fn hex(text: &str) -> Option<u16> {
+    let text = text.trim();
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Shown as nibbles, such as `D???`, or as `mask/value` when a mask splits a nibble
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nibbles = (0..4).rev().map(|i| (self.mask >> (i * 4) & 0xF, self.value >> (i * 4) & 0xF));
+        if nibbles.clone().any(|(mask, _)| mask != 0 && mask != 0xF) {
+            return write!(f, "{:04X}/{:04X}", self.mask, self.value);
+        }
+        for (mask, value) in nibbles {
+            if mask == 0 {
+                write!(f, "?")?;
+            } else {
+                write!(f, "{:X}", value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Cartridge;
+    use alloc::string::ToString;
+    use EmulatorState::*;
+
+    // 0x200: CALL 0x206; ADD V0,1; JP 0x204
+    // 0x206: CALL 0x20C; ADD V1,1; RET
+    // 0x20C: ADD V2,1; RET
+    const NESTED: [u8; 16] = [
+        0x22, 0x06, 0x70, 0x01, 0x12, 0x04, 0x22, 0x0C, 0x71, 0x01, 0x00, 0xEE, 0x72, 0x01, 0x00, 0xEE,
+    ];
+
+    fn vm_running(rom: &[u8]) -> VirtualMachine {
+        let mut vm = VirtualMachine::with_seed(0);
+        vm.load_rom(&Cartridge::from_bytes(rom).unwrap()).unwrap();
+        vm
+    }
+
+    /// Runs until the target `aim` picks is reached or given up on,
+    /// returning how many instructions that took
+    fn run_to(vm: &mut VirtualMachine, aim: impl Fn(&VirtualMachine) -> StepTarget) -> (StepProgress, u32) {
+        let mut target = aim(vm);
+        let mut cycles = 0;
+        loop {
+            vm.run_cycle().unwrap();
+            cycles += 1;
+            match target.after_cycle(vm) {
+                StepProgress::Running => {}
+                progress => return (progress, cycles),
+            }
+        }
+    }
+
+    #[test]
+    fn pause_toggles() {
+        assert_eq!(Running.advance(&[InputEvent::Pause]), (Paused, 0));
+        assert_eq!(Paused.advance(&[InputEvent::Pause]), (Running, 0));
+        assert_eq!(Running.advance(&[InputEvent::Pause, InputEvent::Pause]), (Running, 0));
+        // Anything else leaves the state be
+        assert_eq!(Running.advance(&[InputEvent::KeyDown(1), InputEvent::ToggleOverlay]), (Running, 0));
+        assert_eq!(Paused.advance(&[]), (Paused, 0));
+    }
+
+    #[test]
+    fn steps_only_while_paused() {
+        assert_eq!(Running.advance(&[InputEvent::Step]), (Running, 0));
+        assert_eq!(Paused.advance(&[InputEvent::Step]), (Paused, 1));
+        assert_eq!(Paused.advance(&[InputEvent::Step, InputEvent::KeyUp(1), InputEvent::Step]), (Paused, 2));
+        // Pausing and stepping within the same frame
+        assert_eq!(Running.advance(&[InputEvent::Step, InputEvent::Pause, InputEvent::Step]), (Paused, 1));
+    }
+
+    #[test]
+    fn runs_the_budget_or_the_steps() {
+        assert_eq!(Running.cycles(10, 0), 10);
+        assert_eq!(Paused.cycles(10, 0), 0);
+        let (state, steps) = Paused.advance(&[InputEvent::Step]);
+        assert_eq!(state.cycles(10, steps), 1);
+        assert!(state.is_paused());
+        assert!(!Running.is_paused());
+    }
+
+    #[test]
+    fn steps_over_nested_calls() {
+        let mut vm = vm_running(&NESTED);
+        // CALL 0x206 and everything it calls, down to the RET back
+        assert_eq!(run_to(&mut vm, StepTarget::over), (StepProgress::Reached, 6));
+        assert_eq!((vm.pc(), vm.sp()), (0x202, 0));
+        assert!(vm.trace_line().contains("V=[00 01 01 "));
+
+        // Anything but a call is a single step
+        assert_eq!(run_to(&mut vm, StepTarget::over), (StepProgress::Reached, 1));
+        assert_eq!(vm.pc(), 0x204);
+        assert_eq!(run_to(&mut vm, StepTarget::over), (StepProgress::Reached, 1));
+        assert_eq!(vm.pc(), 0x204);
+    }
+
+    #[test]
+    fn steps_out_one_level_at_a_time() {
+        let mut vm = vm_running(&NESTED);
+        assert_eq!(StepTarget::out(&vm), None);
+        // Into the innermost subroutine
+        vm.step(2).unwrap();
+        assert_eq!((vm.pc(), vm.sp()), (0x20C, 2));
+
+        assert_eq!(run_to(&mut vm, |vm| StepTarget::out(vm).unwrap()), (StepProgress::Reached, 2));
+        assert_eq!((vm.pc(), vm.sp()), (0x208, 1));
+        assert_eq!(run_to(&mut vm, |vm| StepTarget::out(vm).unwrap()), (StepProgress::Reached, 2));
+        assert_eq!((vm.pc(), vm.sp()), (0x202, 0));
+        assert_eq!(StepTarget::out(&vm), None);
+    }
+
+    #[test]
+    fn parses_opcode_patterns() {
+        let draw = OpcodePattern::parse("DXYN").unwrap();
+        assert_eq!(draw, OpcodePattern { mask: 0xF000, value: 0xD000 });
+        assert!(draw.matches(0xD015) && draw.matches(0xDFFF) && !draw.matches(0xC015));
+        let key = OpcodePattern::parse("f?0a").unwrap();
+        assert_eq!(key, OpcodePattern { mask: 0xF0FF, value: 0xF00A });
+        assert!(key.matches(0xF30A) && !key.matches(0xF307));
+        assert_eq!(OpcodePattern::parse("00E0"), Some(OpcodePattern { mask: 0xFFFF, value: 0x00E0 }));
+        assert_eq!(OpcodePattern::parse("8XY?"), OpcodePattern::parse("8xyk"));
+        assert_eq!(OpcodePattern::parse("F0FF/F00A"), Some(key));
+        assert_eq!(OpcodePattern::parse("0xF00F/0x8006"), Some(OpcodePattern { mask: 0xF00F, value: 0x8006 }));
+
+        for text in ["DXY", "DXYNN", "GXYN", "D-YN", "", "F0FF/F10A", "F0FF/", "/F00A", "10000/0"] {
+            assert_eq!(OpcodePattern::parse(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn shows_opcode_patterns() {
+        assert_eq!(OpcodePattern::parse("DXYN").unwrap().to_string(), "D???");
+        assert_eq!(OpcodePattern::parse("f?0a").unwrap().to_string(), "F?0A");
+        assert_eq!(OpcodePattern::parse("F00F/8006").unwrap().to_string(), "8??6");
+        assert_eq!(OpcodePattern::parse("F00C/8004").unwrap().to_string(), "F00C/8004");
+    }
+
+    #[test]
+    fn gives_up_on_subroutines_that_never_return() {
+        // CALL 0x204; .. ; JP 0x204
+        let mut vm = vm_running(&[0x22, 0x04, 0x00, 0x00, 0x12, 0x04]);
+        assert_eq!(run_to(&mut vm, |vm| StepTarget::over(vm).with_limit(50)), (StepProgress::GaveUp, 50));
+        assert_eq!((vm.pc(), vm.sp()), (0x204, 1));
+        // Stepping out of it fares no better
+        let out = |vm: &VirtualMachine| StepTarget::out(vm).unwrap().with_limit(10);
+        assert_eq!(run_to(&mut vm, out), (StepProgress::GaveUp, 10));
+    }
+}
@@ -0,0 +1,119 @@
+use crate::instruction;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Formats an opcode as its assembly mnemonic, e.g. `6A02` becomes `LD VA,0x02`.
+/// Words that don't encode any known instruction are shown as raw data.
+/// The operand of the four-byte XO-CHIP F000 NNNN is the word after it, so it isn't shown.
+pub fn mnemonic(opcode: u16) -> String {
+    instruction::decode(opcode).to_string()
+}
+
+/// Lists `bytes`, a program loaded at `start`, one instruction per line with its address,
+/// the raw words and the mnemonic, e.g. `0x200  A22A       LD I,0x22a`.
+/// The word after an XO-CHIP F000 is shown on the same line, as it's the address loaded.
+/// A trailing odd byte is shown as a byte of data.
+pub fn disassemble(start: u16, bytes: &[u8]) -> String {
+    let mut text = String::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let address = start as usize + offset;
+        let rest = &bytes[offset..];
+        let (raw, instruction, length) = match rest {
+            [0xF0, 0x00, high, low, ..] => {
+                let long = u16::from_be_bytes([*high, *low]);
+                (format!("F000 {:04X}", long), format!("LD I,{:#06x}", long), 4)
+            }
+            [high, low, ..] => {
+                let opcode = u16::from_be_bytes([*high, *low]);
+                (format!("{:04X}", opcode), mnemonic(opcode), 2)
+            }
+            [byte] => (format!("{:02X}", byte), format!("DB {:#04x}", byte), 1),
+            [] => break,
+        };
+        text.push_str(&format!("{:#05x}  {:<9}  {}\n", address, raw, instruction));
+        offset += length;
+    }
+    text
+}
+
+/// Formats `bytes`, found in memory at `start`, as a hex dump with 16 bytes per line, e.g.
+/// `0x200: 00 E0 A2 2A 60 0C 61 08 D0 1F 70 09 A2 39 D0 1F`
+pub fn hex_dump(start: u16, bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let address = start as usize + line * 16;
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+        text.push_str(&format!("{:#05x}: {}\n", address, hex.join(" ")));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_one_opcode_of_each_family() {
+        let cases = [
+            (0x00E0, "CLS"),
+            (0x1234, "JP 0x234"),
+            (0x2ABC, "CALL 0xabc"),
+            (0x3A0F, "SE VA,0x0f"),
+            (0x4B10, "SNE VB,0x10"),
+            (0x5120, "SE V1,V2"),
+            (0x6A02, "LD VA,0x02"),
+            (0x7001, "ADD V0,0x01"),
+            (0x8124, "ADD V1,V2"),
+            (0x9340, "SNE V3,V4"),
+            (0xA22A, "LD I,0x22a"),
+            (0xB300, "JP V0,0x300"),
+            (0xC5FF, "RND V5,0xff"),
+            (0xD01F, "DRW V0,V1,0xf"),
+            (0xE79E, "SKP V7"),
+            (0xF833, "LD B,V8"),
+        ];
+        for (opcode, expected) in cases {
+            assert_eq!(mnemonic(opcode), expected, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn formats_unknown_words_as_data() {
+        assert_eq!(mnemonic(0x5001), "DW 0x5001");
+        assert_eq!(mnemonic(0x800F), "DW 0x800f");
+        assert_eq!(mnemonic(0xE000), "DW 0xe000");
+        assert_eq!(mnemonic(0xF0FF), "DW 0xf0ff");
+    }
+
+    #[test]
+    fn lists_a_program() {
+        let listing = disassemble(0x200, &[0x00, 0xE0, 0xA2, 0x2A, 0x80, 0x0F, 0x12, 0x06, 0xFF]);
+        assert_eq!(
+            listing,
+            "0x200  00E0       CLS\n\
+             0x202  A22A       LD I,0x22a\n\
+             0x204  800F       DW 0x800f\n\
+             0x206  1206       JP 0x206\n\
+             0x208  FF         DB 0xff\n"
+        );
+        assert_eq!(disassemble(0x200, &[]), "");
+    }
+
+    #[test]
+    fn lists_long_loads_on_one_line() {
+        let listing = disassemble(0x600, &[0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0]);
+        assert_eq!(listing, "0x600  F000 1234  LD I,0x1234\n0x604  00E0       CLS\n");
+        // Cut short, the address is missing
+        assert_eq!(disassemble(0x200, &[0xF0, 0x00, 0x12]), "0x200  F000       LD I,LONG\n0x202  12         DB 0x12\n");
+    }
+
+    #[test]
+    fn dumps_sixteen_bytes_per_line() {
+        let bytes: Vec<u8> = (0..20).collect();
+        assert_eq!(
+            hex_dump(0x300, &bytes),
+            "0x300: 00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F\n0x310: 10 11 12 13\n"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use crate::quirks::QUIRK_NAMES;
+use crate::render::THEME_NAMES;
+use alloc::string::String;
+use core::fmt;
+
+/// Everything that can go wrong in the emulator, from reading the options
+/// and the ROM to running the program.
+/// With the `std` feature, it converts to and from an `std::io::Error`.
+#[derive(Debug)]
+pub enum Error {
+    // The ROM is bigger than the memory of any machine.
+    // The size isn't known when reading from a stream stops at the limit.
+    RomTooLarge { size: Option<usize>, max: usize },
+    // The ROM has no data at all
+    EmptyRom,
+    // The ROM doesn't fit in the memory of this machine past the address it's loaded at
+    RomDoesNotFit { size: usize, available: usize, address: u16 },
+    // The ROM couldn't be opened or read, from the file at `path` if it came from one
+    #[cfg(feature = "std")]
+    RomRead { path: Option<std::path::PathBuf>, source: std::io::Error },
+    // The zip archive the ROM was to be loaded from has no ROM in it
+    #[cfg(feature = "std")]
+    NoRomInArchive,
+    // The zip archive has several ROMs, and none was picked
+    #[cfg(feature = "std")]
+    SeveralRomsInArchive(Vec<String>),
+    // The member picked out of the zip archive isn't there
+    #[cfg(feature = "std")]
+    RomNotInArchive(String),
+    // A custom font is bigger than the memory set aside for it
+    FontTooBig { size: usize, max: usize },
+    // The load address is past the end of memory
+    LoadAddressOutOfRange { address: u16, memory_size: usize },
+    UnknownQuirk(String),
+    UnknownProfile(String),
+    UnknownTheme(String),
+    // A command-line option, or its counterpart in the configuration file, has a bad value
+    InvalidArgument { flag: &'static str, value: String },
+    // The program ran an opcode no machine knows of
+    UnknownOpcode { opcode: u16, pc: u16 },
+    // The program called a subroutine with all 16 levels of the stack in use
+    StackOverflow { pc: u16 },
+    // The program returned from a subroutine it never called
+    StackUnderflow { pc: u16 },
+    // The assembly source has a mistake on the given line, counting from 1
+    Assembly { line: usize, message: String },
+    // An input recording has a line, counting from 1, that's neither a header nor a frame
+    InvalidRecording { line: usize },
+    // An instruction reading or writing several bytes from `addr` would run past the end of memory
+    MemoryOutOfBounds { addr: u16 },
+    // A keyboard or controller mapping doesn't have one `kind` (keys or buttons) per hex key
+    KeyCount { kind: &'static str, count: usize },
+    UnknownKey(String),
+    UnknownButton(String),
+    // The configuration file at `path` couldn't be read
+    #[cfg(feature = "std")]
+    ConfigRead { path: std::path::PathBuf, source: std::io::Error },
+    #[cfg(feature = "std")]
+    InvalidConfig(toml::de::Error),
+    // The ROM database isn't valid TOML, or isn't a table of entries
+    #[cfg(feature = "std")]
+    InvalidRomDatabase(Option<toml::de::Error>),
+    // The entry for the ROM with this SHA-1 has a `field` of the wrong type or out of range
+    #[cfg(feature = "std")]
+    InvalidRomDatabaseEntry { sha1: String, field: &'static str },
+    // The file at this path doesn't hold the RPL flags saved by an earlier run
+    #[cfg(feature = "std")]
+    InvalidFlagsFile(std::path::PathBuf),
+    #[cfg(feature = "std")]
+    Png(png::EncodingError),
+    // A save state couldn't be written, or read back
+    #[cfg(feature = "serde")]
+    InvalidState(bincode::Error),
+    // Any other failure to read or write a file
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::RomTooLarge { size: Some(size), max } => {
+                write!(f, "the supplied ROM is too big: {} bytes, over the limit of {}.", size, max)
+            }
+            Error::RomTooLarge { size: None, max } => {
+                write!(f, "the supplied ROM is too big: over the limit of {} bytes.", max)
+            }
+            Error::EmptyRom => write!(f, "the supplied ROM is empty."),
+            Error::RomDoesNotFit { size, available, address } => write!(
+                f,
+                "the supplied ROM is too big: {} bytes, but only {} fit in memory past {:#05x}.",
+                size, available, address
+            ),
+            #[cfg(feature = "std")]
+            Error::RomRead { path: Some(path), source } => {
+                write!(f, "could not read the ROM {}: {}.", path.display(), source)
+            }
+            #[cfg(feature = "std")]
+            Error::RomRead { path: None, source } => write!(f, "could not read the ROM: {}.", source),
+            #[cfg(feature = "std")]
+            Error::NoRomInArchive => write!(
+                f,
+                "the archive has no ROM in it, with any of the extensions {}.",
+                crate::rom::ROM_EXTENSIONS.join(", ")
+            ),
+            #[cfg(feature = "std")]
+            Error::SeveralRomsInArchive(names) => write!(
+                f,
+                "the archive has several ROMs, pick one of them with ARCHIVE.zip:NAME: {}.",
+                names.join(", ")
+            ),
+            #[cfg(feature = "std")]
+            Error::RomNotInArchive(name) => write!(f, "there is no {} in the archive.", name),
+            Error::FontTooBig { size, max } => {
+                write!(f, "the font is too big: {} bytes, but only {} fit in the font area.", size, max)
+            }
+            Error::LoadAddressOutOfRange { address, memory_size } => write!(
+                f,
+                "the load address {:#05x} is past the end of the {} bytes of memory.",
+                address, memory_size
+            ),
+            Error::UnknownQuirk(name) => write!(
+                f,
+                "unknown quirk \"{}\". Valid quirks are: {}.",
+                name,
+                QUIRK_NAMES.join(", ")
+            ),
+            Error::UnknownProfile(name) => {
+                write!(f, "unknown profile \"{}\". Valid profiles are: classic, xochip.", name)
+            }
+            Error::UnknownTheme(name) => write!(
+                f,
+                "unknown theme \"{}\". Valid themes are: {}.",
+                name,
+                THEME_NAMES.join(", ")
+            ),
+            Error::InvalidArgument { flag, value } => {
+                write!(f, "invalid argument \"{}\" passed on to {}.", value, flag)
+            }
+            Error::UnknownOpcode { opcode, pc } => write!(f, "unknown opcode {:#06x} at {:#05x}.", opcode, pc),
+            Error::StackOverflow { pc } => write!(f, "stack overflow calling a subroutine at {:#05x}.", pc),
+            Error::StackUnderflow { pc } => write!(f, "returning at {:#05x} with no subroutine to return from.", pc),
+            Error::Assembly { line, message } => write!(f, "line {}: {}.", line, message),
+            Error::InvalidRecording { line } => write!(f, "invalid input recording on line {}.", line),
+            Error::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access from {:#05x} runs past the end of memory.", addr)
+            }
+            Error::KeyCount { kind, count } => {
+                write!(f, "expected 16 {}, for the hex keys 0 to F, but got {}.", kind, count)
+            }
+            Error::UnknownKey(name) => write!(f, "unknown key \"{}\".", name),
+            Error::UnknownButton(name) => write!(f, "unknown controller button \"{}\".", name),
+            #[cfg(feature = "std")]
+            Error::ConfigRead { path, source } => write!(f, "could not read {}: {}.", path.display(), source),
+            #[cfg(feature = "std")]
+            Error::InvalidConfig(err) => write!(f, "invalid configuration file: {}.", err),
+            #[cfg(feature = "std")]
+            Error::InvalidRomDatabase(Some(err)) => write!(f, "invalid ROM database: {}.", err),
+            #[cfg(feature = "std")]
+            Error::InvalidRomDatabase(None) => write!(f, "the ROM database must be a TOML table."),
+            #[cfg(feature = "std")]
+            Error::InvalidRomDatabaseEntry { sha1, field } => {
+                write!(f, "invalid `{}` in ROM database entry {}.", field, sha1)
+            }
+            #[cfg(feature = "std")]
+            Error::InvalidFlagsFile(path) => write!(f, "{} is not a valid RPL flags file.", path.display()),
+            #[cfg(feature = "std")]
+            Error::Png(err) => write!(f, "could not encode the PNG: {}.", err),
+            #[cfg(feature = "serde")]
+            Error::InvalidState(err) => write!(f, "invalid save state: {}.", err),
+            #[cfg(feature = "std")]
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::RomRead { source: err, .. } | Error::ConfigRead { source: err, .. } | Error::Io(err) => {
+                Some(err)
+            }
+            Error::InvalidConfig(err) | Error::InvalidRomDatabase(Some(err)) => Some(err),
+            Error::Png(err) => Some(err),
+            #[cfg(feature = "serde")]
+            Error::InvalidState(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> std::io::Error {
+        std::io::Error::other(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::VirtualMachine;
+    use crate::rom::Cartridge;
+    use alloc::string::ToString;
+
+    #[test]
+    fn too_big_rom() {
+        let result = Cartridge::from_bytes(&vec![0; 0x10000]);
+        assert!(matches!(result, Err(Error::RomTooLarge { size: Some(0x10000), max: 0xFE00 })));
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut vm = VirtualMachine::with_seed(0);
+        vm.load_rom(&Cartridge::from_bytes(&[0x01, 0x23]).unwrap()).unwrap();
+        let err = vm.run_cycle().unwrap_err();
+        assert!(matches!(err, Error::UnknownOpcode { opcode: 0x0123, pc: 0x200 }));
+        assert_eq!(err.to_string(), "unknown opcode 0x0123 at 0x200.");
+    }
+
+    #[test]
+    fn memory_out_of_bounds() {
+        let mut vm = VirtualMachine::with_seed(0);
+        // LD I,0xffe; LD B,V0
+        vm.load_rom(&Cartridge::from_bytes(&[0xAF, 0xFE, 0xF0, 0x33]).unwrap()).unwrap();
+        vm.run_cycle().unwrap();
+        let err = vm.run_cycle().unwrap_err();
+        assert!(matches!(err, Error::MemoryOutOfBounds { addr: 0xFFE }));
+        assert_eq!(vm.pc(), 0x202);
+    }
+
+    #[test]
+    fn messages_start_in_lowercase() {
+        let errors = [
+            Error::EmptyRom,
+            Error::RomTooLarge { size: None, max: 1 },
+            Error::UnknownKey("Foo".to_string()),
+            Error::KeyCount { kind: "keys", count: 3 },
+            Error::MemoryOutOfBounds { addr: 0xFFF },
+        ];
+        for err in &errors {
+            let message = err.to_string();
+            assert!(message.starts_with(char::is_lowercase), "{}", message);
+            assert!(message.ends_with('.'), "{}", message);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use crate::cli::Config;
+use lascaoito::chip8::{VirtualMachine, VirtualMachineBuilder, VmState};
+use lascaoito::error::Error;
+use lascaoito::profile::Profile;
+use lascaoito::rom::Cartridge;
+use lascaoito::romdb::RomDatabase;
+use lascaoito::rpl;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// A ROM along with the settings it runs with, from startup
+/// or from a ROM dropped onto the window
+pub struct Game {
+    pub cart: Cartridge,
+    // Sets up the VM again on every reset
+    pub builder: VirtualMachineBuilder,
+    pub ips: u32,
+    // Where the RPL flags of the ROM are kept, if anywhere
+    flags_path: Option<PathBuf>,
+    // The RPL flags saved by earlier runs of the ROM, all zero if none
+    pub saved_flags: [u8; rpl::RPL_FLAG_COUNT],
+    // Where F5 saves the state of the VM, for ROMs that came from a file
+    state_path: Option<PathBuf>,
+    // The platform the VM emulates, which saved states must have been made on
+    profile: Profile,
+}
+
+impl Game {
+    /// Works out how to run `cart`: explicit command-line options take precedence
+    /// over the settings the ROM database knows to suit it, then come the defaults.
+    /// `path` is where the ROM was read from, if from a file.
+    pub fn new(cart: Cartridge, path: Option<&str>, cfg: &Config, rom_db: &RomDatabase) -> Game {
+        let (quirks, ips) = rom_db.settings(&cart.info, cfg.quirks, cfg.ips);
+        if let Some(settings) = rom_db.lookup(&cart.info) {
+            println!("Recognized {}: using {} at {} instructions per second", settings.name, quirks, ips);
+        }
+
+        // Restore the RPL flags (usually high scores) saved by earlier runs of this ROM
+        let flags_path = rpl::flags_path(&cart.info);
+        let mut saved_flags = [0; rpl::RPL_FLAG_COUNT];
+        if let Some(path) = &flags_path {
+            match rpl::load(path) {
+                Ok(flags) => saved_flags = flags,
+                Err(err) => eprintln!("Error: could not load the RPL flags: {}", err)
+            }
+        }
+
+        // Saves go next to the ROM, e.g. games/PONG.state for games/PONG.ch8 or games/pack.zip:PONG.ch8
+        let state_path = path.map(|path| {
+            let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+            dir.join(format!("{}.state", cart.info.name))
+        });
+
+        Game { cart, builder: cfg.builder().quirks(quirks), ips, flags_path, saved_flags, state_path, profile: cfg.profile }
+    }
+
+    /// Reads the ROM file at `path` and starts it, as `new` and `start` would,
+    /// for a ROM dropped onto the window. Nothing is kept if either fails,
+    /// so the game already running can go on.
+    pub fn load(path: &str, cfg: &Config, rom_db: &RomDatabase) -> Result<(Game, VirtualMachine), Error> {
+        let game = Game::new(Cartridge::from_file(path)?, Some(path), cfg, rom_db);
+        let vm = game.start(cfg, game.saved_flags)?;
+        Ok((game, vm))
+    }
+
+    /// A fresh VM with the ROM loaded, the given RPL flags,
+    /// and the breakpoints, watchpoints and statistics asked for
+    pub fn start(&self, cfg: &Config, rpl_flags: [u8; rpl::RPL_FLAG_COUNT]) -> Result<VirtualMachine, Error> {
+        let mut vm = self.builder.build()?;
+        for &address in &cfg.breakpoints {
+            vm.add_breakpoint(address);
+        }
+        for &pattern in &cfg.opcode_breakpoints {
+            vm.add_opcode_breakpoint(pattern);
+        }
+        for &address in &cfg.watchpoints {
+            vm.add_watchpoint(address);
+        }
+        for &register in &cfg.register_watches {
+            vm.add_register_watch(register);
+        }
+        if cfg.opcode_stats {
+            vm.enable_opcode_stats();
+        }
+        vm.rpl_flags = rpl_flags;
+        vm.load_rom(&self.cart)?;
+        Ok(vm)
+    }
+
+    /// Writes the state of `vm` to the save file of the ROM, returning where it went.
+    /// None if the ROM didn't come from a file, and so has nowhere to save to.
+    pub fn quicksave(&self, vm: &VirtualMachine) -> Result<Option<&Path>, Error> {
+        let path = match &self.state_path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        vm.snapshot().save_to_writer(&self.cart.info.sha1, BufWriter::new(File::create(path)?))?;
+        Ok(Some(path))
+    }
+
+    /// Puts `vm` back in the state last saved by `quicksave`.
+    /// Returns false, leaving `vm` as it was, if nothing was saved yet.
+    /// Fails, also leaving `vm` alone, on states saved from another ROM or profile.
+    pub fn quickload(&self, vm: &mut VirtualMachine) -> Result<bool, Error> {
+        let file = match self.state_path.as_ref().map(File::open) {
+            Some(Ok(file)) => file,
+            Some(Err(err)) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+            _ => return Ok(false),
+        };
+        vm.restore(&VmState::load_from_reader(BufReader::new(file), &self.cart.info.sha1, self.profile)?);
+        Ok(true)
+    }
+
+    /// Saves `flags` for the next runs of the ROM.
+    /// Only ROMs that changed their flags get a file.
+    pub fn save_flags(&self, flags: &[u8; rpl::RPL_FLAG_COUNT]) {
+        if let (Some(path), true) = (&self.flags_path, *flags != self.saved_flags) {
+            if let Err(err) = rpl::save(path, flags) {
+                eprintln!("Error: could not save the RPL flags: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::app;
+    use lascaoito::config::FileConfig;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn swaps_in_a_dropped_rom() {
+        let dir = env::temp_dir().join(format!("lascaoito-game-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("first.ch8");
+        let second = dir.join("second.ch8");
+        let empty = dir.join("empty.ch8");
+        // LD V0,1 and LD V0,2
+        fs::write(&first, [0x60, 0x01]).unwrap();
+        fs::write(&second, [0x60, 0x02]).unwrap();
+        fs::write(&empty, []).unwrap();
+        let (first, second, empty) = (first.to_str().unwrap(), second.to_str().unwrap(), empty.to_str().unwrap());
+
+        let matches = app().get_matches_from_safe(["lascaoito", first]).unwrap();
+        let cfg = Config::merge(&matches, FileConfig::default()).unwrap();
+        let rom_db = RomDatabase::builtin();
+        let (game, mut vm) = Game::load(first, &cfg, &rom_db).unwrap();
+        assert_eq!(game.cart.info.name, "first");
+        vm.run_cycle().unwrap();
+        assert_eq!(vm.pc(), 0x202);
+
+        // A fresh machine for the new ROM
+        let (game, vm) = Game::load(second, &cfg, &rom_db).unwrap();
+        assert_eq!(game.cart.info.name, "second");
+        assert_eq!(vm.pc(), 0x200);
+        assert_eq!(vm.dump_memory(0x200, 2), [0x60, 0x02]);
+        assert_eq!(vm.stats.instructions, 0);
+
+        let missing = Game::load(&format!("{}/missing.ch8", dir.display()), &cfg, &rom_db);
+        let empty = Game::load(empty, &cfg, &rom_db);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(missing, Err(Error::RomRead { path: Some(_), .. })));
+        assert!(matches!(empty, Err(Error::EmptyRom)));
+    }
+
+    #[test]
+    fn quickloads_what_was_quicksaved() {
+        let dir = env::temp_dir().join(format!("lascaoito-quicksave-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("digit.ch8");
+        // LD V0,5; LD F,V0; DRW V0,V0,5; CLS; ADD V1,1; JP 0x208
+        fs::write(&rom, [0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05, 0x00, 0xE0, 0x71, 0x01, 0x12, 0x08]).unwrap();
+        let rom = rom.to_str().unwrap();
+
+        let matches = app().get_matches_from_safe(["lascaoito", rom]).unwrap();
+        let cfg = Config::merge(&matches, FileConfig::default()).unwrap();
+        let (game, mut vm) = Game::load(rom, &cfg, &RomDatabase::builtin()).unwrap();
+        // Nothing to load yet
+        assert!(!game.quickload(&mut vm).unwrap());
+
+        // Saved with the digit on screen, then cleared and counted past
+        for _ in 0..3 {
+            vm.run_cycle().unwrap();
+        }
+        let saved = vm.snapshot();
+        let path = game.quicksave(&vm).unwrap().map(Path::to_path_buf);
+        assert_eq!(path, Some(dir.join("digit.state")));
+        for _ in 0..10 {
+            vm.run_cycle().unwrap();
+        }
+        assert!(vm.snapshot() != saved);
+
+        let loaded = game.quickload(&mut vm);
+        // The same save file, for another ROM of that name
+        let mut other = Cartridge::from_bytes(&[0x12, 0x00]).unwrap();
+        other.info.name = "digit".to_string();
+        let refused = Game::new(other, Some(rom), &cfg, &RomDatabase::builtin()).quickload(&mut vm);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(loaded.unwrap());
+        assert!(matches!(refused, Err(Error::InvalidState(_))));
+        assert!(vm.snapshot() == saved);
+        assert_eq!(vm.pc(), 0x206);
+        // The top of the 5 is back
+        assert!((0..64).any(|x| vm.framebuffer().pixel(x, 5) != 0));
+
+        // The built-in ROMs have nowhere to save to
+        let cart = Cartridge::from_bytes(&[0x12, 0x00]).unwrap();
+        let game = Game::new(cart, None, &cfg, &RomDatabase::builtin());
+        assert_eq!(game.quicksave(&vm).unwrap(), None);
+        assert!(!game.quickload(&mut vm).unwrap());
+    }
+}
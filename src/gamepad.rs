@@ -0,0 +1,118 @@
+use sdl2::controller::{Button, GameController};
+use sdl2::GameControllerSubsystem;
+use lascaoito::error::Error;
+
+/// Which controller button stands for each key of the hex keypad, indexed by hex key.
+/// Keys may be left without a button.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ButtonMap {
+    buttons: [Option<Button>; 16],
+}
+
+impl Default for ButtonMap {
+    /// The D-pad on 5, 7, 8 and 9, which most games move with (W, A, S and D
+    /// on the keyboard), B on 4 and A on 6, which many games act with
+    fn default() -> ButtonMap {
+        let mut buttons = [None; 16];
+        buttons[0x4] = Some(Button::B);
+        buttons[0x5] = Some(Button::DPadUp);
+        buttons[0x6] = Some(Button::A);
+        buttons[0x7] = Some(Button::DPadLeft);
+        buttons[0x8] = Some(Button::DPadDown);
+        buttons[0x9] = Some(Button::DPadRight);
+        ButtonMap { buttons }
+    }
+}
+
+impl ButtonMap {
+    /// Parses a comma-separated list of the 16 SDL button names standing for
+    /// the hex keys 0 to F, in order, where an empty name leaves the key unmapped,
+    /// such as ",,,,b,dpup,a,dpleft,dpdown,dpright,,,,,,"
+    pub fn parse(list: &str) -> Result<ButtonMap, Error> {
+        let names: Vec<&str> = list.split(',').map(str::trim).collect();
+        if names.len() != 16 {
+            return Err(Error::KeyCount { kind: "buttons", count: names.len() });
+        }
+        let mut buttons = [None; 16];
+        for (button, name) in buttons.iter_mut().zip(names) {
+            if name.is_empty() {
+                continue;
+            }
+            *button = Some(Button::from_string(name).ok_or_else(|| Error::UnknownButton(name.to_string()))?);
+        }
+        Ok(ButtonMap { buttons })
+    }
+
+    /// The hex keys that have a button
+    pub fn keys(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..16).filter(move |&key| self.buttons[key as usize].is_some())
+    }
+}
+
+/// The hex key `button` stands for in `mapping`, if any
+pub fn map_button(button: Button, mapping: &ButtonMap) -> Option<u8> {
+    mapping.buttons.iter().position(|&mapped| mapped == Some(button)).map(|key| key as u8)
+}
+
+/// The game controllers currently plugged in.
+/// SDL announces the ones already connected at startup as if they were just plugged in,
+/// so all of them go through `connect`.
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    // SDL only reports the buttons of opened controllers, which close when dropped
+    open: Vec<GameController>,
+}
+
+impl Gamepads {
+    pub fn new(subsystem: GameControllerSubsystem) -> Gamepads {
+        Gamepads { subsystem, open: Vec::new() }
+    }
+
+    /// Opens the controller plugged in as joystick `index`
+    pub fn connect(&mut self, index: u32) {
+        match self.subsystem.open(index) {
+            Ok(controller) => {
+                println!("Connected {}", controller.name());
+                self.open.push(controller);
+            }
+            Err(err) => eprintln!("Error: could not open the controller: {}", err),
+        }
+    }
+
+    /// Closes the controller with joystick instance `id`, once unplugged
+    pub fn disconnect(&mut self, id: u32) {
+        self.open.retain(|controller| controller.instance_id() != id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_the_default_buttons() {
+        let mapping = ButtonMap::default();
+        assert_eq!(map_button(Button::DPadUp, &mapping), Some(0x5));
+        assert_eq!(map_button(Button::DPadLeft, &mapping), Some(0x7));
+        assert_eq!(map_button(Button::DPadDown, &mapping), Some(0x8));
+        assert_eq!(map_button(Button::DPadRight, &mapping), Some(0x9));
+        assert_eq!(map_button(Button::Start, &mapping), None);
+        assert_eq!(mapping.keys().collect::<Vec<u8>>(), [4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn maps_parsed_buttons() {
+        let mapping = ButtonMap::parse("a,,,,,,,,,,,,,,,start").unwrap();
+        assert_eq!(map_button(Button::A, &mapping), Some(0x0));
+        assert_eq!(map_button(Button::Start, &mapping), Some(0xF));
+        assert_eq!(map_button(Button::B, &mapping), None);
+        assert_eq!(mapping.keys().collect::<Vec<u8>>(), [0x0, 0xF]);
+        assert_eq!(ButtonMap::parse(",,,,b,dpup,a,dpleft,dpdown,dpright,,,,,,").unwrap(), ButtonMap::default());
+    }
+
+    #[test]
+    fn rejects_bad_buttons() {
+        assert!(ButtonMap::parse("a,b").is_err());
+        assert!(ButtonMap::parse("a,,,,,,,,,,,,,,,turbo").is_err());
+    }
+}
@@ -1,16 +1,165 @@
-mod chip8;
-mod cli;
-mod sdl2;
+use crate::overlay;
+use lascaoito::chip8::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use lascaoito::render::{self, Framebuffer, Ghosting, RenderError, Renderer};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
 
+// The CHIP-8 has a 64x32 screen. The SUPER-CHIP 128x64 mode
+// is drawn into the same window with pixels half as big.
+const SCREEN_SIZE: (u32, u32) = (64, 32);
 
-
-struct Graphics {
-    config: cli::Config,
-    vm    : chip8::VirtualMachine,
+/// The SDL window the screen of the VM is drawn into.
+/// The screen is kept in a texture, one texel per pixel, which gets stretched
+/// over the window, so only the pixels that changed need to be repainted.
+pub struct Graphics {
+    canvas: Canvas<Window>,
+    texture: Texture,
+    // RGB copy of the texture, where changed pixels are repainted before uploading it
+    texels: Vec<u8>,
+    // Size of the area of the texture in use, as of the last draw
+    screen: (u32, u32),
+    // Title of the window when there's no status to show
+    title: String,
+    // Pixel colors, indexed by the planes the pixel is set on
+    palette: [[u8; 3]; 4],
+    // Whether unset and set pixels swap colors
+    invert: bool,
+    // Set when every pixel must be painted again on the next draw, dirty or not
+    repaint: bool,
+    // Unset pixels fading out rather than vanishing, with --ghosting
+    ghosting: Option<Ghosting>,
+    // Text drawn over the screen, such as the registers of the VM
+    overlay: Option<String>,
 }
 
 impl Graphics {
-    fn new() -> Graphics {
-        
+    /// Opens a window big enough for a 64x32 screen with `scale` x `scale` pixels
+    pub fn new(video_subsystem: &VideoSubsystem, title: String, scale: u8, palette: [[u8; 3]; 4], invert: bool) -> Result<Graphics, String> {
+        let width  = SCREEN_SIZE.0 * (scale as u32);
+        let height = SCREEN_SIZE.1 * (scale as u32);
+
+        let window = video_subsystem.window(&title, width, height)
+            .position_centered()
+            .build()
+            .map_err(|err| err.to_string())?;
+        let mut canvas = window.into_canvas().build().map_err(|err| err.to_string())?;
+        let texture = canvas.texture_creator()
+            .create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
+            .map_err(|err| err.to_string())?;
+
+        canvas.clear();
+        canvas.present();
+
+        Ok(Graphics {
+            canvas,
+            texture,
+            texels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+            screen: SCREEN_SIZE,
+            title,
+            palette,
+            invert,
+            repaint: false,
+            ghosting: None,
+            overlay: None,
+        })
+    }
+
+    /// Changes the title of the window, such as when another ROM is loaded.
+    /// Any status is cleared.
+    pub fn set_title(&mut self, title: String) -> Result<(), String> {
+        self.title = title;
+        self.set_status(None)
+    }
+
+    /// Shows `status`, such as "[PAUSED]", after the title of the window
+    pub fn set_status(&mut self, status: Option<&str>) -> Result<(), String> {
+        let title = match status {
+            Some(status) => format!("{} {}", self.title, status),
+            None => self.title.clone(),
+        };
+        self.canvas.window_mut().set_title(&title).map_err(|err| err.to_string())
+    }
+
+    /// Shows `text` over the screen from the next frame on, or nothing if it's `None`
+    pub fn set_overlay(&mut self, text: Option<String>) {
+        self.overlay = text;
+    }
+
+    /// Swaps the colors of unset and set pixels from the next frame on, or swaps them back
+    pub fn set_invert(&mut self, invert: bool) {
+        self.invert = invert;
+        self.repaint = true;
+    }
+
+    /// Lets unset pixels fade out over `frames` frames from now on
+    pub fn set_ghosting(&mut self, frames: u8) {
+        self.ghosting = Some(Ghosting::new(frames));
+    }
+
+    /// Paints the texel at (x, y) with the color of `planes`
+    fn paint(&mut self, x: usize, y: usize, planes: u8) {
+        let offset = (y * SCREEN_WIDTH + x) * 3;
+        self.texels[offset..offset + 3].copy_from_slice(&render::pixel_color(&self.palette, planes, self.invert));
     }
-}
\ No newline at end of file
+}
+
+impl Renderer for Graphics {
+    fn clear(&mut self) -> Result<(), RenderError> {
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                self.paint(x, y, 0);
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, framebuffer: &Framebuffer) -> Result<(), RenderError> {
+        let screen = (framebuffer.width() as u32, framebuffer.height() as u32);
+        // Switching resolutions repaints everything anyway, as does switching colors
+        let repaint = screen != self.screen || self.repaint;
+        self.screen = screen;
+        self.repaint = false;
+        if let Some(ghosting) = &mut self.ghosting {
+            // Fading pixels change on every frame, so every pixel is painted
+            ghosting.update(framebuffer);
+            for y in 0..framebuffer.height() {
+                for x in 0..framebuffer.width() {
+                    let offset = (y * SCREEN_WIDTH + x) * 3;
+                    let color = ghosting.color(x, y, &self.palette, self.invert);
+                    self.texels[offset..offset + 3].copy_from_slice(&color);
+                }
+            }
+            self.texture.update(None, &self.texels, SCREEN_WIDTH * 3).map_err(|err| err.to_string())?;
+            return Ok(());
+        }
+        for y in 0..framebuffer.height() {
+            for x in 0..framebuffer.width() {
+                if repaint || framebuffer.is_dirty(x, y) {
+                    self.paint(x, y, framebuffer.pixel(x, y));
+                }
+            }
+        }
+        self.texture.update(None, &self.texels, SCREEN_WIDTH * 3).map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    fn present(&mut self) -> Result<(), RenderError> {
+        // The texture is stretched over the whole window, whatever the resolution
+        let (width, height) = self.screen;
+        self.canvas.copy(&self.texture, Rect::new(0, 0, width, height), None)?;
+        if let Some(text) = &self.overlay {
+            // Sized after the window, so that the text stays readable at any scale
+            let (window_width, _) = self.canvas.output_size()?;
+            overlay::draw_text(&mut self.canvas, text, (window_width / 160).max(1))?;
+        }
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn is_animating(&self) -> bool {
+        self.ghosting.as_ref().is_some_and(Ghosting::is_fading)
+    }
+}
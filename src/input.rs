@@ -0,0 +1,163 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Something the user did, already translated from whatever device it came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputEvent {
+    // A key of the hex keypad, 0x0 to 0xF, was pressed or released
+    KeyDown(u8),
+    KeyUp(u8),
+    // Frontend commands
+    Quit,
+    Pause,
+    Step,
+    // Running the next instruction along with any subroutine it calls, or the rest of the current one
+    StepOver,
+    StepOut,
+    Reset,
+    Screenshot,
+    ShowStats,
+    ToggleOverlay,
+    // Swaps the colors of unset and set pixels
+    ToggleInvert,
+    DumpMemory,
+    // The memory around I, to see what the program is about to draw, load or store
+    DumpIndexMemory,
+    // Saving the state of the VM to the ROM's save file, and loading it back
+    QuickSave,
+    QuickLoad,
+    // Rewinding and fast-forwarding last while their keys are held
+    Rewind(bool),
+    Turbo(bool),
+    // A ROM file was dropped onto the window, to be run instead
+    LoadRom(String),
+    // Back to the ROM browser, when started with a directory
+    ShowMenu,
+}
+
+/// What an input source reports once per frame: the hex keypad as it stands,
+/// and everything that happened since the last poll
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyStateSnapshot {
+    // The keys held down, bit n for key n, as given to `VirtualMachine::set_keys`
+    pub keys: u16,
+    // Everything that happened, in order. Key presses and releases are in there
+    // too, for frontends that react to them, such as menus.
+    pub events: Vec<InputEvent>,
+}
+
+impl KeyStateSnapshot {
+    /// Follows `events` on from the keypad `keys` held at the previous poll
+    pub fn new(keys: u16, events: Vec<InputEvent>) -> KeyStateSnapshot {
+        let keys = events.iter().fold(keys, |keys, event| match *event {
+            InputEvent::KeyDown(key) => keys | 1 << (key & 0xF),
+            InputEvent::KeyUp(key) => keys & !(1 << (key & 0xF)),
+            _ => keys,
+        });
+        KeyStateSnapshot { keys, events }
+    }
+}
+
+/// Where the input comes from, such as the SDL keyboard
+pub trait InputSource {
+    /// The keypad and everything that happened since the last call.
+    /// Called once per frame.
+    fn poll(&mut self) -> KeyStateSnapshot;
+}
+
+/// Replays a predefined list of events, one batch per frame,
+/// so that interactive programs can be driven without a keyboard
+pub struct ScriptedInput {
+    frames: VecDeque<Vec<InputEvent>>,
+    // The keys held after the last batch
+    keys: u16,
+}
+
+impl ScriptedInput {
+    /// `frames[n]` holds the events returned by the `n`th call to `poll`.
+    /// Once they run out, no more events happen.
+    pub fn new(frames: Vec<Vec<InputEvent>>) -> ScriptedInput {
+        ScriptedInput { frames: frames.into(), keys: 0 }
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn poll(&mut self) -> KeyStateSnapshot {
+        let snapshot = KeyStateSnapshot::new(self.keys, self.frames.pop_front().unwrap_or_default());
+        self.keys = snapshot.keys;
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::VirtualMachine;
+    use crate::rom::Cartridge;
+
+    #[test]
+    fn replays_one_batch_per_frame() {
+        let mut input = ScriptedInput::new(vec![
+            vec![InputEvent::KeyDown(1), InputEvent::KeyDown(2)],
+            vec![],
+            vec![InputEvent::Quit],
+        ]);
+        assert_eq!(input.poll().events, [InputEvent::KeyDown(1), InputEvent::KeyDown(2)]);
+        assert_eq!(input.poll().events, []);
+        assert_eq!(input.poll().events, [InputEvent::Quit]);
+        assert_eq!(input.poll().events, []);
+    }
+
+    #[test]
+    fn drives_a_program_waiting_for_a_key() {
+        // LD V0,K; LD V1,1; JP 0x204
+        let rom = Cartridge::from_bytes(&[0xF0, 0x0A, 0x61, 0x01, 0x12, 0x04]).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&rom).unwrap();
+        let mut input = ScriptedInput::new(vec![vec![], vec![InputEvent::KeyDown(0xB)], vec![InputEvent::KeyUp(0xB)]]);
+
+        let mut waiting = Vec::new();
+        for _ in 0..3 {
+            vm.set_keys(input.poll().keys);
+            for _ in 0..4 {
+                vm.run_cycle().unwrap();
+            }
+            waiting.push(vm.trace_line().contains(" 0x200:"));
+        }
+        assert_eq!(waiting, [true, false, false]);
+        assert!(vm.trace_line().contains("V=[0B 01 "));
+    }
+
+    #[test]
+    fn keeps_the_keypad_held_across_frames() {
+        let mut input = ScriptedInput::new(vec![
+            vec![InputEvent::KeyDown(0x1), InputEvent::KeyDown(0xF)],
+            vec![InputEvent::Pause],
+            vec![InputEvent::KeyUp(0x1), InputEvent::KeyDown(0x4)],
+            // Pressed and released within the same frame
+            vec![InputEvent::KeyDown(0x2), InputEvent::KeyUp(0x2)],
+        ]);
+        let keys: Vec<u16> = (0..5).map(|_| input.poll().keys).collect();
+        assert_eq!(keys, [0x8002, 0x8002, 0x8010, 0x8010, 0x8010]);
+    }
+
+    #[test]
+    fn drives_the_keypad_once_per_frame() {
+        // SKNP V0; ADD V1,1; JP 0x200, with V0 = 0: counts the cycles key 0 is held
+        let rom = Cartridge::from_bytes(&[0xE0, 0xA1, 0x71, 0x01, 0x12, 0x00]).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&rom).unwrap();
+        let mut input = ScriptedInput::new(vec![vec![], vec![InputEvent::KeyDown(0x0)], vec![], vec![InputEvent::KeyUp(0x0)]]);
+
+        let mut held = Vec::new();
+        for _ in 0..4 {
+            vm.set_keys(input.poll().keys);
+            // Two rounds of the loop
+            vm.step(6).unwrap();
+            held.push(vm.keys());
+        }
+        assert_eq!(held, [0, 1, 1, 0]);
+        assert!(vm.trace_line().contains("V=[00 04 "));
+    }
+}
@@ -0,0 +1,412 @@
+use core::fmt;
+
+/// A decoded CHIP-8 instruction, including the SUPER-CHIP and XO-CHIP extensions.
+/// Registers are given by their number, 0x0 to 0xF for V0 to VF.
+/// Whether an extension instruction can run is up to the machine: see `VirtualMachine::run_cycle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // 0NNN: calls a machine language routine, which no interpreter supports
+    Sys(u16),
+    // 00E0
+    ClearScreen,
+    // 00EE
+    Return,
+    // 00CN (SUPER-CHIP): scrolls the screen down by N pixels
+    ScrollDown(u8),
+    // 00FB (SUPER-CHIP): scrolls the screen right by 4 pixels
+    ScrollRight,
+    // 00FC (SUPER-CHIP): scrolls the screen left by 4 pixels
+    ScrollLeft,
+    // 00FD (SUPER-CHIP): exits the interpreter
+    Exit,
+    // 00FE (SUPER-CHIP): switches to the 64x32 low resolution mode
+    LowRes,
+    // 00FF (SUPER-CHIP): switches to the 128x64 high resolution mode
+    HighRes,
+    // 1NNN
+    Jump(u16),
+    // 2NNN
+    Call(u16),
+    // 3XNN: skips the next instruction if VX == NN
+    SkipEqImm { x: u8, nn: u8 },
+    // 4XNN: skips the next instruction if VX != NN
+    SkipNeImm { x: u8, nn: u8 },
+    // 5XY0: skips the next instruction if VX == VY
+    SkipEqReg { x: u8, y: u8 },
+    // 5XY2 (XO-CHIP): stores VX to VY in memory, starting at I
+    SaveRange { x: u8, y: u8 },
+    // 5XY3 (XO-CHIP): reads VX to VY from memory, starting at I
+    LoadRange { x: u8, y: u8 },
+    // 6XNN: VX = NN
+    LoadImm { x: u8, nn: u8 },
+    // 7XNN: VX += NN, without carry
+    AddImm { x: u8, nn: u8 },
+    // 8XY0 to 8XYE: VX = VY, VX |= VY, VX &= VY, VX ^= VY, VX += VY,
+    // VX -= VY, VX >>= 1, VX = VY - VX and VX <<= 1
+    Move { x: u8, y: u8 },
+    Or { x: u8, y: u8 },
+    And { x: u8, y: u8 },
+    Xor { x: u8, y: u8 },
+    Add { x: u8, y: u8 },
+    Sub { x: u8, y: u8 },
+    ShiftRight { x: u8, y: u8 },
+    SubN { x: u8, y: u8 },
+    ShiftLeft { x: u8, y: u8 },
+    // 9XY0: skips the next instruction if VX != VY
+    SkipNeReg { x: u8, y: u8 },
+    // ANNN: I = NNN
+    LoadI(u16),
+    // BNNN: jumps to NNN + V0
+    JumpV0(u16),
+    // CXNN: VX = a random number & NN
+    Random { x: u8, nn: u8 },
+    // DXYN: draws a sprite N rows tall at (VX, VY)
+    Draw { x: u8, y: u8, n: u8 },
+    // EX9E and EXA1: skip the next instruction if the key VX is, or isn't, pressed
+    SkipKey { x: u8 },
+    SkipNotKey { x: u8 },
+    // F000 NNNN (XO-CHIP): I = NNNN, taken from the word after the instruction
+    LoadILong,
+    // FN01 (XO-CHIP): selects the planes drawn to
+    Plane(u8),
+    // F002 (XO-CHIP): loads the audio pattern from memory at I
+    Audio,
+    // FX3A (XO-CHIP): sets the pitch of the audio pattern to VX
+    Pitch { x: u8 },
+    // FX07: VX = the delay timer
+    GetDelay { x: u8 },
+    // FX0A: waits for a key press, then stores the key in VX
+    WaitKey { x: u8 },
+    // FX15 and FX18: set the delay and sound timers to VX
+    SetDelay { x: u8 },
+    SetSound { x: u8 },
+    // FX1E: I += VX
+    AddI { x: u8 },
+    // FX29: points I at the small font glyph of VX
+    Font { x: u8 },
+    // FX30 (SUPER-CHIP): points I at the large font glyph of VX
+    BigFont { x: u8 },
+    // FX33: stores the decimal digits of VX in memory at I
+    Bcd { x: u8 },
+    // FX55 and FX65: store V0 to VX in memory at I, and read them back
+    Store { x: u8 },
+    Load { x: u8 },
+    // FX75 and FX85 (SUPER-CHIP): save V0 to VX to the RPL flags, and read them back
+    SaveFlags { x: u8 },
+    LoadFlags { x: u8 },
+    // A word that doesn't encode any known instruction
+    Unknown(u16),
+}
+
+/// Decodes an opcode. Words that don't encode any known instruction become `Instruction::Unknown`.
+pub fn decode(opcode: u16) -> Instruction {
+    use Instruction::*;
+
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let nn = (opcode & 0x00FF) as u8;
+    let nnn = opcode & 0x0FFF;
+
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => ClearScreen,
+            0x00EE => Return,
+            0x00C0..=0x00CF => ScrollDown(n),
+            0x00FB => ScrollRight,
+            0x00FC => ScrollLeft,
+            0x00FD => Exit,
+            0x00FE => LowRes,
+            0x00FF => HighRes,
+            _ => Sys(nnn),
+        },
+        0x1000 => Jump(nnn),
+        0x2000 => Call(nnn),
+        0x3000 => SkipEqImm { x, nn },
+        0x4000 => SkipNeImm { x, nn },
+        0x5000 => match n {
+            0x0 => SkipEqReg { x, y },
+            0x2 => SaveRange { x, y },
+            0x3 => LoadRange { x, y },
+            _ => Unknown(opcode),
+        },
+        0x6000 => LoadImm { x, nn },
+        0x7000 => AddImm { x, nn },
+        0x8000 => match n {
+            0x0 => Move { x, y },
+            0x1 => Or { x, y },
+            0x2 => And { x, y },
+            0x3 => Xor { x, y },
+            0x4 => Add { x, y },
+            0x5 => Sub { x, y },
+            0x6 => ShiftRight { x, y },
+            0x7 => SubN { x, y },
+            0xE => ShiftLeft { x, y },
+            _ => Unknown(opcode),
+        },
+        0x9000 if n == 0 => SkipNeReg { x, y },
+        0xA000 => LoadI(nnn),
+        0xB000 => JumpV0(nnn),
+        0xC000 => Random { x, nn },
+        0xD000 => Draw { x, y, n },
+        0xE000 => match nn {
+            0x9E => SkipKey { x },
+            0xA1 => SkipNotKey { x },
+            _ => Unknown(opcode),
+        },
+        0xF000 => match nn {
+            0x00 if x == 0 => LoadILong,
+            0x01 => Plane(x),
+            0x02 if x == 0 => Audio,
+            0x3A => Pitch { x },
+            0x07 => GetDelay { x },
+            0x0A => WaitKey { x },
+            0x15 => SetDelay { x },
+            0x18 => SetSound { x },
+            0x1E => AddI { x },
+            0x29 => Font { x },
+            0x30 => BigFont { x },
+            0x33 => Bcd { x },
+            0x55 => Store { x },
+            0x65 => Load { x },
+            0x75 => SaveFlags { x },
+            0x85 => LoadFlags { x },
+            _ => Unknown(opcode),
+        },
+        _ => Unknown(opcode),
+    }
+}
+
+impl Instruction {
+    /// The opcode pattern of the instruction, such as `8XY4`, with its operands as letters
+    pub fn pattern(self) -> &'static str {
+        use Instruction::*;
+
+        match self {
+            Sys(_) => "0NNN",
+            ClearScreen => "00E0",
+            Return => "00EE",
+            ScrollDown(_) => "00CN",
+            ScrollRight => "00FB",
+            ScrollLeft => "00FC",
+            Exit => "00FD",
+            LowRes => "00FE",
+            HighRes => "00FF",
+            Jump(_) => "1NNN",
+            Call(_) => "2NNN",
+            SkipEqImm { .. } => "3XNN",
+            SkipNeImm { .. } => "4XNN",
+            SkipEqReg { .. } => "5XY0",
+            SaveRange { .. } => "5XY2",
+            LoadRange { .. } => "5XY3",
+            LoadImm { .. } => "6XNN",
+            AddImm { .. } => "7XNN",
+            Move { .. } => "8XY0",
+            Or { .. } => "8XY1",
+            And { .. } => "8XY2",
+            Xor { .. } => "8XY3",
+            Add { .. } => "8XY4",
+            Sub { .. } => "8XY5",
+            ShiftRight { .. } => "8XY6",
+            SubN { .. } => "8XY7",
+            ShiftLeft { .. } => "8XYE",
+            SkipNeReg { .. } => "9XY0",
+            LoadI(_) => "ANNN",
+            JumpV0(_) => "BNNN",
+            Random { .. } => "CXNN",
+            Draw { .. } => "DXYN",
+            SkipKey { .. } => "EX9E",
+            SkipNotKey { .. } => "EXA1",
+            LoadILong => "F000",
+            Plane(_) => "FN01",
+            Audio => "F002",
+            Pitch { .. } => "FX3A",
+            GetDelay { .. } => "FX07",
+            WaitKey { .. } => "FX0A",
+            SetDelay { .. } => "FX15",
+            SetSound { .. } => "FX18",
+            AddI { .. } => "FX1E",
+            Font { .. } => "FX29",
+            BigFont { .. } => "FX30",
+            Bcd { .. } => "FX33",
+            Store { .. } => "FX55",
+            Load { .. } => "FX65",
+            SaveFlags { .. } => "FX75",
+            LoadFlags { .. } => "FX85",
+            Unknown(_) => "unknown",
+        }
+    }
+
+    /// The opcode of the instruction, so that `decode(instruction.encode()) == instruction`.
+    /// Register numbers and operands too big for their field are cut to fit.
+    pub fn encode(self) -> u16 {
+        use Instruction::*;
+
+        // Puts the operands in their fields, under the given leading nibble
+        let xnn = |top: u16, x: u8, nn: u8| top | (x as u16 & 0xF) << 8 | nn as u16;
+        let xyn = |top: u16, x: u8, y: u8, n: u8| xnn(top, x, (y & 0xF) << 4 | n & 0xF);
+
+        match self {
+            Sys(nnn) => nnn & 0x0FFF,
+            ClearScreen => 0x00E0,
+            Return => 0x00EE,
+            ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            LowRes => 0x00FE,
+            HighRes => 0x00FF,
+            Jump(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+            SkipEqImm { x, nn } => xnn(0x3000, x, nn),
+            SkipNeImm { x, nn } => xnn(0x4000, x, nn),
+            SkipEqReg { x, y } => xyn(0x5000, x, y, 0x0),
+            SaveRange { x, y } => xyn(0x5000, x, y, 0x2),
+            LoadRange { x, y } => xyn(0x5000, x, y, 0x3),
+            LoadImm { x, nn } => xnn(0x6000, x, nn),
+            AddImm { x, nn } => xnn(0x7000, x, nn),
+            Move { x, y } => xyn(0x8000, x, y, 0x0),
+            Or { x, y } => xyn(0x8000, x, y, 0x1),
+            And { x, y } => xyn(0x8000, x, y, 0x2),
+            Xor { x, y } => xyn(0x8000, x, y, 0x3),
+            Add { x, y } => xyn(0x8000, x, y, 0x4),
+            Sub { x, y } => xyn(0x8000, x, y, 0x5),
+            ShiftRight { x, y } => xyn(0x8000, x, y, 0x6),
+            SubN { x, y } => xyn(0x8000, x, y, 0x7),
+            ShiftLeft { x, y } => xyn(0x8000, x, y, 0xE),
+            SkipNeReg { x, y } => xyn(0x9000, x, y, 0x0),
+            LoadI(nnn) => 0xA000 | (nnn & 0x0FFF),
+            JumpV0(nnn) => 0xB000 | (nnn & 0x0FFF),
+            Random { x, nn } => xnn(0xC000, x, nn),
+            Draw { x, y, n } => xyn(0xD000, x, y, n),
+            SkipKey { x } => xnn(0xE000, x, 0x9E),
+            SkipNotKey { x } => xnn(0xE000, x, 0xA1),
+            LoadILong => 0xF000,
+            Plane(planes) => xnn(0xF000, planes, 0x01),
+            Audio => 0xF002,
+            Pitch { x } => xnn(0xF000, x, 0x3A),
+            GetDelay { x } => xnn(0xF000, x, 0x07),
+            WaitKey { x } => xnn(0xF000, x, 0x0A),
+            SetDelay { x } => xnn(0xF000, x, 0x15),
+            SetSound { x } => xnn(0xF000, x, 0x18),
+            AddI { x } => xnn(0xF000, x, 0x1E),
+            Font { x } => xnn(0xF000, x, 0x29),
+            BigFont { x } => xnn(0xF000, x, 0x30),
+            Bcd { x } => xnn(0xF000, x, 0x33),
+            Store { x } => xnn(0xF000, x, 0x55),
+            Load { x } => xnn(0xF000, x, 0x65),
+            SaveFlags { x } => xnn(0xF000, x, 0x75),
+            LoadFlags { x } => xnn(0xF000, x, 0x85),
+            Unknown(word) => word,
+        }
+    }
+}
+
+/// Formats the instruction as its assembly mnemonic, e.g. `LD VA,0x02`.
+/// Unknown words are shown as a data directive, e.g. `DW 0x5001`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            Sys(nnn) => write!(f, "SYS {:#05x}", nnn),
+            ClearScreen => write!(f, "CLS"),
+            Return => write!(f, "RET"),
+            ScrollDown(n) => write!(f, "SCD {:#03x}", n),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            LowRes => write!(f, "LOW"),
+            HighRes => write!(f, "HIGH"),
+            Jump(nnn) => write!(f, "JP {:#05x}", nnn),
+            Call(nnn) => write!(f, "CALL {:#05x}", nnn),
+            SkipEqImm { x, nn } => write!(f, "SE V{:X},{:#04x}", x, nn),
+            SkipNeImm { x, nn } => write!(f, "SNE V{:X},{:#04x}", x, nn),
+            SkipEqReg { x, y } => write!(f, "SE V{:X},V{:X}", x, y),
+            SaveRange { x, y } => write!(f, "LD [I],V{:X}-V{:X}", x, y),
+            LoadRange { x, y } => write!(f, "LD V{:X}-V{:X},[I]", x, y),
+            LoadImm { x, nn } => write!(f, "LD V{:X},{:#04x}", x, nn),
+            AddImm { x, nn } => write!(f, "ADD V{:X},{:#04x}", x, nn),
+            Move { x, y } => write!(f, "LD V{:X},V{:X}", x, y),
+            Or { x, y } => write!(f, "OR V{:X},V{:X}", x, y),
+            And { x, y } => write!(f, "AND V{:X},V{:X}", x, y),
+            Xor { x, y } => write!(f, "XOR V{:X},V{:X}", x, y),
+            Add { x, y } => write!(f, "ADD V{:X},V{:X}", x, y),
+            Sub { x, y } => write!(f, "SUB V{:X},V{:X}", x, y),
+            ShiftRight { x, y } => write!(f, "SHR V{:X},V{:X}", x, y),
+            SubN { x, y } => write!(f, "SUBN V{:X},V{:X}", x, y),
+            ShiftLeft { x, y } => write!(f, "SHL V{:X},V{:X}", x, y),
+            SkipNeReg { x, y } => write!(f, "SNE V{:X},V{:X}", x, y),
+            LoadI(nnn) => write!(f, "LD I,{:#05x}", nnn),
+            JumpV0(nnn) => write!(f, "JP V0,{:#05x}", nnn),
+            Random { x, nn } => write!(f, "RND V{:X},{:#04x}", x, nn),
+            Draw { x, y, n } => write!(f, "DRW V{:X},V{:X},{:#03x}", x, y, n),
+            SkipKey { x } => write!(f, "SKP V{:X}", x),
+            SkipNotKey { x } => write!(f, "SKNP V{:X}", x),
+            LoadILong => write!(f, "LD I,LONG"),
+            Plane(planes) => write!(f, "PLANE {}", planes),
+            Audio => write!(f, "AUDIO"),
+            Pitch { x } => write!(f, "PITCH V{:X}", x),
+            GetDelay { x } => write!(f, "LD V{:X},DT", x),
+            WaitKey { x } => write!(f, "LD V{:X},K", x),
+            SetDelay { x } => write!(f, "LD DT,V{:X}", x),
+            SetSound { x } => write!(f, "LD ST,V{:X}", x),
+            AddI { x } => write!(f, "ADD I,V{:X}", x),
+            Font { x } => write!(f, "LD F,V{:X}", x),
+            BigFont { x } => write!(f, "LD HF,V{:X}", x),
+            Bcd { x } => write!(f, "LD B,V{:X}", x),
+            Store { x } => write!(f, "LD [I],V{:X}", x),
+            Load { x } => write!(f, "LD V{:X},[I]", x),
+            SaveFlags { x } => write!(f, "LD R,V{:X}", x),
+            LoadFlags { x } => write!(f, "LD V{:X},R", x),
+            Unknown(word) => write!(f, "DW {:#06x}", word),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn every_opcode_encodes_back() {
+        for opcode in 0..=0xFFFF {
+            assert_eq!(decode(opcode).encode(), opcode, "{:#06x}", opcode);
+        }
+    }
+
+    #[test]
+    fn decodes_the_classic_set() {
+        use Instruction::*;
+
+        assert_eq!(decode(0x00E0), ClearScreen);
+        assert_eq!(decode(0x00EE), Return);
+        assert_eq!(decode(0x0123), Sys(0x123));
+        assert_eq!(decode(0x1234), Jump(0x234));
+        assert_eq!(decode(0x2ABC), Call(0xABC));
+        assert_eq!(decode(0x3A42), SkipEqImm { x: 0xA, nn: 0x42 });
+        assert_eq!(decode(0x5120), SkipEqReg { x: 1, y: 2 });
+        assert_eq!(decode(0x8AB4), Add { x: 0xA, y: 0xB });
+        assert_eq!(decode(0x8ABE), ShiftLeft { x: 0xA, y: 0xB });
+        assert_eq!(decode(0x9120), SkipNeReg { x: 1, y: 2 });
+        assert_eq!(decode(0xD125), Draw { x: 1, y: 2, n: 5 });
+        assert_eq!(decode(0xE3A1), SkipNotKey { x: 3 });
+        assert_eq!(decode(0xF265), Load { x: 2 });
+    }
+
+    #[test]
+    fn keeps_words_it_does_not_know() {
+        for &opcode in &[0x5001, 0x8008, 0x9001, 0xE000, 0xF0FF, 0xF102] {
+            assert_eq!(decode(opcode), Instruction::Unknown(opcode));
+        }
+    }
+
+    #[test]
+    fn formats_mnemonics() {
+        assert_eq!(decode(0x00E0).to_string(), "CLS");
+        assert_eq!(decode(0x1234).to_string(), "JP 0x234");
+        assert_eq!(decode(0x6A02).to_string(), "LD VA,0x02");
+        assert_eq!(decode(0x5001).to_string(), "DW 0x5001");
+    }
+}
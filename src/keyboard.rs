@@ -0,0 +1,176 @@
+use crate::gamepad::{self, ButtonMap, Gamepads};
+use lascaoito::input::{InputEvent, InputSource, KeyStateSnapshot};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::EventPump;
+use lascaoito::error::Error;
+
+/// Which keyboard key stands for each key of the hex keypad, indexed by hex key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyMap {
+    keys: [Keycode; 16],
+}
+
+impl Default for KeyMap {
+    /// The hex keypad laid over the left side of a QWERTY keyboard:
+    ///
+    /// ```text
+    /// 1 2 3 C      1 2 3 4
+    /// 4 5 6 D  ->  Q W E R
+    /// 7 8 9 E      A S D F
+    /// A 0 B F      Z X C V
+    /// ```
+    fn default() -> KeyMap {
+        KeyMap {
+            keys: [
+                Keycode::X, Keycode::Num1, Keycode::Num2, Keycode::Num3,
+                Keycode::Q, Keycode::W, Keycode::E, Keycode::A,
+                Keycode::S, Keycode::D, Keycode::Z, Keycode::C,
+                Keycode::Num4, Keycode::R, Keycode::F, Keycode::V,
+            ],
+        }
+    }
+}
+
+impl KeyMap {
+    /// Parses a comma-separated list of the 16 SDL key names standing for
+    /// the hex keys 0 to F, in order, such as "X,1,2,3,Q,W,E,A,S,D,Z,C,4,R,F,V"
+    pub fn parse(list: &str) -> Result<KeyMap, Error> {
+        let names: Vec<&str> = list.split(',').map(str::trim).collect();
+        if names.len() != 16 {
+            return Err(Error::KeyCount { kind: "keys", count: names.len() });
+        }
+        let mut keys = KeyMap::default().keys;
+        for (key, name) in keys.iter_mut().zip(names) {
+            *key = Keycode::from_name(name).ok_or_else(|| Error::UnknownKey(name.to_string()))?;
+        }
+        Ok(KeyMap { keys })
+    }
+}
+
+/// The hex key `keycode` stands for in `mapping`, if any
+pub fn map_key(keycode: Keycode, mapping: &KeyMap) -> Option<u8> {
+    mapping.keys.iter().position(|&key| key == keycode).map(|key| key as u8)
+}
+
+/// Reads the input from the SDL keyboard, with the hex keypad laid out by a `KeyMap`,
+/// and from any game controllers, laid out by a `ButtonMap`. Both can be used at once.
+/// Keys used by the frontend, such as P or Tab, can't be mapped to the keypad.
+pub struct SdlInput {
+    event_pump: EventPump,
+    keymap: KeyMap,
+    // None if SDL has no controller support here
+    gamepads: Option<Gamepads>,
+    buttons: ButtonMap,
+    // The hex keys held down, from the keyboard and the controllers alike
+    keys: u16,
+}
+
+impl SdlInput {
+    pub fn new(event_pump: EventPump, keymap: KeyMap, gamepads: Option<Gamepads>, buttons: ButtonMap) -> SdlInput {
+        SdlInput { event_pump, keymap, gamepads, buttons, keys: 0 }
+    }
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self) -> KeyStateSnapshot {
+        let mut events = Vec::new();
+        for event in self.event_pump.poll_iter() {
+            let input = match event {
+                Event::Quit { .. } => InputEvent::Quit,
+                Event::KeyDown { keycode: Some(keycode), repeat, .. } => match keycode {
+                    Keycode::Escape => InputEvent::Quit,
+                    Keycode::P if !repeat => InputEvent::Pause,
+                    // Holding N keeps stepping
+                    Keycode::N => InputEvent::Step,
+                    Keycode::M if !repeat => InputEvent::DumpIndexMemory,
+                    Keycode::F1 if !repeat => InputEvent::ShowMenu,
+                    Keycode::F2 if !repeat => InputEvent::Screenshot,
+                    Keycode::F3 if !repeat => InputEvent::ToggleOverlay,
+                    Keycode::F4 if !repeat => InputEvent::DumpMemory,
+                    Keycode::F5 if !repeat => InputEvent::QuickSave,
+                    Keycode::F6 if !repeat => InputEvent::ToggleInvert,
+                    Keycode::F8 if !repeat => InputEvent::QuickLoad,
+                    Keycode::F9 if !repeat => InputEvent::ShowStats,
+                    Keycode::F10 if !repeat => InputEvent::StepOver,
+                    Keycode::F11 if !repeat => InputEvent::StepOut,
+                    Keycode::F12 if !repeat => InputEvent::Reset,
+                    Keycode::Backspace if !repeat => InputEvent::Rewind(true),
+                    Keycode::Tab if !repeat => InputEvent::Turbo(true),
+                    keycode if !repeat => match map_key(keycode, &self.keymap) {
+                        Some(key) => InputEvent::KeyDown(key),
+                        None => continue,
+                    },
+                    _ => continue,
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } => match keycode {
+                    Keycode::Backspace => InputEvent::Rewind(false),
+                    Keycode::Tab => InputEvent::Turbo(false),
+                    keycode => match map_key(keycode, &self.keymap) {
+                        Some(key) => InputEvent::KeyUp(key),
+                        None => continue,
+                    },
+                },
+                Event::DropFile { filename, .. } => InputEvent::LoadRom(filename),
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(gamepads) = self.gamepads.as_mut() {
+                        gamepads.connect(which);
+                    }
+                    continue;
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(gamepads) = self.gamepads.as_mut() {
+                        gamepads.disconnect(which);
+                    }
+                    // Don't leave the keys held on the controller stuck down
+                    events.extend(self.buttons.keys().map(InputEvent::KeyUp));
+                    continue;
+                }
+                Event::ControllerButtonDown { button, .. } => match gamepad::map_button(button, &self.buttons) {
+                    Some(key) => InputEvent::KeyDown(key),
+                    None => continue,
+                },
+                Event::ControllerButtonUp { button, .. } => match gamepad::map_button(button, &self.buttons) {
+                    Some(key) => InputEvent::KeyUp(key),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            events.push(input);
+        }
+        let snapshot = KeyStateSnapshot::new(self.keys, events);
+        self.keys = snapshot.keys;
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_the_classic_layout() {
+        let mapping = KeyMap::default();
+        assert_eq!(map_key(Keycode::X, &mapping), Some(0x0));
+        assert_eq!(map_key(Keycode::Num1, &mapping), Some(0x1));
+        assert_eq!(map_key(Keycode::Num4, &mapping), Some(0xC));
+        assert_eq!(map_key(Keycode::V, &mapping), Some(0xF));
+        assert_eq!(map_key(Keycode::P, &mapping), None);
+    }
+
+    #[test]
+    fn maps_a_parsed_layout() {
+        // AZERTY
+        let mapping = KeyMap::parse("X,1,2,3,A,Z,E,Q,S,D,W,C,4,R,F,V").unwrap();
+        assert_eq!(map_key(Keycode::A, &mapping), Some(0x4));
+        assert_eq!(map_key(Keycode::Q, &mapping), Some(0x7));
+        assert_eq!(map_key(Keycode::W, &mapping), Some(0xA));
+        assert_eq!(map_key(Keycode::Z, &mapping), Some(0x5));
+    }
+
+    #[test]
+    fn rejects_bad_layouts() {
+        assert!(KeyMap::parse("X,1,2").is_err());
+        assert!(KeyMap::parse("X,1,2,3,Q,W,E,A,S,D,Z,C,4,R,F,Nope").is_err());
+    }
+}
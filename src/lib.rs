@@ -0,0 +1,35 @@
+//! The emulator core: a CHIP-8 virtual machine along with the SUPER-CHIP and
+//! XO-CHIP extensions, ROM loading and the configuration types used to set it up.
+//! Nothing in here depends on SDL, which only the `lascaoito` binary uses.
+//! Without the `std` feature it's `no_std`, needing only an allocator.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg_attr(not(feature = "std"), macro_use)]
+extern crate alloc;
+
+pub mod asm;
+pub mod audio;
+pub mod chip8;
+#[cfg(feature = "std")]
+pub mod config;
+pub mod console;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod input;
+pub mod instruction;
+pub mod profile;
+pub mod quirks;
+pub mod recording;
+pub mod render;
+pub mod rewind;
+pub mod rom;
+#[cfg(feature = "std")]
+pub mod romdb;
+#[cfg(feature = "std")]
+pub mod rpl;
+#[cfg(feature = "std")]
+pub mod screenshot;
+pub mod stats;
+pub mod timing;
@@ -1,14 +1,34 @@
-#[macro_use] extern crate p_macro;
-mod chip8;
 mod cli;
-mod rom;
-use sdl2::{self, pixels::{Color, PixelFormatEnum}, event::Event, keyboard::Keycode};
-use sdl2::rect::Rect;
+mod game;
+mod gamepad;
+mod gfx;
+mod keyboard;
+mod menu;
+mod overlay;
+mod speaker;
+mod terminal;
+use lascaoito::audio::{AudioSink, Buzzer, NullAudio};
+use lascaoito::chip8::Chip8Event;
+use lascaoito::debugger::{self, EmulatorState, StepKind, StepProgress, StepTarget};
+use lascaoito::error::Error;
+use lascaoito::input::{InputEvent, InputSource};
+use lascaoito::profile::CLASSIC_MEMORY_SIZE;
+use lascaoito::recording::Recording;
+use lascaoito::render::Renderer;
+use lascaoito::{asm, chip8, console, disasm, render, rewind, rom, romdb, screenshot, timing};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
+/// The ROM run by --test, from ROMs/selftest.ch8. It draws checkered bars
+/// along the top and bottom of the screen with DXYN, "C8" in between from
+/// the font with FX29, then beeps once and loops forever.
+const SELF_TEST_ROM: &[u8] = include_bytes!("../ROMs/selftest.ch8");
 
-// The CHIP-8 has a 64x32 screen
-const SCREEN_SIZE: (u32, u32) = (64, 32);
-// const BLACK: Color = Color::RGB(0, 0, 0);
+/// How many instructions --rom-info shows
+const ROM_INFO_INSTRUCTIONS: usize = 8;
 
 macro_rules! catch {
     ($a:expr) => {
@@ -19,17 +39,140 @@ macro_rules! catch {
     };
 }
 
+/// Like `catch!`, for the main loop: leaves the loop called `$label` instead of returning,
+/// so that everything done on the way out, such as saving the RPL flags, still happens
+macro_rules! catch_break {
+    ($a:expr, $label:lifetime) => {
+        match $a {
+            Ok(value) => value,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                break $label;
+            }
+        }
+    };
+}
+
 fn main() {
+    // Warnings are shown unless RUST_LOG says otherwise.
+    // The core logs every executed opcode at the trace level.
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
 	let cfg = cli::Config::new();
-    let mut vm = chip8::VirtualMachine::new();
     catch!(cfg);
-    let cfg = cfg.unwrap();
-    println!("{:?}", cfg);
-    let cart = rom::Cartridge::new(cfg.filename.clone());
+    let mut cfg = cfg.unwrap();
+    log::debug!("{:?}", cfg);
+    if let Some((source, output)) = &cfg.assemble {
+        match assemble_file(source, output, cfg.load_address) {
+            Ok(size) => println!("Assembled {} bytes into {}", size, output),
+            Err(err) => {
+                eprintln!("Error: {}: {}", source, err);
+                // Scripts and build steps must see that nothing was assembled
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    // Given a directory, start on a menu of the ROMs in it
+    let mut menu = None;
+    if let Some(dir) = cfg.filename.as_deref().filter(|filename| Path::new(filename).is_dir()) {
+        if cfg.headless_cycles.is_some() || cfg.tui || cfg.disassemble || cfg.rom_info || cfg.record.is_some() || cfg.playback.is_some() {
+            eprintln!("Error: --headless, --tui, --disassemble, --rom-info, --record and --playback need a ROM file, not a directory.");
+            return;
+        }
+        let scanned = menu::Menu::scan(Path::new(dir));
+        catch!(scanned);
+        menu = scanned.ok();
+    }
+    let mut in_menu = menu.is_some();
+    let cart = match &cfg.filename {
+        Some(filename) if menu.is_none() => rom::Cartridge::from_file(filename),
+        // The self-test ROM also stands in until a ROM is picked from the menu
+        _ => rom::Cartridge::from_bytes(SELF_TEST_ROM).map(|mut cart| {
+            cart.info.name = "selftest".to_string();
+            cart
+        })
+    };
     catch!(cart);
     let cart = cart.unwrap();
-    vm.load_rom(cart);
-    println!("{}", cart.size);
+    if cfg.disassemble {
+        print!("{}", disasm::disassemble(cfg.load_address, &cart.data));
+        return;
+    }
+    if cfg.rom_info {
+        print_rom_info(&cart, cfg.load_address);
+        return;
+    }
+
+    // A recording holds the seed of the run it was made from, so that random numbers come out the same
+    let mut playback = None;
+    if let Some(path) = &cfg.playback {
+        let recording = Recording::load(path);
+        catch!(recording);
+        let recording = recording.unwrap();
+        if recording.rom.as_ref().is_some_and(|sha1| *sha1 != cart.info.sha1) {
+            eprintln!("Warning: {} was recorded with another ROM", path);
+        }
+        cfg.seed = recording.seed.or(cfg.seed);
+        playback = Some(recording.play());
+    }
+    let mut recording = None;
+    if cfg.record.is_some() {
+        let seed = *cfg.seed.get_or_insert_with(rand::random);
+        recording = Some(Recording::new(&cart.info.sha1, seed));
+    }
+
+    // Look for settings known to suit this ROM, and any ROM dropped later
+    let mut rom_db = romdb::RomDatabase::builtin();
+    if let Some(path) = &cfg.rom_db {
+        catch!(rom_db.load_file(path));
+    }
+    let mut game = game::Game::new(cart, cfg.filename.as_deref().filter(|_| menu.is_none()), &cfg, &rom_db);
+    let vm = game.start(&cfg, game.saved_flags);
+    catch!(vm);
+    let mut vm = vm.unwrap();
+    if !in_menu {
+        println!("{}", game.cart.info);
+    }
+
+    // Destination of the execution trace, if enabled
+    let mut tracer: Option<Box<dyn Write>> = match &cfg.trace {
+        Some(cli::TraceOutput::Stderr) => Some(Box::new(io::stderr())),
+        Some(cli::TraceOutput::File(path)) => {
+            let file = File::create(path);
+            catch!(file);
+            // One write per instruction would slow emulation down to a crawl
+            Some(Box::new(BufWriter::new(file.unwrap())))
+        }
+        None => None
+    };
+
+    if let Some(cycles) = cfg.headless_cycles {
+        if let Err(err) = run_headless(&mut vm, cycles, game.ips, &mut tracer) {
+            report_error(&vm, &err);
+            return;
+        }
+        if let Some(opcode_stats) = vm.opcode_stats() {
+            println!("{}", opcode_stats);
+        }
+        print!("{}", screenshot::render_ascii(&vm));
+        println!("{}", vm.trace_line());
+        if let Some((start, len)) = cfg.dump {
+            print!("{}", vm.hexdump(start, len));
+        }
+        return;
+    }
+
+    if cfg.tui {
+        if let Err(err) = run_tui(&mut vm, game.ips, timing::frame_target(cfg.delay, false), &mut tracer) {
+            report_error(&vm, &err);
+            return;
+        }
+        print_stats(&vm);
+        if let Some((start, len)) = cfg.dump {
+            print!("{}", vm.hexdump(start, len));
+        }
+        return;
+    }
 
     let sdl_context = sdl2::init();
     catch!(sdl_context);
@@ -39,86 +182,511 @@ fn main() {
     let video_subsystem = video_subsystem.unwrap();
 
     // Window title shows the loaded ROM
-    let title = format!("lascaoito [{}]", cfg.filename.to_string());
-
-    // Window dimensions
-    let width  = SCREEN_SIZE.0 * (cfg.scale as u32);
-    let height = SCREEN_SIZE.1 * (cfg.scale as u32);
-    
-    let window = video_subsystem.window(&title, width, height)
-        .position_centered()
-        .build();
-    if let Err(err) = window {
-        eprintln!("Error: {}", err);
-        return;
+    let title = if in_menu { "lascaoito".to_string() } else { format!("lascaoito [{}]", game.cart.info.name) };
+    let graphics = gfx::Graphics::new(&video_subsystem, title, cfg.scale, cfg.palette, cfg.invert);
+    catch!(graphics);
+    let mut graphics = graphics.unwrap();
+    if let Some(frames) = cfg.ghosting {
+        graphics.set_ghosting(frames);
     }
-    let window = window.unwrap();
-    drop(title);
-    let canvas = window.into_canvas().build();
-    
-    catch!(canvas);
-    let mut canvas = canvas.unwrap();
-    // canvas.set_draw_color(BLACK);
 
-    let texture_creator = canvas.texture_creator();
+    // Sound is optional: without an audio device the emulator simply stays silent
+    let mut audio: Box<dyn AudioSink> = if cfg.mute {
+        Box::new(NullAudio)
+    } else {
+        match speaker::SdlAudio::open(&sdl_context, cfg.beep) {
+            Ok(audio) => Box::new(audio),
+            Err(err) => {
+                eprintln!("Warning: no sound: {}", err);
+                Box::new(NullAudio)
+            }
+        }
+    };
+    let mut buzzer = Buzzer::default();
+
+    let event_pump = sdl_context.event_pump();
+    catch!(event_pump);
+    // Controllers are optional too, the keyboard always works
+    let gamepads = match sdl_context.game_controller() {
+        Ok(subsystem) => Some(gamepad::Gamepads::new(subsystem)),
+        Err(err) => {
+            eprintln!("Warning: no controller support: {}", err);
+            None
+        }
+    };
+    let mut input = keyboard::SdlInput::new(event_pump.unwrap(), cfg.keymap, gamepads, cfg.buttons);
 
-    // let surface = Surface::new()
+    // Snapshots of the previous frames, restored while Backspace is held
+    let mut rewind = rewind::RewindBuffer::new(cfg.rewind_depth);
 
-    // let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, 64, 32);
+    // How many cycles to run on each frame
+    let mut budget = timing::CycleBudget::new(game.ips);
 
-    // let texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGBA8888,  64, 32);
-    // catch!(texture);
-    // let mut texture = texture.unwrap();
+    // Each frame lasts 1/60th of a second plus the user-requested delay
+    let frame_target = timing::frame_target(cfg.delay, false);
 
-    canvas.clear();
-    canvas.present();
+    // While paused, the VM is left untouched but events are still handled.
+    // P pauses and resumes, N runs a single instruction while paused.
+    let mut state = EmulatorState::Running;
+    // Where stepping over or out of a subroutine stops, with F10 and F11.
+    // The emulation runs as usual until then.
+    let mut step_target: Option<StepTarget> = None;
+    // Instructions stepped through since the timers last ticked.
+    // Timers tick every `cycles_per_tick` steps, keeping their usual pace.
+    let mut stepped = 0;
+    let mut cycles_per_tick = (game.ips / timing::FRAME_RATE).max(1);
 
+    // Whether the registers are shown over the screen, toggled with F3
+    let mut show_overlay = false;
+    // Whether unset and set pixels swap colors, toggled with F6
+    let mut invert = cfg.invert;
 
+    // Backspace rewinds and Tab fast-forwards for as long as they're held
+    let mut rewinding = false;
+    let mut turbo = false;
 
-    let event_pump = sdl_context.event_pump();
-    catch!(event_pump);
-    let mut event_pump = event_pump.unwrap();
+    // Set once a program that halted in a spin loop has been reported.
+    // Its last frame stays on screen until the window is closed.
+    let mut ended = false;
 
+    let mut pacer = timing::FramePacer::new(Instant::now());
 
+    // Debugger commands typed on stdin, with --debug
+    let console = if cfg.debug { Some(console::spawn()) } else { None };
 
     'main_loop: loop {
-        for event in event_pump.poll_iter() 
-        {
-            match event 
-            {
-                Event::Quit { .. } | 
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    break 'main_loop;
-                }
-                _ => {}
-            }
-        }
-        vm.run_cycle();
-        if vm.draw_to_screen {
-            canvas.clear();
-            for (y, row) in vm.graphics.iter().enumerate() {
-                for (x, &pixcol) in row.iter().enumerate() {
-                    let x = (x as i32) * (cfg.scale as i32);
-                    let y = (y as i32) * (cfg.scale as i32);
-
-                    let color = if pixcol == 0 {
-                        Color::RGB(0, 0, 0)
-                    } else {
-                        Color::RGB(0, 250, 0)
+        // A ROM dropped onto the window or picked from the menu, to be run next
+        let mut load = None;
+        // Stepping over or out of a subroutine, asked for this frame
+        let mut finish = None;
+        let mut snapshot = input.poll();
+        let replaying = recording.is_some() || playback.is_some();
+        if replaying {
+            // Anything that would throw the recording out of step with the program is off
+            snapshot.events.retain(|event| !matches!(event,
+                InputEvent::Step | InputEvent::StepOver | InputEvent::StepOut | InputEvent::Reset | InputEvent::LoadRom(_) | InputEvent::ShowMenu | InputEvent::QuickLoad
+                | InputEvent::Rewind(_) | InputEvent::Turbo(_)));
+        }
+        let (next, mut steps) = state.advance(&snapshot.events);
+        if next != state && !in_menu {
+            state = next;
+            let status = if state.is_paused() { Some(paused_status(&vm)) } else { None };
+            catch_break!(graphics.set_status(status.as_deref()), 'main_loop);
+        }
+        for event in snapshot.events {
+            match event {
+                InputEvent::Quit => break 'main_loop,
+                InputEvent::KeyDown(key) if in_menu => {
+                    // Navigate with 2 and 8, the usual up and down of CHIP-8 games, and pick with 5
+                    if let Some(menu) = menu.as_mut() {
+                        match key {
+                            0x2 => menu.up(),
+                            0x8 => menu.down(),
+                            0x5 => load = menu.selected_path(),
+                            _ => {}
+                        }
+                    }
+                }
+                // The keypad is set all at once below
+                InputEvent::KeyDown(_) | InputEvent::KeyUp(_) => {}
+                InputEvent::Screenshot => {
+                    // Save a screenshot of the current frame
+                    let image = screenshot::render(&vm, &cfg.palette, invert, cfg.scale as u32);
+                    match screenshot::save(&image) {
+                        Ok(filename) => println!("Saved screenshot to {}", filename),
+                        Err(err) => eprintln!("Error: could not save screenshot: {}", err)
+                    }
+                }
+                // Pausing and stepping were handled above
+                InputEvent::Pause | InputEvent::Step => {}
+                InputEvent::StepOver if state.is_paused() => finish = Some(StepKind::Over),
+                InputEvent::StepOut if state.is_paused() => finish = Some(StepKind::Out),
+                InputEvent::StepOver | InputEvent::StepOut => {}
+                InputEvent::ToggleOverlay => show_overlay = !show_overlay,
+                InputEvent::ToggleInvert => {
+                    invert = !invert;
+                    graphics.set_invert(invert);
+                }
+                InputEvent::DumpMemory => {
+                    // The program area, to see what the program wrote over itself
+                    eprint!("{}", vm.hexdump(cfg.load_address, game.cart.size()));
+                }
+                InputEvent::DumpIndexMemory => {
+                    // A line before the one I is on, and three after
+                    let start = (vm.index() & !0xF).saturating_sub(0x10);
+                    eprint!("{}", vm.hexdump(start, 0x50));
+                }
+                InputEvent::QuickSave => match game.quicksave(&vm) {
+                    Ok(Some(path)) => println!("Saved the state to {}", path.display()),
+                    Ok(None) => eprintln!("Error: {} has no file to save the state next to", game.cart.info.name),
+                    Err(err) => eprintln!("Error: could not save the state: {}", err)
+                },
+                InputEvent::QuickLoad => match game.quickload(&mut vm) {
+                    Ok(true) => {
+                        println!("Loaded the saved state");
+                        rewind.clear();
+                    }
+                    Ok(false) => println!("There is no saved state to load yet, F5 saves one"),
+                    Err(err) => eprintln!("Error: could not load the state: {}", err)
+                },
+                InputEvent::ShowStats => {
+                    // Report the statistics gathered so far
+                    print_stats(&vm);
+                }
+                InputEvent::Reset => {
+                    // Start the program over, keeping its RPL flags
+                    vm = catch_break!(game.start(&cfg, vm.rpl_flags), 'main_loop);
+                    rewind.clear();
+                    if ended {
+                        ended = false;
+                        catch_break!(graphics.set_status(None), 'main_loop);
+                    }
+                }
+                InputEvent::LoadRom(path) => load = Some(path),
+                InputEvent::ShowMenu => {
+                    if menu.is_some() {
+                        in_menu = true;
+                        catch_break!(graphics.set_status(None), 'main_loop);
+                    }
+                }
+                InputEvent::Rewind(held) => rewinding = held,
+                InputEvent::Turbo(held) => turbo = held,
+            }
+        }
+        // The keypad is set once per frame, as the input source has it, unless a recording plays it
+        if playback.is_none() && !in_menu {
+            vm.set_keys(snapshot.keys);
+        }
+        for command in console.iter().flat_map(|console| console.try_iter()) {
+            match command {
+                // Like the input events above, these would throw the recording out of step
+                console::Command::Step(_) | console::Command::Finish(_) | console::Command::Set(..) if replaying => {
+                    println!("Not while recording or playing back input");
+                }
+                console::Command::Break(address) => {
+                    vm.add_breakpoint(address);
+                    println!("Breakpoint at {:#05x}", address);
+                }
+                console::Command::Delete(address) => {
+                    vm.remove_breakpoint(address);
+                    println!("Removed the breakpoint at {:#05x}", address);
+                }
+                console::Command::BreakOpcode(pattern) => {
+                    vm.add_opcode_breakpoint(pattern);
+                    println!("Breakpoint on {}", pattern);
+                }
+                console::Command::DeleteOpcode(pattern) => {
+                    vm.remove_opcode_breakpoint(pattern);
+                    println!("Removed the breakpoint on {}", pattern);
+                }
+                console::Command::Pause => {
+                    state = EmulatorState::Paused;
+                    catch_break!(graphics.set_status(Some(&paused_status(&vm))), 'main_loop);
+                }
+                console::Command::Step(count) => {
+                    state = EmulatorState::Paused;
+                    steps += count;
+                }
+                console::Command::Finish(kind) => {
+                    state = EmulatorState::Paused;
+                    finish = Some(kind);
+                }
+                console::Command::Continue => {
+                    state = EmulatorState::Running;
+                    catch_break!(graphics.set_status(None), 'main_loop);
+                }
+                console::Command::Regs => println!("{}", chip8::debug_lines(&vm).join("\n")),
+                console::Command::Mem { start, len } => print!("{}", vm.hexdump(start, len.into())),
+                console::Command::Set(console::Register::V(x), value) => vm.set_register(x, value as u8),
+                console::Command::Set(console::Register::I, value) => vm.set_index(value),
+                console::Command::Set(console::Register::Pc, value) => vm.set_pc(value),
+                console::Command::Quit => break 'main_loop,
+                console::Command::Help => println!("{}", console::USAGE),
+            }
+        }
+        if state.is_paused() {
+            // Paused again, by the user, a breakpoint or an error, before getting there
+            step_target = None;
+        }
+        if let Some(kind) = finish {
+            match StepTarget::new(kind, &vm) {
+                Some(target) => {
+                    step_target = Some(target);
+                    state = EmulatorState::Running;
+                    catch_break!(graphics.set_status(None), 'main_loop);
+                }
+                None => println!("Not in a subroutine, there is nothing to step out of"),
+            }
+        }
+        if let Some(path) = load {
+            // Switch to the new ROM, or keep running this one if it can't be loaded
+            match game::Game::load(&path, &cfg, &rom_db) {
+                Ok((next, next_vm)) => {
+                    game.save_flags(&vm.rpl_flags);
+                    println!("{}", next.cart.info);
+                    game = next;
+                    vm = next_vm;
+                    rewind.clear();
+                    budget = timing::CycleBudget::new(game.ips);
+                    cycles_per_tick = (game.ips / timing::FRAME_RATE).max(1);
+                    stepped = 0;
+                    state = EmulatorState::Running;
+                    ended = false;
+                    in_menu = false;
+                    catch_break!(graphics.set_status(None), 'main_loop);
+                    catch_break!(graphics.set_title(format!("lascaoito [{}]", game.cart.info.name)), 'main_loop);
+                }
+                Err(err) => eprintln!("Error: could not load {}: {}", path, err)
+            }
+        }
+        if in_menu {
+            // The program waits, silently, while the menu is up
+            buzzer.update(None, audio.as_mut());
+            if let Some(menu) = &menu {
+                catch_break!(menu.draw(&mut graphics), 'main_loop);
+            }
+            thread::sleep(pacer.frame_sleep(frame_target, Instant::now()));
+            continue;
+        }
+        if state.is_paused() {
+            for _ in 0..steps {
+                if let Some(out) = tracer.as_mut() {
+                    catch_break!(writeln!(out, "{}", vm.trace_line()), 'main_loop);
+                }
+                if let Err(err) = vm.run_cycle() {
+                    report_error(&vm, &err);
+                }
+                for event in vm.take_events() {
+                    eprintln!("{}", event);
+                }
+                eprint!("{}", vm);
+                stepped += 1;
+                if stepped >= cycles_per_tick {
+                    stepped = 0;
+                    vm.tick_timers();
+                }
+            }
+            if steps > 0 {
+                catch_break!(graphics.set_status(Some(&paused_status(&vm))), 'main_loop);
+            }
+        } else if rewinding {
+            // Step backwards instead of running the next frame
+            if let Some(state) = rewind.pop() {
+                vm.restore(&state);
+            }
+        } else {
+            rewind.push(vm.snapshot());
+            if let Some(frames) = playback.as_mut() {
+                match frames.next_frame() {
+                    Some(keys) => vm.set_keys(keys),
+                    None => {
+                        println!("The recording ended, the keyboard takes over");
+                        playback = None;
+                    }
+                }
+            }
+            if let Some(recording) = recording.as_mut() {
+                recording.push(vm.keys());
+            }
+            let budget = if turbo {
+                budget.next_frame_scaled(cfg.turbo)
+            } else {
+                budget.next_frame()
+            };
+            for _ in 0..state.cycles(budget, steps) {
+                if vm.should_break() {
+                    // Enter step mode right before the instruction
+                    state = EmulatorState::Paused;
+                    let status = format!("[BREAK {:#05x}] P: resume, N: step", vm.pc());
+                    catch_break!(graphics.set_status(Some(&status)), 'main_loop);
+                    eprint!("{}", vm);
+                    break;
+                }
+                if let Some(out) = tracer.as_mut() {
+                    catch_break!(writeln!(out, "{}", vm.trace_line()), 'main_loop);
+                }
+                if let Err(err) = vm.run_cycle() {
+                    // Stop right at the faulty instruction so it can be looked into
+                    report_error(&vm, &err);
+                    state = EmulatorState::Paused;
+                    catch_break!(graphics.set_status(Some("[ERROR] P: retry, N: step")), 'main_loop);
+                    eprint!("{}", vm);
+                    break;
+                }
+                let events = vm.take_events();
+                if let Some(event) = events.first() {
+                    // A watched address or register was written: stop right after the instruction that did it
+                    state = EmulatorState::Paused;
+                    let watched = match event {
+                        Chip8Event::MemoryWrite { addr, .. } => format!("{:#05x}", addr),
+                        Chip8Event::RegisterChange { register, .. } => register.to_string(),
                     };
+                    for event in &events {
+                        eprintln!("{}", event);
+                    }
+                    let status = format!("[WATCH {}] P: resume, N: step", watched);
+                    catch_break!(graphics.set_status(Some(&status)), 'main_loop);
+                    eprint!("{}", vm);
+                    break;
+                }
+                let progress = step_target.as_mut().map(|target| target.after_cycle(&vm));
+                if let Some(StepProgress::Reached | StepProgress::GaveUp) = progress {
+                    if progress == Some(StepProgress::GaveUp) {
+                        println!("The subroutine didn't return within {} instructions", debugger::STEP_LIMIT);
+                    }
+                    step_target = None;
+                    state = EmulatorState::Paused;
+                    catch_break!(graphics.set_status(Some(&paused_status(&vm))), 'main_loop);
+                    eprint!("{}", vm);
+                    break;
+                }
+            }
+            // Timers tick once per frame, independently of the emulation speed
+            vm.tick_timers();
+        }
+        // Stay quiet while paused rather than holding a note
+        buzzer.update(if state.is_paused() { None } else { vm.tone() }, audio.as_mut());
+        if vm.is_halted() && !vm.is_spinning() {
+            println!("The program ended");
+            break 'main_loop;
+        }
+        if vm.is_spinning() && !ended {
+            ended = true;
+            println!("The program ended in a spin loop at {:#05x}", vm.pc());
+            catch_break!(graphics.set_status(Some("[ENDED] F12: reset, Esc: quit")), 'main_loop);
+        }
+        graphics.set_overlay(if show_overlay { Some(chip8::debug_lines(&vm).join("\n")) } else { None });
+        catch_break!(render::render_frame(&mut graphics, &mut vm), 'main_loop);
+
+        // Sleep for whatever is left of this frame
+        thread::sleep(pacer.frame_sleep(timing::frame_target(cfg.delay, turbo), Instant::now()));
+    };
+
+    game.save_flags(&vm.rpl_flags);
+
+    if let (Some(recording), Some(path)) = (&recording, &cfg.record) {
+        match recording.save(path) {
+            Ok(()) => println!("Saved {} frames of input to {}", recording.frames.len(), path),
+            Err(err) => eprintln!("Error: could not save the input recording: {}", err)
+        }
+    }
+
+    print_stats(&vm);
+    if let Some((start, len)) = cfg.dump {
+        print!("{}", vm.hexdump(start, len));
+    }
+}
+
+/// The window status while paused, showing where the program stands
+fn paused_status(vm: &chip8::VirtualMachine) -> String {
+    format!("[PAUSED {:#05x}] P: resume, N: step", vm.pc())
+}
+
+/// Prints what's known about `cart`, loaded at `load_address`,
+/// along with its first few instructions
+fn print_rom_info(cart: &rom::Cartridge, load_address: u16) {
+    let info = &cart.info;
+    println!("Name:     {}", info.name);
+    println!("Size:     {} bytes", info.size);
+    println!("SHA-1:    {}", info.sha1);
+    println!("Variant:  {} (guessed from its opcodes)", info.variant);
+    let available = CLASSIC_MEMORY_SIZE.saturating_sub(load_address as usize);
+    if info.size <= available {
+        println!("Memory:   fits in the {} bytes of classic memory past {:#05x}", available, load_address);
+    } else {
+        println!("Memory:   too big for classic memory, which has {} bytes past {:#05x}", available, load_address);
+    }
+    println!("First instructions:");
+    for line in disasm::disassemble(load_address, &cart.data).lines().take(ROM_INFO_INSTRUCTIONS) {
+        println!("  {}", line);
+    }
+}
+
+/// Prints `err`, followed by the calls the program is in when it's the program at fault
+fn report_error(vm: &chip8::VirtualMachine, err: &Error) {
+    eprintln!("Error: {}", err);
+    if matches!(err, Error::UnknownOpcode { .. } | Error::StackOverflow { .. } | Error::StackUnderflow { .. } | Error::MemoryOutOfBounds { .. }) {
+        eprint!("{}", vm.backtrace());
+    }
+}
 
-                    let scale = cfg.scale as u32;
+/// Prints the statistics gathered so far, with the opcode counts if enabled
+fn print_stats(vm: &chip8::VirtualMachine) {
+    println!("{}", vm.stats);
+    if let Some(opcode_stats) = vm.opcode_stats() {
+        println!("{}", opcode_stats);
+    }
+}
 
-                    let fill_result = canvas.fill_rect(
-                        Rect::new(x, y, scale, scale)
-                    );
+/// Runs `cycles` instructions, or fewer if the program ends or hits a breakpoint or watchpoint, without a window
+/// and as fast as possible. Timers still tick once per frame's worth of instructions.
+/// Stops with an error if the program can't go on.
+fn run_headless(vm: &mut chip8::VirtualMachine, cycles: u64, ips: u32, tracer: &mut Option<Box<dyn Write>>) -> Result<(), Error> {
+    let mut budget = timing::CycleBudget::new(ips);
+    let mut remaining = cycles;
+    while remaining > 0 && !vm.is_halted() {
+        let frame = (budget.next_frame() as u64).min(remaining);
+        for _ in 0..frame {
+            if vm.should_break() {
+                println!("Breakpoint at {:#05x}", vm.pc());
+                return Ok(());
+            }
+            if let Some(out) = tracer.as_mut() {
+                writeln!(out, "{}", vm.trace_line())?;
+            }
+            vm.run_cycle()?;
+            let events = vm.take_events();
+            if !events.is_empty() {
+                for event in events {
+                    println!("Watchpoint: {}", event);
+                }
+                return Ok(());
+            }
+        }
+        remaining -= frame;
+        vm.tick_timers();
+    }
+    Ok(())
+}
 
-                    catch!(fill_result);
+/// Runs the program at its usual pace, each frame lasting `frame_target`, drawing its screen in the terminal, until it ends
+/// or hits a breakpoint or watchpoint. The last frame is left in the terminal.
+/// Stops with an error if the program can't go on.
+fn run_tui(vm: &mut chip8::VirtualMachine, ips: u32, frame_target: Duration, tracer: &mut Option<Box<dyn Write>>) -> Result<(), Error> {
+    let mut terminal = terminal::TerminalRenderer::new(io::stdout().lock());
+    terminal.clear().map_err(io::Error::other)?;
+    let mut budget = timing::CycleBudget::new(ips);
+    let mut pacer = timing::FramePacer::new(Instant::now());
+    while !vm.is_halted() {
+        for _ in 0..budget.next_frame() {
+            if vm.should_break() {
+                println!("Breakpoint at {:#05x}", vm.pc());
+                return Ok(());
+            }
+            if let Some(out) = tracer.as_mut() {
+                writeln!(out, "{}", vm.trace_line())?;
+            }
+            vm.run_cycle()?;
+            let events = vm.take_events();
+            if !events.is_empty() {
+                render::render_frame(&mut terminal, vm).map_err(io::Error::other)?;
+                for event in events {
+                    println!("Watchpoint: {}", event);
                 }
+                return Ok(());
             }
-            // canvas.present();
-            vm.draw_to_screen = false;
         }
-        canvas.present();
-    };
+        vm.tick_timers();
+        render::render_frame(&mut terminal, vm).map_err(io::Error::other)?;
+        thread::sleep(pacer.frame_sleep(frame_target, Instant::now()));
+    }
+    Ok(())
+}
+
+/// Assembles the file `source` into a ROM loaded at `start`, written to `output`.
+/// Returns the size of the ROM.
+fn assemble_file(source: &str, output: &str, start: u16) -> Result<usize, Error> {
+    let rom = asm::assemble_at(&std::fs::read_to_string(source)?, start)?;
+    std::fs::write(output, &rom)?;
+    Ok(rom.len())
 }
@@ -0,0 +1,216 @@
+use lascaoito::chip8::{Plane, FONTSET, LORES_HEIGHT, LORES_WIDTH, PLANE_COUNT, SCREEN_HEIGHT};
+use lascaoito::render::{Framebuffer, RenderError, Renderer};
+use lascaoito::rom;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Glyphs for the letters G to Z, in the style of the CHIP-8 fontset,
+/// which has the hex digits. Every glyph is 4 pixels wide and 5 pixels tall.
+const LETTERS: [[u8; 5]; 20] = [
+    [0xF0, 0x80, 0xB0, 0x90, 0xF0], // G
+    [0x90, 0x90, 0xF0, 0x90, 0x90], // H
+    [0xE0, 0x40, 0x40, 0x40, 0xE0], // I
+    [0x30, 0x10, 0x10, 0x90, 0x60], // J
+    [0x90, 0xA0, 0xC0, 0xA0, 0x90], // K
+    [0x80, 0x80, 0x80, 0x80, 0xF0], // L
+    [0x90, 0xF0, 0xF0, 0x90, 0x90], // M
+    [0x90, 0xD0, 0xB0, 0x90, 0x90], // N
+    [0x60, 0x90, 0x90, 0x90, 0x60], // O
+    [0xF0, 0x90, 0xF0, 0x80, 0x80], // P
+    [0x60, 0x90, 0x90, 0xB0, 0x70], // Q
+    [0xE0, 0x90, 0xE0, 0xA0, 0x90], // R
+    [0x70, 0x80, 0x60, 0x10, 0xE0], // S
+    [0xF0, 0x40, 0x40, 0x40, 0x40], // T
+    [0x90, 0x90, 0x90, 0x90, 0xF0], // U
+    [0x90, 0x90, 0x90, 0xA0, 0x40], // V
+    [0x90, 0x90, 0xF0, 0xF0, 0x90], // W
+    [0x90, 0x90, 0x60, 0x90, 0x90], // X
+    [0xA0, 0xA0, 0x40, 0x40, 0x40], // Y
+    [0xF0, 0x10, 0x60, 0x80, 0xF0], // Z
+];
+
+// Every character takes its 4 pixels plus one of spacing
+const CHAR_WIDTH: usize = 5;
+// Every line takes its 5 pixels plus one of spacing
+const LINE_HEIGHT: usize = 6;
+// How many ROMs fit on screen at once
+const VISIBLE_LINES: usize = LORES_HEIGHT / LINE_HEIGHT;
+// How many characters fit on a line, the first of which marks the selected ROM
+const LINE_LENGTH: usize = LORES_WIDTH / CHAR_WIDTH;
+
+/// The ROMs of a directory, one of which is selected, to be picked from
+/// on the emulator's own screen when started with a directory
+pub struct Menu {
+    dir: PathBuf,
+    // File names of the ROMs, sorted
+    names: Vec<String>,
+    selected: usize,
+}
+
+impl Menu {
+    /// Lists the ROMs in `dir`, judging by their extension
+    pub fn scan(dir: &Path) -> io::Result<Menu> {
+        let mut names = Vec::new();
+        for entry in dir.read_dir()? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_file() && rom::is_rom_name(&name) {
+                names.push(name);
+            }
+        }
+        names.sort_by_key(|name| name.to_lowercase());
+        Ok(Menu { dir: dir.to_path_buf(), names, selected: 0 })
+    }
+
+    /// Selects the previous ROM, going round to the last one
+    pub fn up(&mut self) {
+        if !self.names.is_empty() {
+            self.selected = (self.selected + self.names.len() - 1) % self.names.len();
+        }
+    }
+
+    /// Selects the next ROM, going round to the first one
+    pub fn down(&mut self) {
+        if !self.names.is_empty() {
+            self.selected = (self.selected + 1) % self.names.len();
+        }
+    }
+
+    /// The path to the selected ROM, if there are any
+    pub fn selected_path(&self) -> Option<String> {
+        let name = self.names.get(self.selected)?;
+        Some(self.dir.join(name).to_string_lossy().into_owned())
+    }
+
+    /// Shows the menu on a 64 x 32 screen
+    pub fn draw(&self, renderer: &mut dyn Renderer) -> Result<(), RenderError> {
+        let mut planes = [[0; SCREEN_HEIGHT]; PLANE_COUNT];
+        planes[0] = render(&self.names, self.selected);
+        // The menu is cheap to draw, so all of it is every frame
+        let dirty = [u128::MAX; SCREEN_HEIGHT];
+        renderer.draw(&Framebuffer::new(&planes, &dirty, LORES_WIDTH, LORES_HEIGHT))?;
+        renderer.present()
+    }
+}
+
+/// Draws the list of ROMs named `names` into the top-left 64 x 32 area of a plane,
+/// as many as fit, scrolled so that the `selected` one shows with a mark before it.
+/// Names are drawn without their extension, in capitals and cut to fit.
+pub fn render(names: &[String], selected: usize) -> Plane {
+    let mut plane = [0; SCREEN_HEIGHT];
+    if names.is_empty() {
+        draw_text(&mut plane, 0, " NO ROMS");
+        return plane;
+    }
+    let first = selected.saturating_sub(VISIBLE_LINES - 1);
+    for (line, (index, name)) in names.iter().enumerate().skip(first).take(VISIBLE_LINES).enumerate() {
+        let stem = Path::new(name).file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+        let mark = if index == selected { '>' } else { ' ' };
+        let text: String = std::iter::once(mark).chain(stem.chars()).take(LINE_LENGTH).collect();
+        draw_text(&mut plane, line, &text);
+    }
+    plane
+}
+
+/// Draws `text` on the given line, starting from the left edge
+fn draw_text(plane: &mut Plane, line: usize, text: &str) {
+    let top = line * LINE_HEIGHT + 1;
+    for (column, character) in text.chars().enumerate() {
+        let left = column * CHAR_WIDTH + 2;
+        for (y, row) in glyph(character).iter().enumerate() {
+            // The leftmost pixel of a glyph is its highest bit, the leftmost of a plane row its lowest
+            let bits = (row.reverse_bits() & 0xF) as u128;
+            plane[top + y] |= bits << left;
+        }
+    }
+}
+
+/// The glyph for `character`, ignoring case. Characters without one are drawn as a question mark.
+fn glyph(character: char) -> [u8; 5] {
+    let character = character.to_ascii_uppercase();
+    match character {
+        '0'..='9' | 'A'..='F' => {
+            let digit = character.to_digit(16).unwrap_or(0) as usize;
+            let mut glyph = [0; 5];
+            glyph.copy_from_slice(&FONTSET[digit * 5..digit * 5 + 5]);
+            glyph
+        }
+        'G'..='Z' => LETTERS[character as usize - 'G' as usize],
+        ' ' => [0; 5],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x40],
+        '-' => [0x00, 0x00, 0xF0, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0xF0],
+        '>' => [0x80, 0x40, 0x20, 0x40, 0x80],
+        _ => [0xE0, 0x10, 0x60, 0x00, 0x40],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// Whether the selection mark is drawn on `line`
+    fn marked(plane: &Plane, line: usize) -> bool {
+        plane[line * LINE_HEIGHT + 1] & (1 << 2) != 0
+    }
+
+    #[test]
+    fn renders_names_in_the_font() {
+        let plane = render(&names(&["a.ch8"]), 0);
+        // The top rows of the mark, then of the A, one character further
+        assert_eq!(plane[1], 1 << 2 | 0xF << 7);
+        assert_eq!(plane[5], 1 << 2 | 0x9 << 7);
+        assert!(plane[7..].iter().all(|&row| row == 0));
+        // The extension is left out
+        assert_eq!(render(&names(&["a"]), 0), plane);
+    }
+
+    #[test]
+    fn scrolls_to_the_selection() {
+        let list = names(&["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"]);
+        let plane = render(&list, 1);
+        assert!(!marked(&plane, 0));
+        assert!(marked(&plane, 1));
+
+        // Five lines fit, with the selection at the bottom: 3 to 7
+        let plane = render(&list, 7);
+        assert!((0..4).all(|line| !marked(&plane, line)));
+        assert!(marked(&plane, 4));
+        assert_eq!(plane, render(&names(&["3", "4", "5", "6", "7"]), 4));
+    }
+
+    #[test]
+    fn says_when_there_are_no_roms() {
+        let mut expected = [0; SCREEN_HEIGHT];
+        draw_text(&mut expected, 0, " NO ROMS");
+        assert_eq!(render(&[], 0), expected);
+        assert_ne!(expected[1], 0);
+        assert_eq!(glyph('n'), glyph('N'));
+        assert_eq!(glyph('!'), glyph('?'));
+    }
+
+    #[test]
+    fn lists_and_selects_roms() {
+        let dir = env::temp_dir().join(format!("lascaoito-menu-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub.ch8")).unwrap();
+        for name in ["b.ch8", "A.c8", "notes.txt"] {
+            fs::write(dir.join(name), [0x00, 0xE0]).unwrap();
+        }
+        let menu = Menu::scan(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let mut menu = menu.unwrap();
+
+        assert_eq!(menu.names, ["A.c8", "b.ch8"]);
+        assert_eq!(menu.selected_path(), Some(dir.join("A.c8").to_string_lossy().into_owned()));
+        menu.up();
+        assert_eq!(menu.selected, 1);
+        menu.down();
+        assert_eq!(menu.selected, 0);
+    }
+}
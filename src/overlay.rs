@@ -0,0 +1,70 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+// Glyphs are 3x5 pixels, with a pixel of spacing after each character and line
+const GLYPH_WIDTH: i32 = 3;
+const GLYPH_HEIGHT: i32 = 5;
+const ADVANCE: i32 = GLYPH_WIDTH + 1;
+const LINE_HEIGHT: i32 = GLYPH_HEIGHT + 1;
+
+/// The rows of the glyph for `c`, three bits each, most significant bit on the left.
+/// Only what the debug overlay shows is covered: hex digits and a few letters.
+/// Anything else is left blank.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b111, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b111, 0b100, 0b100, 0b100, 0b111],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b111, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b111, 0b100, 0b100],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'P' => [0b111, 0b101, 0b111, 0b100, 0b100],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        _ => [0; 5],
+    }
+}
+
+/// Draws `text` in the top-left corner of the window, in white over a translucent
+/// black box, with every pixel of the font drawn as a `size` x `size` square
+pub fn draw_text(canvas: &mut Canvas<Window>, text: &str, size: u32) -> Result<(), String> {
+    let size = size as i32;
+    let columns = text.lines().map(|line| line.chars().count()).max().unwrap_or(0) as i32;
+    let rows = text.lines().count() as i32;
+
+    // One pixel of margin around the text
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 192));
+    canvas.fill_rect(Rect::new(0, 0, ((columns * ADVANCE + 1) * size) as u32, ((rows * LINE_HEIGHT + 1) * size) as u32))?;
+
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    for (row, line) in text.lines().enumerate() {
+        for (column, c) in line.chars().enumerate() {
+            let left = 1 + column as i32 * ADVANCE;
+            let top = 1 + row as i32 * LINE_HEIGHT;
+            for (y, bits) in glyph(c).iter().enumerate() {
+                for x in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> x) != 0 {
+                        let rect = Rect::new((left + x) * size, (top + y as i32) * size, size as u32, size as u32);
+                        canvas.fill_rect(rect)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
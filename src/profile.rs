@@ -0,0 +1,47 @@
+use crate::error::Error;
+use alloc::string::ToString;
+use core::fmt;
+
+/// Memory available to CHIP-8 and SUPER-CHIP programs
+pub const CLASSIC_MEMORY_SIZE: usize = 4096;
+
+/// Memory available to XO-CHIP programs, the whole range `F000 NNNN` can address
+pub const XO_CHIP_MEMORY_SIZE: usize = 65536;
+
+/// The platform being emulated
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Profile {
+    // CHIP-8 along with the SUPER-CHIP extensions, with 4KB of memory
+    #[default]
+    Classic,
+    // XO-CHIP: 64KB of memory, a second display plane and sound patterns
+    XoChip,
+}
+
+impl Profile {
+    /// Parses a profile name, as accepted by `--profile`
+    pub fn parse(name: &str) -> Result<Profile, Error> {
+        match name.trim() {
+            "classic" => Ok(Profile::Classic),
+            "xochip" => Ok(Profile::XoChip),
+            unknown => Err(Error::UnknownProfile(unknown.to_string())),
+        }
+    }
+
+    /// Bytes of memory the machine has
+    pub fn memory_size(self) -> usize {
+        match self {
+            Profile::Classic => CLASSIC_MEMORY_SIZE,
+            Profile::XoChip => XO_CHIP_MEMORY_SIZE,
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::Classic => write!(f, "classic"),
+            Profile::XoChip => write!(f, "xochip"),
+        }
+    }
+}
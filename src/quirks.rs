@@ -0,0 +1,110 @@
+use crate::error::Error;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The name of every quirk, as accepted by `--quirks`
+pub const QUIRK_NAMES: [&str; 6] = ["shift", "memory", "clip", "vf-reset", "jump", "dispwait"];
+
+/// Behaviors in which CHIP-8 interpreters historically disagree.
+/// Each flag, when set, enables the quirky behavior described.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quirks {
+    // 8XY6 and 8XYE shift VX in place instead of shifting VY into VX
+    pub shift: bool,
+    // FX55 and FX65 leave I pointing right after the last register stored or read
+    pub memory: bool,
+    // Sprites are clipped at the screen edges instead of wrapping around
+    pub clip: bool,
+    // 8XY1, 8XY2 and 8XY3 reset VF to zero
+    pub vf_reset: bool,
+    // BNNN jumps to NNN + VX, X being the highest nibble of NNN, instead of NNN + V0
+    pub jump: bool,
+    // DXYN waits for the next frame before drawing
+    pub dispwait: bool,
+}
+
+impl Quirks {
+    /// The quirks enabled by a bare `--quirks`, and used when neither the command line
+    /// nor the ROM database gives any. Matches how this emulator behaved before quirks
+    /// were configurable.
+    pub fn default_set() -> Quirks {
+        Quirks {
+            shift: true,
+            memory: true,
+            ..Quirks::default()
+        }
+    }
+
+    /// Parses a comma-separated list of quirk names, e.g. `shift,clip,vf-reset`.
+    /// Repeated names are accepted.
+    pub fn parse(list: &str) -> Result<Quirks, Error> {
+        let mut quirks = Quirks::default();
+        for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "shift" => quirks.shift = true,
+                "memory" => quirks.memory = true,
+                "clip" => quirks.clip = true,
+                "vf-reset" => quirks.vf_reset = true,
+                "jump" => quirks.jump = true,
+                "dispwait" => quirks.dispwait = true,
+                unknown => return Err(Error::UnknownQuirk(unknown.to_string())),
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+impl fmt::Display for Quirks {
+    /// Lists the enabled quirks in the format accepted by `Quirks::parse`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [self.shift, self.memory, self.clip, self.vf_reset, self.jump, self.dispwait];
+        let enabled: Vec<&str> = QUIRK_NAMES
+            .iter()
+            .zip(flags.iter())
+            .filter(|(_, &enabled)| enabled)
+            .map(|(&name, _)| name)
+            .collect();
+        if enabled.is_empty() {
+            write!(f, "no quirks")
+        } else {
+            write!(f, "{}", enabled.join(","))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_list() {
+        let quirks = Quirks::parse("shift, clip,vf-reset").unwrap();
+        assert_eq!(quirks, Quirks { shift: true, clip: true, vf_reset: true, ..Quirks::default() });
+        assert_eq!(Quirks::parse("").unwrap(), Quirks::default());
+    }
+
+    #[test]
+    fn accepts_every_name() {
+        let quirks = Quirks::parse(&QUIRK_NAMES.join(",")).unwrap();
+        assert_eq!(quirks.to_string(), QUIRK_NAMES.join(","));
+    }
+
+    #[test]
+    fn accepts_duplicates() {
+        assert_eq!(Quirks::parse("jump,jump").unwrap(), Quirks { jump: true, ..Quirks::default() });
+    }
+
+    #[test]
+    fn rejects_typos() {
+        let message = Quirks::parse("shift,clipp").unwrap_err().to_string();
+        assert!(message.contains("\"clipp\""));
+        assert!(message.contains("vf-reset"));
+    }
+
+    #[test]
+    fn default_set_matches_the_old_behavior() {
+        assert_eq!(Quirks::default_set().to_string(), "shift,memory");
+        assert_eq!(Quirks::default().to_string(), "no quirks");
+    }
+}
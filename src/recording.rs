@@ -0,0 +1,180 @@
+use crate::error::Error;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The keypad of every frame of a run, along with what's needed to run it again
+/// the same way: the seed of the random number generator and the ROM it was made with.
+///
+/// Written as text, one frame per line, with the keys held as a hex bitmask:
+///
+/// ```text
+/// rom 9d6be3d72e99e4c61955ee4961833b24411bb779
+/// seed 1234
+/// 0000
+/// 0020
+/// ```
+///
+/// Lines starting with `#` are comments.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Recording {
+    // SHA-1 of the ROM the recording was made with, if known
+    pub rom: Option<String>,
+    pub seed: Option<u64>,
+    // The keypad bitmask of each frame, see `VirtualMachine::keys`
+    pub frames: Vec<u16>,
+}
+
+impl Recording {
+    /// An empty recording of the ROM with the SHA-1 `rom`, run with `seed`
+    pub fn new(rom: &str, seed: u64) -> Recording {
+        Recording { rom: Some(rom.to_string()), seed: Some(seed), frames: Vec::new() }
+    }
+
+    /// Records the keys held in the next frame
+    pub fn push(&mut self, keys: u16) {
+        self.frames.push(keys);
+    }
+
+    /// Parses a recording written by `Display`
+    pub fn parse(text: &str) -> Result<Recording, Error> {
+        let mut recording = Recording::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let invalid = || Error::InvalidRecording { line: index + 1 };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(sha1) = line.strip_prefix("rom ") {
+                recording.rom = Some(sha1.trim().to_string());
+            } else if let Some(seed) = line.strip_prefix("seed ") {
+                recording.seed = Some(seed.trim().parse().map_err(|_| invalid())?);
+            } else {
+                recording.frames.push(u16::from_str_radix(line, 16).map_err(|_| invalid())?);
+            }
+        }
+        Ok(recording)
+    }
+
+    /// Reads a recording from the file at `path`
+    #[cfg(feature = "std")]
+    pub fn load(path: &str) -> Result<Recording, Error> {
+        Recording::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Writes the recording to the file at `path`, to be read back by `load`
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Plays the recording back from the first frame
+    pub fn play(self) -> Playback {
+        Playback { frames: self.frames, next: 0 }
+    }
+}
+
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# lascaoito input recording, one frame per line")?;
+        if let Some(rom) = &self.rom {
+            writeln!(f, "rom {}", rom)?;
+        }
+        if let Some(seed) = self.seed {
+            writeln!(f, "seed {}", seed)?;
+        }
+        for keys in &self.frames {
+            writeln!(f, "{:04x}", keys)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hands out the frames of a recording one at a time
+pub struct Playback {
+    frames: Vec<u16>,
+    // The frame to hand out next
+    next: usize,
+}
+
+impl Playback {
+    /// The keys to hold in the next frame, or None once the recording is over
+    pub fn next_frame(&mut self) -> Option<u16> {
+        let keys = self.frames.get(self.next).copied();
+        self.next += 1;
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::VirtualMachine;
+    use crate::rom::Cartridge;
+
+    // Waits for a key, then draws a random digit at a random place, over and over:
+    // LD V0,K; RND V1,0xf; LD F,V1; RND V2,0x3f; RND V3,0x1f; DRW V2,V3,5; JP 0x200
+    const PROGRAM: [u8; 14] = [
+        0xF0, 0x0A, 0xC1, 0x0F, 0xF1, 0x29, 0xC2, 0x3F, 0xC3, 0x1F, 0xD2, 0x35, 0x12, 0x00,
+    ];
+
+    /// Runs 10 instructions a frame, holding the keys `next_keys` gives for each frame
+    fn run(seed: u64, mut next_keys: impl FnMut(&mut VirtualMachine)) -> VirtualMachine {
+        let mut vm = VirtualMachine::with_seed(seed);
+        vm.load_rom(&Cartridge::from_bytes(&PROGRAM).unwrap()).unwrap();
+        for _ in 0..30 {
+            next_keys(&mut vm);
+            for _ in 0..10 {
+                vm.run_cycle().unwrap();
+            }
+            vm.tick_timers();
+        }
+        vm
+    }
+
+    #[test]
+    fn replays_a_recorded_run() {
+        let script = [0x0001, 0, 0x0010, 0x8000, 0, 0x0004];
+        let mut recording = Recording::new("sha1", 42);
+        let mut frame = 0;
+        let recorded = run(42, |vm| {
+            vm.set_keys(script[frame % script.len()]);
+            frame += 1;
+            recording.push(vm.keys());
+        });
+
+        // Through the text format and back, as --record and --playback do
+        let recording = Recording::parse(&recording.to_string()).unwrap();
+        assert_eq!(recording.seed, Some(42));
+        assert_eq!(recording.frames.len(), 30);
+        let mut playback = recording.play();
+        let replayed = run(42, |vm| vm.set_keys(playback.next_frame().unwrap()));
+        assert!(playback.next_frame().is_none());
+
+        // Registers, memory and the screen alike
+        assert!(recorded.snapshot() == replayed.snapshot());
+        assert_eq!(recorded.stats.instructions, replayed.stats.instructions);
+    }
+
+    #[test]
+    fn parses_the_text_format() {
+        let recording = Recording::parse("# comment\nrom abc\nseed 7\n\n0000\n00ff\n").unwrap();
+        assert_eq!(recording, Recording { rom: Some("abc".to_string()), seed: Some(7), frames: vec![0, 0xFF] });
+        assert!(matches!(Recording::parse("0000\nkeys\n"), Err(Error::InvalidRecording { line: 2 })));
+        assert!(matches!(Recording::parse("seed x\n"), Err(Error::InvalidRecording { line: 1 })));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn saves_and_loads_a_file() {
+        let path = std::env::temp_dir().join(format!("lascaoito-recording-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut recording = Recording::new("abc", 1);
+        recording.push(0x0123);
+        recording.save(path).unwrap();
+        let loaded = Recording::load(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.unwrap(), recording);
+    }
+}
@@ -0,0 +1,397 @@
+use crate::chip8::{Plane, VirtualMachine, PLANE_COUNT, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::error::Error;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Colors of pixels unset, set on the first plane, set on the second plane
+/// and set on both. Only XO-CHIP programs draw on the second plane.
+pub const DEFAULT_PALETTE: [[u8; 3]; 4] = [[0, 0, 0], [0, 250, 0], [0, 100, 250], [250, 250, 250]];
+
+/// The color of a pixel set on `planes`, from `palette`, which is indexed by them.
+/// Inverting swaps the colors of unset pixels and pixels set on the first plane only,
+/// the two colors of every program but XO-CHIP ones.
+pub fn pixel_color(palette: &[[u8; 3]; 4], planes: u8, invert: bool) -> [u8; 3] {
+    let index = planes as usize & 0b11;
+    if invert && index < 2 {
+        palette[index ^ 1]
+    } else {
+        palette[index]
+    }
+}
+
+/// The color themes known to `theme`
+pub const THEME_NAMES: [&str; 4] = ["classic-green", "amber", "white", "gameboy"];
+
+/// The colors of unset and set pixels in the theme called `name`:
+///
+/// | Theme         | Unset     | Set       |
+/// |---------------|-----------|-----------|
+/// | classic-green | `#000000` | `#00fa00` |
+/// | amber         | `#000000` | `#ffb000` |
+/// | white         | `#000000` | `#ffffff` |
+/// | gameboy       | `#0f380f` | `#9bbc0f` |
+pub fn theme(name: &str) -> Result<[[u8; 3]; 2], Error> {
+    match name.trim() {
+        "classic-green" => Ok([[0x00, 0x00, 0x00], [0x00, 0xFA, 0x00]]),
+        "amber" => Ok([[0x00, 0x00, 0x00], [0xFF, 0xB0, 0x00]]),
+        "white" => Ok([[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF]]),
+        "gameboy" => Ok([[0x0F, 0x38, 0x0F], [0x9B, 0xBC, 0x0F]]),
+        unknown => Err(Error::UnknownTheme(unknown.into())),
+    }
+}
+
+/// A read-only view of the screen of the VM, in its current resolution
+#[derive(Clone, Copy)]
+pub struct Framebuffer<'a> {
+    planes: &'a [Plane; PLANE_COUNT],
+    // One bit per pixel, set if it changed since it was last drawn
+    dirty: &'a [u128; SCREEN_HEIGHT],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Framebuffer<'a> {
+    pub fn new(
+        planes: &'a [Plane; PLANE_COUNT],
+        dirty: &'a [u128; SCREEN_HEIGHT],
+        width: usize,
+        height: usize,
+    ) -> Framebuffer<'a> {
+        Framebuffer { planes, dirty, width, height }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The planes the pixel at (x, y) is set on: 0 when unset, 1 for the first plane,
+    /// 2 for the second XO-CHIP plane and 3 for both
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        let mut planes = 0;
+        for (i, plane) in self.planes.iter().enumerate() {
+            planes |= (((plane[y] >> x) & 1) as u8) << i;
+        }
+        planes
+    }
+
+    /// Whether the pixel at (x, y) changed since the screen was last drawn.
+    /// Renderers that keep what they drew only need to repaint these.
+    pub fn is_dirty(&self, x: usize, y: usize) -> bool {
+        self.dirty[y] & (1 << x) != 0
+    }
+
+    /// How many pixels changed since the screen was last drawn
+    pub fn dirty_count(&self) -> usize {
+        // Rows are 128 bits wide, past the low resolution screen too
+        let width = if self.width < 128 { (1 << self.width) - 1 } else { u128::MAX };
+        self.dirty.iter().take(self.height).map(|row| (row & width).count_ones() as usize).sum()
+    }
+
+    /// The rows of the screen, each made of the `width()` pixels in it, as given by `pixel`
+    pub fn rows(&self) -> impl Iterator<Item = impl Iterator<Item = u8> + 'a> + 'a {
+        let framebuffer = *self;
+        (0..self.height).map(move |y| (0..framebuffer.width).map(move |x| framebuffer.pixel(x, y)))
+    }
+}
+
+/// The most frames `Ghosting` lets a pixel fade over, a whole second
+pub const MAX_GHOSTING: u8 = 60;
+
+/// How lit the pixel is on the next frame, out of `frames + 1`, given how lit it was:
+/// fully while set, then one step less for each frame since it was unset
+pub fn fade(level: u8, lit: bool, frames: u8) -> u8 {
+    if lit {
+        frames + 1
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+/// `lit` mixed into `unlit` for a pixel at `level` out of `frames + 1`
+pub fn blend(unlit: [u8; 3], lit: [u8; 3], level: u8, frames: u8) -> [u8; 3] {
+    let full = frames as u32 + 1;
+    let level = (level as u32).min(full);
+    let mut color = [0; 3];
+    for (channel, (&from, &to)) in color.iter_mut().zip(unlit.iter().zip(lit.iter())) {
+        *channel = ((from as u32 * (full - level) + to as u32 * level) / full) as u8;
+    }
+    color
+}
+
+/// Phosphor persistence, for programs that flicker by erasing and redrawing their
+/// sprites on every frame: pixels that get unset fade out over a few frames,
+/// in the color they had, instead of vanishing at once
+pub struct Ghosting {
+    // How many frames an unset pixel takes to fade out
+    frames: u8,
+    // How lit each pixel is, as given by `fade`, rows being SCREEN_WIDTH long
+    levels: Vec<u8>,
+    // The planes each pixel was last set on
+    planes: Vec<u8>,
+    // The resolution as of the last update, the screen being cleared when it changes
+    screen: (usize, usize),
+}
+
+impl Ghosting {
+    pub fn new(frames: u8) -> Ghosting {
+        let frames = frames.min(MAX_GHOSTING);
+        Ghosting {
+            frames,
+            levels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            planes: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            screen: (0, 0),
+        }
+    }
+
+    /// Follows `framebuffer` through one more frame
+    pub fn update(&mut self, framebuffer: &Framebuffer) {
+        let screen = (framebuffer.width(), framebuffer.height());
+        if screen != self.screen {
+            self.levels.iter_mut().for_each(|level| *level = 0);
+            self.screen = screen;
+        }
+        for y in 0..framebuffer.height() {
+            for x in 0..framebuffer.width() {
+                let i = y * SCREEN_WIDTH + x;
+                let planes = framebuffer.pixel(x, y);
+                self.levels[i] = fade(self.levels[i], planes != 0, self.frames);
+                if planes != 0 {
+                    self.planes[i] = planes;
+                }
+            }
+        }
+    }
+
+    /// Whether any pixel is still fading out, and so must be drawn on the next frame too
+    pub fn is_fading(&self) -> bool {
+        self.levels.iter().any(|&level| level > 0 && level <= self.frames)
+    }
+
+    /// The color of the pixel at (x, y) as of the last update, from `palette` as in `pixel_color`
+    pub fn color(&self, x: usize, y: usize, palette: &[[u8; 3]; 4], invert: bool) -> [u8; 3] {
+        let i = y * SCREEN_WIDTH + x;
+        let unlit = pixel_color(palette, 0, invert);
+        blend(unlit, pixel_color(palette, self.planes[i], invert), self.levels[i], self.frames)
+    }
+}
+
+/// Characters standing for pixels in `render_terminal`, indexed by the planes the pixel is set on
+pub const TERMINAL_SHADES: [char; 4] = [' ', '\u{2588}', '\u{2592}', '\u{2593}'];
+
+/// Draws `framebuffer`, in its current resolution, as text for a terminal:
+/// one line per row, with a full block for set pixels and a space for unset ones.
+/// XO-CHIP pixels set on the second plane only, or on both, get lighter shades.
+pub fn render_terminal(framebuffer: &Framebuffer) -> String {
+    let mut text = String::with_capacity((framebuffer.width() * 3 + 1) * framebuffer.height());
+    for row in framebuffer.rows() {
+        for pixel in row {
+            text.push(TERMINAL_SHADES[pixel as usize & 0b11]);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Something that went wrong while drawing, as reported by the frontend
+#[derive(Debug)]
+pub struct RenderError(pub String);
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RenderError {}
+
+impl From<String> for RenderError {
+    fn from(message: String) -> RenderError {
+        RenderError(message)
+    }
+}
+
+/// A frontend that shows the screen of the VM, such as an SDL window
+pub trait Renderer {
+    /// Blanks the whole output
+    fn clear(&mut self) -> Result<(), RenderError>;
+
+    /// Draws `framebuffer`. Renderers that keep what they drew across frames may
+    /// repaint only the pixels marked as dirty, others must draw every pixel.
+    fn draw(&mut self, framebuffer: &Framebuffer) -> Result<(), RenderError>;
+
+    /// Shows what was drawn since the last call
+    fn present(&mut self) -> Result<(), RenderError>;
+
+    /// Whether the screen must be drawn on the next frame even if it didn't change,
+    /// such as while pixels are fading out
+    fn is_animating(&self) -> bool {
+        false
+    }
+}
+
+/// Redraws the screen of the VM, if it changed since the last frame
+/// or the renderer is animating it, and presents it
+pub fn render_frame(renderer: &mut dyn Renderer, vm: &mut VirtualMachine) -> Result<(), RenderError> {
+    if vm.draw_to_screen || renderer.is_animating() {
+        renderer.draw(&vm.framebuffer())?;
+    }
+    if vm.draw_to_screen {
+        vm.clear_dirty();
+        vm.draw_to_screen = false;
+        vm.stats.frames += 1;
+    }
+    renderer.present()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Cartridge;
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn inverting_swaps_unset_and_set_pixels() {
+        let palette = [[1, 1, 1], [2, 2, 2], [3, 3, 3], [4, 4, 4]];
+        let colors: Vec<[u8; 3]> = (0..4).map(|planes| pixel_color(&palette, planes, false)).collect();
+        assert_eq!(colors, palette);
+        let inverted: Vec<[u8; 3]> = (0..4).map(|planes| pixel_color(&palette, planes, true)).collect();
+        assert_eq!(inverted, [[2, 2, 2], [1, 1, 1], [3, 3, 3], [4, 4, 4]]);
+    }
+
+    #[test]
+    fn resolves_the_themes() {
+        assert_eq!(theme("classic-green").unwrap(), [[0, 0, 0], DEFAULT_PALETTE[1]]);
+        assert_eq!(theme("amber").unwrap(), [[0, 0, 0], [0xFF, 0xB0, 0x00]]);
+        assert_eq!(theme("white").unwrap(), [[0, 0, 0], [0xFF, 0xFF, 0xFF]]);
+        assert_eq!(theme("gameboy").unwrap(), [[0x0F, 0x38, 0x0F], [0x9B, 0xBC, 0x0F]]);
+        for name in THEME_NAMES {
+            assert!(theme(name).is_ok(), "{}", name);
+        }
+        let err = theme("Amber").unwrap_err();
+        assert_eq!(err.to_string(), "unknown theme \"Amber\". Valid themes are: classic-green, amber, white, gameboy.");
+    }
+
+    #[test]
+    fn fades_a_pixel_that_turns_off() {
+        // Fully lit while set, then down a step per frame
+        let mut levels = Vec::new();
+        let mut level = 0;
+        for lit in [true, true, false, false, false, false, true] {
+            level = fade(level, lit, 3);
+            levels.push(level);
+        }
+        assert_eq!(levels, [4, 4, 3, 2, 1, 0, 4]);
+
+        let (unlit, lit) = ([0, 0, 0], [0, 200, 100]);
+        let colors: Vec<[u8; 3]> = levels[1..6].iter().map(|&level| blend(unlit, lit, level, 3)).collect();
+        assert_eq!(colors, [[0, 200, 100], [0, 150, 75], [0, 100, 50], [0, 50, 25], [0, 0, 0]]);
+    }
+
+    #[test]
+    fn ghosts_unset_pixels_in_their_color() {
+        let mut planes = [[0; SCREEN_HEIGHT]; PLANE_COUNT];
+        let dirty = [0; SCREEN_HEIGHT];
+        let palette = [[0, 0, 0], [200, 200, 200], [0, 0, 100], [10, 10, 10]];
+        let mut ghosting = Ghosting::new(1);
+        planes[1][3] = 1 << 2;
+        ghosting.update(&Framebuffer::new(&planes, &dirty, 64, 32));
+        assert_eq!(ghosting.color(2, 3, &palette, false), [0, 0, 100]);
+        assert!(!ghosting.is_fading());
+
+        planes[1][3] = 0;
+        ghosting.update(&Framebuffer::new(&planes, &dirty, 64, 32));
+        assert_eq!(ghosting.color(2, 3, &palette, false), [0, 0, 50]);
+        assert!(ghosting.is_fading());
+        ghosting.update(&Framebuffer::new(&planes, &dirty, 64, 32));
+        assert_eq!(ghosting.color(2, 3, &palette, false), [0, 0, 0]);
+        assert!(!ghosting.is_fading());
+    }
+
+    /// Keeps what it was asked to do, the screens as lists of lit pixels
+    #[derive(Default)]
+    struct MockRenderer {
+        // Since the last clear
+        draws: Vec<Vec<(usize, usize)>>,
+        // All of them, which clearing doesn't reset
+        draw_count: usize,
+        presents: usize,
+    }
+
+    impl Renderer for MockRenderer {
+        fn clear(&mut self) -> Result<(), RenderError> {
+            self.draws.clear();
+            Ok(())
+        }
+
+        fn draw(&mut self, framebuffer: &Framebuffer) -> Result<(), RenderError> {
+            let mut lit = Vec::new();
+            for (y, row) in framebuffer.rows().enumerate() {
+                lit.extend(row.enumerate().filter(|&(_, pixel)| pixel != 0).map(|(x, _)| (x, y)));
+            }
+            self.draws.push(lit);
+            self.draw_count += 1;
+            Ok(())
+        }
+
+        fn present(&mut self) -> Result<(), RenderError> {
+            self.presents += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn draws_frames_that_changed() {
+        // LD V0,1; LD F,V0; DRW V0,V0,5; JP 0x206
+        let rom = Cartridge::from_bytes(&[0x60, 0x01, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06]).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&rom).unwrap();
+        let mut renderer = MockRenderer::default();
+        for _ in 0..3 {
+            for _ in 0..4 {
+                vm.run_cycle().unwrap();
+            }
+            render_frame(&mut renderer, &mut vm).unwrap();
+        }
+
+        // The 1 glyph at (1, 1), drawn on the first frame only
+        let one = [(3, 1), (2, 2), (3, 2), (3, 3), (3, 4), (2, 5), (3, 5), (4, 5)];
+        assert_eq!(renderer.draws, [one]);
+        assert_eq!(renderer.draw_count, 1);
+        assert_eq!(renderer.presents, 3);
+        assert_eq!(vm.stats.frames, 1);
+        assert!(!vm.draw_to_screen);
+    }
+
+    #[test]
+    fn renders_a_pixel_in_the_terminal() {
+        let mut planes = [[0; SCREEN_HEIGHT]; PLANE_COUNT];
+        planes[0][2] = 1 << 5;
+        let dirty = [0; SCREEN_HEIGHT];
+        let text = render_terminal(&Framebuffer::new(&planes, &dirty, 64, 32));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert!(lines.iter().all(|line| line.chars().count() == 64));
+        assert_eq!(lines[2], format!("{}\u{2588}{}", " ".repeat(5), " ".repeat(58)));
+        assert_eq!(text.matches('\u{2588}').count(), 1);
+    }
+
+    #[test]
+    fn shades_the_planes_in_the_terminal() {
+        let mut planes = [[0; SCREEN_HEIGHT]; PLANE_COUNT];
+        planes[0][0] = 0b101;
+        planes[1][0] = 0b110;
+        let dirty = [0; SCREEN_HEIGHT];
+        // The high resolution screen is 128 wide
+        let text = render_terminal(&Framebuffer::new(&planes, &dirty, 128, 64));
+        let first: String = text.lines().next().unwrap().chars().take(4).collect();
+        assert_eq!(first, "\u{2588}\u{2592}\u{2593} ");
+        assert_eq!(text.lines().count(), 64);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::chip8::VmState;
+use alloc::collections::VecDeque;
+
+/// How many snapshots are kept by default.
+/// At 60 frames per second this amounts to five seconds of rewind.
+pub const DEFAULT_REWIND_DEPTH: usize = 300;
+
+/// A bounded ring buffer holding the most recent snapshots of the VM.
+/// Once full, pushing a new snapshot discards the oldest one.
+pub struct RewindBuffer {
+    // Snapshots, from oldest to newest
+    snapshots: VecDeque<VmState>,
+    // How many snapshots may be kept at once
+    capacity: usize,
+}
+
+impl RewindBuffer {
+    /// Creates an empty buffer able to hold `capacity` snapshots.
+    /// A capacity of zero disables rewinding altogether.
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Records a new snapshot, dropping the oldest one if the buffer is full
+    pub fn push(&mut self, state: VmState) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(state);
+    }
+
+    /// Takes out the most recent snapshot, if any
+    pub fn pop(&mut self) -> Option<VmState> {
+        self.snapshots.pop_back()
+    }
+
+    /// Forgets every snapshot, such as after the VM is reset
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::VirtualMachine;
+    use alloc::vec::Vec;
+
+    /// Snapshots of a machine whose stack pointer goes 0, 1, 2...
+    fn snapshots(count: u8) -> Vec<VmState> {
+        let mut vm = VirtualMachine::new();
+        (0..count)
+            .map(|n| {
+                vm.sp = n as u16;
+                vm.snapshot()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pops_the_newest_first() {
+        let states = snapshots(3);
+        let mut buffer = RewindBuffer::new(10);
+        for state in &states {
+            buffer.push(state.clone());
+        }
+        assert!(buffer.pop() == Some(states[2].clone()));
+        assert!(buffer.pop() == Some(states[1].clone()));
+        assert!(buffer.pop() == Some(states[0].clone()));
+        assert!(buffer.pop().is_none());
+    }
+
+    #[test]
+    fn drops_the_oldest_when_full() {
+        let states = snapshots(4);
+        let mut buffer = RewindBuffer::new(2);
+        for state in &states {
+            buffer.push(state.clone());
+        }
+        assert!(buffer.pop() == Some(states[3].clone()));
+        assert!(buffer.pop() == Some(states[2].clone()));
+        assert!(buffer.pop().is_none());
+    }
+
+    #[test]
+    fn keeps_nothing_without_capacity() {
+        let mut buffer = RewindBuffer::new(0);
+        buffer.push(snapshots(1).remove(0));
+        assert!(buffer.pop().is_none());
+    }
+}
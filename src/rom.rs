@@ -1,39 +1,481 @@
+#[cfg(feature = "std")]
+use flate2::read::GzDecoder;
+#[cfg(feature = "std")]
 use std::fs::File;
-use std::io::{Error, ErrorKind, Read};
+#[cfg(feature = "std")]
+use std::io::{self, BufRead, BufReader, Read, Seek};
+#[cfg(feature = "std")]
+use std::path::Path;
+#[cfg(feature = "std")]
+use zip::{result::ZipError, ZipArchive};
+use crate::error::Error;
+use crate::profile::XO_CHIP_MEMORY_SIZE;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use log::warn;
 
-/// A ROM may contain at max 4096-512 bytes, since 4096 bytes is the
-/// maximum available amount of memory, and the first 512 bytes are  
+/// A ROM may contain at max 65536-512 bytes, since 64KB is the most
+/// memory any machine has (XO-CHIP's), and the first 512 bytes are
 /// reserved by the machine-specific interpreters.
-const MAX_ROM_SIZE: u16 = 4096-512;
+/// Whether it fits the memory of the machine it's loaded in is checked by `load_rom`.
+const MAX_ROM_SIZE: usize = XO_CHIP_MEMORY_SIZE - 512;
 
-#[derive(Debug, Clone, Copy)]
+/// The first bytes of gzip and zip files
+#[cfg(feature = "std")]
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+#[cfg(feature = "std")]
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+
+/// Extensions of the files taken for ROMs inside zip archives
+#[cfg(feature = "std")]
+pub const ROM_EXTENSIONS: [&str; 3] = ["ch8", "c8", "sc8"];
+
+#[derive(Debug, Clone)]
 pub struct Cartridge {
     // The data in the ROM
-    pub data: [u8; MAX_ROM_SIZE as usize],
-    // How many bytes are in the ROM
-    pub size: u16
+    pub data: Vec<u8>,
+    // What's known about the ROM, worked out as it's loaded
+    pub info: CartridgeInfo
+}
+
+/// The machines a ROM may be written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Variant {
+    /// Guesses the machine `rom` was written for from the opcodes it uses
+    /// that only the extensions have: the SUPER-CHIP scrolls, exit, resolution
+    /// changes, large font and RPL flags, and XO-CHIP's long loads, audio pattern,
+    /// plane selection and register range saves and loads.
+    /// Data mixed with the code may be mistaken for them, so it's only a guess.
+    pub fn detect(rom: &[u8]) -> Variant {
+        let mut variant = Variant::Chip8;
+        for word in rom.chunks_exact(2) {
+            let opcode = u16::from_be_bytes([word[0], word[1]]);
+            match opcode {
+                0xF000 | 0xF002 => return Variant::XoChip,
+                opcode if opcode & 0xF0FF == 0xF001 => return Variant::XoChip,
+                // Saving and loading a range of registers, 5XY2 and 5XY3
+                opcode if opcode & 0xF00E == 0x5002 => return Variant::XoChip,
+                0x00C1..=0x00CF | 0x00FB..=0x00FF => variant = Variant::SuperChip,
+                // The large font and the RPL flags
+                opcode if matches!(opcode & 0xF0FF, 0xF030 | 0xF075 | 0xF085) => variant = Variant::SuperChip,
+                _ => {}
+            }
+        }
+        variant
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Variant::Chip8 => write!(f, "CHIP-8"),
+            Variant::SuperChip => write!(f, "SUPER-CHIP"),
+            Variant::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+/// What's known about a ROM
+#[derive(Debug, Clone, PartialEq)]
+pub struct CartridgeInfo {
+    // The SHA-1 digest of the ROM in lowercase hexadecimal, which identifies it
+    pub sha1: String,
+    // The name of the file without its extension, empty if it wasn't loaded from one
+    pub name: String,
+    // Size of the ROM in bytes
+    pub size: usize,
+    // The machine the ROM seems to be written for
+    pub variant: Variant,
+}
+
+impl CartridgeInfo {
+    /// Works out what it can about `rom`, which has no name yet
+    pub fn new(rom: &[u8]) -> CartridgeInfo {
+        CartridgeInfo {
+            sha1: sha1_hex(rom),
+            name: String::new(),
+            size: rom.len(),
+            variant: Variant::detect(rom),
+        }
+    }
+}
+
+impl fmt::Display for CartridgeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} bytes, {}, SHA-1 {}", self.name, self.size, self.variant, self.sha1)
+    }
 }
 
 impl Cartridge {
-    pub fn new(filename: String) ->  Result<Cartridge, Error>
+    /// Loads a ROM from the file at `path`.
+    /// Gzip files are decompressed, and zip files are searched for the only ROM in them,
+    /// or for a given member when the path is written like `pack.zip:PONG.ch8`.
+    /// Either way the ROM must fit in memory once decompressed.
+    /// Errors reading the file name it.
+    #[cfg(feature = "std")]
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Cartridge, Error>
+    {
+        let (path, member) = split_member(path.as_ref());
+        Cartridge::open(path, member).map_err(|err| with_path(err, path))
+    }
+
+    /// Loads a ROM from the file at `path`, or from its `member` if it's a zip archive
+    #[cfg(feature = "std")]
+    fn open(path: &Path, member: Option<&str>) -> Result<Cartridge, Error>
     {
-        let mut file = File::open(filename).expect("File not found!");
-        let mut buffer = [0_u8; MAX_ROM_SIZE as usize];
+        let mut reader = BufReader::new(File::open(path).map_err(read_error)?);
 
-        let file_size = file.metadata().unwrap().len();
-        if file_size > (MAX_ROM_SIZE as u64)  {
-            return Err(Error::new(ErrorKind::Other, "The supplied ROM is too big."));
+        // Archives are told apart by their first bytes, whatever their extension
+        let magic = reader.fill_buf().map_err(read_error)?;
+        let gzip = magic.starts_with(&GZIP_MAGIC);
+        let zip = magic.starts_with(&ZIP_MAGIC);
+        if zip || member.is_some() {
+            return Cartridge::from_zip(reader, member);
         }
-    
-        let rom_size = if let Ok(bytes_read) = file.read(&mut buffer) {
-            bytes_read
+        let mut cartridge = if gzip {
+            Cartridge::from_reader(GzDecoder::new(reader))?
         } else {
-            return Err(Error::new(ErrorKind::Other, "There's been a problem reading the ROM."));
+            // Pipes and other special files have no meaningful length
+            let metadata = reader.get_ref().metadata().map_err(read_error)?;
+            let size = if metadata.is_file() { Some(metadata.len()) } else { None };
+            Cartridge::from_reader(reader).map_err(|err| with_size(err, size))?
         };
-    
+        cartridge.info.name = stem(path);
+        if gzip {
+            // PONG.ch8.gz is called PONG too
+            cartridge.info.name = stem(Path::new(&cartridge.info.name));
+        }
+        Ok(cartridge)
+    }
+
+    /// Loads the ROM called `member` out of a zip archive, or the only ROM in it if not given.
+    /// The name of the ROM is the name of the member.
+    #[cfg(feature = "std")]
+    fn from_zip<R: Read + Seek>(reader: R, member: Option<&str>) -> Result<Cartridge, Error>
+    {
+        let mut archive = ZipArchive::new(reader).map_err(|err| read_error(err.into()))?;
+        let name = match member {
+            Some(member) => member.to_string(),
+            None => {
+                let mut roms: Vec<String> = archive
+                    .file_names()
+                    .filter(|name| is_rom_name(name))
+                    .map(str::to_string)
+                    .collect();
+                match roms.len() {
+                    0 => return Err(Error::NoRomInArchive),
+                    1 => roms.remove(0),
+                    _ => {
+                        roms.sort();
+                        return Err(Error::SeveralRomsInArchive(roms));
+                    }
+                }
+            }
+        };
+        let file = match archive.by_name(&name) {
+            Ok(file) => file,
+            Err(ZipError::FileNotFound) => return Err(Error::RomNotInArchive(name)),
+            Err(err) => return Err(read_error(err.into())),
+        };
+        let size = file.size();
+        let mut cartridge = Cartridge::from_reader(file).map_err(|err| with_size(err, Some(size)))?;
+        cartridge.info.name = stem(Path::new(&name));
+        Ok(cartridge)
+    }
+
+    /// Loads a ROM from any source of bytes, such as stdin or a network stream.
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: Read>(reader: R) -> Result<Cartridge, Error>
+    {
+        // Reading one byte past the limit tells apart ROMs that fit exactly
+        // from those that are too big, without reading the whole source
+        let mut bytes = Vec::new();
+        if let Err(err) = reader.take(MAX_ROM_SIZE as u64 + 1).read_to_end(&mut bytes) {
+            return Err(read_error(err));
+        }
+        if bytes.len() > MAX_ROM_SIZE {
+            return Err(Error::RomTooLarge { size: None, max: MAX_ROM_SIZE });
+        }
+
+        Cartridge::from_bytes(&bytes)
+    }
+
+    /// Builds a cartridge out of a ROM image already in memory,
+    /// such as one embedded in the executable.
+    /// An odd size is allowed, since the last byte may be data, but warned about.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Cartridge, Error>
+    {
+        if bytes.is_empty() {
+            return Err(Error::EmptyRom);
+        }
+        if bytes.len() > MAX_ROM_SIZE {
+            return Err(Error::RomTooLarge { size: Some(bytes.len()), max: MAX_ROM_SIZE });
+        }
+        if !bytes.len().is_multiple_of(2) {
+            warn!("The ROM has an odd size, {} bytes, while instructions are 2 bytes long.", bytes.len());
+        }
+
         Ok(Cartridge {
-            data: buffer,
-            size: rom_size as u16
+            data: bytes.to_vec(),
+            info: CartridgeInfo::new(bytes)
         })
     }
-}
\ No newline at end of file
+
+    /// Size of the ROM in bytes
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+/// Splits a path like `pack.zip:PONG.ch8` into the archive and the member in it
+#[cfg(feature = "std")]
+fn split_member(path: &Path) -> (&Path, Option<&str>) {
+    if let Some((archive, member)) = path.to_str().and_then(|path| path.rsplit_once(':')) {
+        if archive.to_ascii_lowercase().ends_with(".zip") {
+            return (Path::new(archive), Some(member));
+        }
+    }
+    (path, None)
+}
+
+/// Fills in the `size` of a too large ROM read from a stream, now that it's known
+#[cfg(feature = "std")]
+fn with_size(err: Error, size: Option<u64>) -> Error {
+    match err {
+        Error::RomTooLarge { size: None, max } => Error::RomTooLarge { size: size.map(|size| size as usize), max },
+        err => err,
+    }
+}
+
+/// A failure to read a ROM, from a source that isn't known yet
+#[cfg(feature = "std")]
+fn read_error(source: io::Error) -> Error {
+    Error::RomRead { path: None, source }
+}
+
+/// Fills in the `path` a ROM failed to be read from
+#[cfg(feature = "std")]
+fn with_path(err: Error, path: &Path) -> Error {
+    match err {
+        Error::RomRead { path: None, source } => Error::RomRead { path: Some(path.to_path_buf()), source },
+        err => err,
+    }
+}
+
+/// The name of the file at `path` without its extension
+#[cfg(feature = "std")]
+fn stem(path: &Path) -> String {
+    path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Whether the file called `name`, in an archive or a directory, has the extension of a ROM
+#[cfg(feature = "std")]
+pub fn is_rom_name(name: &str) -> bool {
+    match Path::new(name).extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => ROM_EXTENSIONS.iter().any(|rom| extension.eq_ignore_ascii_case(rom)),
+        None => false,
+    }
+}
+
+/// The SHA-1 digest of `data`, in lowercase hexadecimal
+pub fn sha1_hex(data: &[u8]) -> String {
+    sha1_smol::Sha1::from(data).digest().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_a_cartridge_from_bytes() {
+        let cartridge = Cartridge::from_bytes(&[0x00, 0xE0, 0x12, 0x02]).unwrap();
+        assert_eq!(cartridge.data[..4], [0x00, 0xE0, 0x12, 0x02]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn reads_a_cartridge_from_a_reader() {
+        let bytes = [0x60, 0x0C, 0x61, 0x08, 0xD0, 0x1F];
+        let cartridge = Cartridge::from_reader(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(cartridge.data[..6], bytes);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn a_missing_file_is_an_error() {
+        let result = Cartridge::from_file("does/not/exist");
+        let err = result.unwrap_err();
+        assert!(matches!(&err, Error::RomRead { path: Some(path), .. } if path == Path::new("does/not/exist")));
+        assert!(err.to_string().starts_with("could not read the ROM does/not/exist: "), "{}", err);
+
+        // Inside an archive, the archive is named
+        let err = Cartridge::from_file("does/not/exist.zip:PONG.ch8").unwrap_err();
+        assert!(err.to_string().starts_with("could not read the ROM does/not/exist.zip: "), "{}", err);
+    }
+
+    #[test]
+    fn rejects_empty_and_too_large_roms() {
+        assert!(matches!(Cartridge::from_bytes(&[]), Err(Error::EmptyRom)));
+        let result = Cartridge::from_bytes(&vec![0; MAX_ROM_SIZE + 1]);
+        assert!(matches!(result, Err(Error::RomTooLarge { size: Some(size), max: MAX_ROM_SIZE }) if size == MAX_ROM_SIZE + 1));
+        assert!(Cartridge::from_bytes(&vec![0; MAX_ROM_SIZE]).is_ok());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn stops_reading_too_large_roms() {
+        // The size of a stream isn't known past the limit
+        let result = Cartridge::from_reader(std::io::repeat(0));
+        assert!(matches!(result, Err(Error::RomTooLarge { size: None, max: MAX_ROM_SIZE })));
+    }
+
+    /// A path of its own in the temporary directory, for the fixture called `name`
+    #[cfg(feature = "std")]
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("lascaoito-{}-{}", std::process::id(), name))
+    }
+
+    /// Writes a zip archive holding each of `files`, as name and contents
+    #[cfg(feature = "std")]
+    fn write_zip(name: &str, files: &[(&str, &[u8])]) -> std::path::PathBuf {
+        use std::io::Write;
+        let path = temp_path(name);
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn reads_a_gzipped_rom() {
+        use std::io::Write;
+        let path = temp_path("PONG.ch8.gz");
+        let mut gz = flate2::write::GzEncoder::new(File::create(&path).unwrap(), flate2::Compression::default());
+        gz.write_all(&[0x00, 0xE0, 0x12, 0x02]).unwrap();
+        gz.finish().unwrap();
+        let cartridge = Cartridge::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let cartridge = cartridge.unwrap();
+        assert_eq!(cartridge.data, [0x00, 0xE0, 0x12, 0x02]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn reads_the_rom_in_a_zip() {
+        let path = write_zip("one.zip", &[("README.txt", b"hi"), ("roms/PONG.ch8", &[0x00, 0xE0])]);
+        let cartridge = Cartridge::from_file(&path);
+        let member = Cartridge::from_file(format!("{}:README.txt", path.display()));
+        let missing = Cartridge::from_file(format!("{}:TETRIS.ch8", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        let cartridge = cartridge.unwrap();
+        assert_eq!(cartridge.data, [0x00, 0xE0]);
+        // Any member can be picked by name
+        assert_eq!(member.unwrap().data, b"hi");
+        assert!(matches!(missing, Err(Error::RomNotInArchive(name)) if name == "TETRIS.ch8"));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn limits_the_decompressed_size() {
+        use std::io::Write;
+        // Small once compressed, too large once not
+        let big = vec![0; MAX_ROM_SIZE + 1];
+        let gz_path = temp_path("BIG.ch8.gz");
+        let mut gz = flate2::write::GzEncoder::new(File::create(&gz_path).unwrap(), flate2::Compression::default());
+        gz.write_all(&big).unwrap();
+        gz.finish().unwrap();
+        let zip_path = write_zip("big.zip", &[("BIG.ch8", &big)]);
+        let (gz_result, zip_result) = (Cartridge::from_file(&gz_path), Cartridge::from_file(&zip_path));
+        std::fs::remove_file(&gz_path).unwrap();
+        std::fs::remove_file(&zip_path).unwrap();
+        // Gzip streams don't tell their decompressed size, zip members do
+        assert!(matches!(gz_result, Err(Error::RomTooLarge { size: None, max: MAX_ROM_SIZE })));
+        assert!(matches!(zip_result, Err(Error::RomTooLarge { size: Some(size), max: MAX_ROM_SIZE }) if size == MAX_ROM_SIZE + 1));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn zips_need_exactly_one_rom() {
+        let none = write_zip("none.zip", &[("README.txt", b"hi")]);
+        let several = write_zip("several.zip", &[("b.ch8", &[0x00, 0xE0]), ("a.sc8", &[0x00, 0xE0])]);
+        let (none_result, several_result) = (Cartridge::from_file(&none), Cartridge::from_file(&several));
+        std::fs::remove_file(&none).unwrap();
+        std::fs::remove_file(&several).unwrap();
+        assert!(matches!(none_result, Err(Error::NoRomInArchive)));
+        assert!(matches!(several_result, Err(Error::SeveralRomsInArchive(names)) if names == ["a.sc8", "b.ch8"]));
+    }
+
+    #[test]
+    fn hashes_the_rom() {
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        let info = Cartridge::from_bytes(b"abc").unwrap().info;
+        assert_eq!(info.sha1, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(info.size, 3);
+    }
+
+    #[test]
+    fn detects_the_variant() {
+        // CLS; JP 0x200
+        assert_eq!(Variant::detect(&[0x00, 0xE0, 0x12, 0x00]), Variant::Chip8);
+        // HIGH; SCR; EXIT
+        assert_eq!(Variant::detect(&[0x00, 0xFF]), Variant::SuperChip);
+        assert_eq!(Variant::detect(&[0x00, 0xE0, 0x00, 0xFB]), Variant::SuperChip);
+        assert_eq!(Variant::detect(&[0x00, 0xFD]), Variant::SuperChip);
+        // SCD 4; LD HF,V3; LD R,V7; LD V2,R
+        assert_eq!(Variant::detect(&[0x00, 0xC4]), Variant::SuperChip);
+        assert_eq!(Variant::detect(&[0xF3, 0x30]), Variant::SuperChip);
+        assert_eq!(Variant::detect(&[0xF7, 0x75]), Variant::SuperChip);
+        assert_eq!(Variant::detect(&[0xF2, 0x85]), Variant::SuperChip);
+        // SAVE V1-V4; LOAD V1-V4
+        assert_eq!(Variant::detect(&[0x51, 0x42]), Variant::XoChip);
+        assert_eq!(Variant::detect(&[0x00, 0xFF, 0x51, 0x43]), Variant::XoChip);
+        // LD I,long; AUDIO; PLANE 2, even after SUPER-CHIP instructions
+        assert_eq!(Variant::detect(&[0xF0, 0x00, 0x12, 0x34]), Variant::XoChip);
+        assert_eq!(Variant::detect(&[0x00, 0xFF, 0xF0, 0x02]), Variant::XoChip);
+        assert_eq!(Variant::detect(&[0x00, 0xFF, 0xF2, 0x01]), Variant::XoChip);
+        // Only whole words count: 0x00FF split across two is no opcode
+        assert_eq!(Variant::detect(&[0x12, 0x00, 0xFF, 0x00]), Variant::Chip8);
+        assert_eq!(Cartridge::from_bytes(&[0x00, 0xFF]).unwrap().info.variant, Variant::SuperChip);
+        assert_eq!(Variant::XoChip.to_string(), "XO-CHIP");
+    }
+
+    #[test]
+    fn loads_odd_sized_roms() {
+        // Only warned about, since the last byte may be data
+        let cartridge = Cartridge::from_bytes(&[0x12, 0x00, 0xFF]).unwrap();
+        assert_eq!(cartridge.size(), 3);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn reports_the_size_of_a_too_large_file() {
+        let path = temp_path("big.ch8");
+        std::fs::write(&path, vec![0; MAX_ROM_SIZE + 10]).unwrap();
+        let result = Cartridge::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let err = result.unwrap_err();
+        assert!(matches!(err, Error::RomTooLarge { size: Some(size), max: MAX_ROM_SIZE } if size == MAX_ROM_SIZE + 10));
+        assert_eq!(err.to_string(), "the supplied ROM is too big: 65034 bytes, over the limit of 65024.");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn empty_files_are_rejected() {
+        let path = temp_path("empty.ch8");
+        std::fs::write(&path, []).unwrap();
+        let result = Cartridge::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(Error::EmptyRom)));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::quirks::Quirks;
+use crate::rom::CartridgeInfo;
+// The digests keying the database, which used to be worked out here
+pub use crate::rom::sha1_hex;
+use crate::timing;
+use std::collections::HashMap;
+use crate::error::Error;
+use std::fs;
+
+/// Settings known to suit a specific ROM
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomSettings {
+    // Human-readable name of the ROM
+    pub name: String,
+    // Quirks the ROM relies on
+    pub quirks: Quirks,
+    // Instructions per second the ROM was designed for, if known
+    pub ips: Option<u32>,
+}
+
+/// An entry of the built-in database
+struct BuiltinEntry {
+    sha1: &'static str,
+    name: &'static str,
+    quirks: &'static str,
+    ips: Option<u32>,
+}
+
+/// ROMs whose needs are known in advance.
+/// Octo programs expect Octo's defaults: I incremented by FX55/FX65 and 20 instructions per frame.
+const BUILTIN: [BuiltinEntry; 3] = [
+    BuiltinEntry {
+        sha1: "018442698067c95d67e27a94e6642c11f049f108",
+        name: "1dcell",
+        quirks: "memory",
+        ips: Some(1200),
+    },
+    BuiltinEntry {
+        sha1: "39970ccfd3a3f00180d53464d4fd7862193eaf0f",
+        name: "octoachip8story",
+        quirks: "memory",
+        ips: Some(1200),
+    },
+    BuiltinEntry {
+        sha1: "d6d8efef811350e7fba6197024c4973b360749b8",
+        name: "octojam5title",
+        quirks: "memory",
+        ips: Some(1200),
+    },
+];
+
+/// Maps the SHA-1 digest of ROMs to their recommended settings
+pub struct RomDatabase {
+    entries: HashMap<String, RomSettings>,
+}
+
+impl RomDatabase {
+    /// Creates a database holding only the built-in entries
+    pub fn builtin() -> RomDatabase {
+        let mut entries = HashMap::new();
+        for entry in BUILTIN.iter() {
+            // The built-in quirk lists are known to be valid
+            let quirks = Quirks::parse(entry.quirks).unwrap_or_default();
+            entries.insert(
+                entry.sha1.to_string(),
+                RomSettings {
+                    name: entry.name.to_string(),
+                    quirks,
+                    ips: entry.ips,
+                },
+            );
+        }
+        RomDatabase { entries }
+    }
+
+    /// Adds the entries of a user-provided TOML file, which take precedence
+    /// over the built-in ones. Every table is keyed by the SHA-1 of a ROM:
+    ///
+    /// ```toml
+    /// [018442698067c95d67e27a94e6642c11f049f108]
+    /// name = "1dcell"
+    /// quirks = "shift,memory"
+    /// ips = 1200
+    /// ```
+    pub fn load_file(&mut self, path: &str) -> Result<(), Error> {
+        let contents = fs::read_to_string(path)?;
+        self.load_toml(&contents)
+    }
+
+    /// Adds the entries of a TOML document, as described in `load_file`
+    pub fn load_toml(&mut self, contents: &str) -> Result<(), Error> {
+        let document = contents.parse::<toml::Value>().map_err(|err| Error::InvalidRomDatabase(Some(err)))?;
+        let tables = match document.as_table() {
+            Some(tables) => tables,
+            None => return Err(Error::InvalidRomDatabase(None)),
+        };
+
+        for (sha1, entry) in tables.iter() {
+            let invalid = |field| Error::InvalidRomDatabaseEntry { sha1: sha1.clone(), field };
+            let name = match entry.get("name") {
+                Some(name) => name.as_str().ok_or_else(|| invalid("name"))?.to_string(),
+                None => sha1.clone(),
+            };
+            let quirks = match entry.get("quirks") {
+                Some(quirks) => Quirks::parse(quirks.as_str().ok_or_else(|| invalid("quirks"))?)?,
+                None => Quirks::default_set(),
+            };
+            let ips = match entry.get("ips") {
+                Some(ips) => match ips.as_integer() {
+                    Some(ips) if ips > 0 && ips <= u32::MAX as i64 => Some(ips as u32),
+                    _ => return Err(invalid("ips")),
+                },
+                None => None,
+            };
+            self.entries
+                .insert(sha1.to_lowercase(), RomSettings { name, quirks, ips });
+        }
+        Ok(())
+    }
+
+    /// Looks up the settings recommended for the given ROM
+    pub fn lookup(&self, rom: &CartridgeInfo) -> Option<&RomSettings> {
+        self.entries.get(&rom.sha1)
+    }
+
+    /// The quirks and instructions per second to run the given ROM with: `quirks` and `ips`,
+    /// when given explicitly, take precedence over what the database recommends,
+    /// then come the defaults
+    pub fn settings(&self, rom: &CartridgeInfo, quirks: Option<Quirks>, ips: Option<u32>) -> (Quirks, u32) {
+        let recommended = self.lookup(rom);
+        let quirks = quirks
+            .or_else(|| recommended.map(|settings| settings.quirks))
+            .unwrap_or_else(Quirks::default_set);
+        let ips = ips
+            .or_else(|| recommended.and_then(|settings| settings.ips))
+            .unwrap_or(timing::DEFAULT_IPS);
+        (quirks, ips)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Variant;
+
+    /// What's known about a ROM whose digest is `sha1`
+    fn rom(sha1: &str) -> CartridgeInfo {
+        CartridgeInfo { sha1: sha1.to_string(), name: String::new(), size: 2, variant: Variant::Chip8 }
+    }
+
+    const ONE_D_CELL: &str = "018442698067c95d67e27a94e6642c11f049f108";
+    const UNKNOWN: &str = "0000000000000000000000000000000000000000";
+
+    #[test]
+    fn finds_builtin_entries() {
+        let db = RomDatabase::builtin();
+        let settings = db.lookup(&rom(ONE_D_CELL)).unwrap();
+        assert_eq!(settings.name, "1dcell");
+        assert_eq!(settings.quirks, Quirks::parse("memory").unwrap());
+        assert_eq!(settings.ips, Some(1200));
+        assert!(db.lookup(&rom(UNKNOWN)).is_none());
+    }
+
+    #[test]
+    fn file_entries_replace_builtin_ones() {
+        let mut db = RomDatabase::builtin();
+        db.load_toml(&format!(
+            "[{}]\nquirks = \"shift\"\n\n[{}]\nname = \"pong\"\nips = 700\n",
+            ONE_D_CELL,
+            UNKNOWN.to_uppercase()
+        ))
+        .unwrap();
+        let settings = db.lookup(&rom(ONE_D_CELL)).unwrap();
+        assert_eq!(settings.name, ONE_D_CELL);
+        assert_eq!(settings.quirks, Quirks::parse("shift").unwrap());
+        assert_eq!(settings.ips, None);
+        // Digests are matched in lowercase, and entries without quirks get the default ones
+        let settings = db.lookup(&rom(UNKNOWN)).unwrap();
+        assert_eq!(settings.name, "pong");
+        assert_eq!(settings.quirks, Quirks::default_set());
+        assert_eq!(settings.ips, Some(700));
+    }
+
+    #[test]
+    fn rejects_bad_entries() {
+        let mut db = RomDatabase::builtin();
+        assert!(db.load_toml("[a\n").is_err());
+        assert!(db.load_toml("[abc]\nips = 0\n").is_err());
+        assert!(db.load_toml("[abc]\nname = 3\n").is_err());
+        assert!(db.load_toml("[abc]\nquirks = \"shfit\"\n").is_err());
+    }
+
+    #[test]
+    fn explicit_settings_take_precedence() {
+        // JP 0x200
+        let rom = CartridgeInfo::new(&[0x12, 0x00]);
+        let other = CartridgeInfo::new(&[0x00, 0xE0]);
+        let mut db = RomDatabase::builtin();
+        db.load_toml(&format!("[{}]\nquirks = \"memory\"\nips = 1200\n", sha1_hex(&[0x12, 0x00]))).unwrap();
+        let shift = Quirks::parse("shift").unwrap();
+        let memory = Quirks::parse("memory").unwrap();
+        assert_eq!(db.settings(&rom, None, None), (memory, 1200));
+        assert_eq!(db.settings(&rom, Some(shift), None), (shift, 1200));
+        assert_eq!(db.settings(&rom, None, Some(900)), (memory, 900));
+        assert_eq!(db.settings(&other, None, None), (Quirks::default_set(), timing::DEFAULT_IPS));
+        assert_eq!(db.settings(&other, Some(shift), Some(900)), (shift, 900));
+    }
+}
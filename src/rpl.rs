@@ -0,0 +1,77 @@
+use crate::error::Error;
+use crate::rom::CartridgeInfo;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+pub use crate::chip8::RPL_FLAG_COUNT;
+
+/// Where the RPL flags of the given ROM are kept:
+/// `$XDG_DATA_HOME/lascaoito/flags/<SHA-1 of the ROM>`, falling back to `~/.local/share`.
+pub fn flags_path(rom: &CartridgeInfo) -> Option<PathBuf> {
+    let data_dir = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("share"),
+    };
+    Some(data_dir.join("lascaoito").join("flags").join(&rom.sha1))
+}
+
+/// Reads previously saved flags. A missing file means no flags were saved yet,
+/// in which case all of them are zero.
+pub fn load(path: &Path) -> Result<[u8; RPL_FLAG_COUNT], Error> {
+    let mut flags = [0; RPL_FLAG_COUNT];
+    let saved = match fs::read(path) {
+        Ok(saved) => saved,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(flags),
+        Err(err) => return Err(err.into()),
+    };
+    if saved.len() != RPL_FLAG_COUNT {
+        return Err(Error::InvalidFlagsFile(path.to_path_buf()));
+    }
+    flags.copy_from_slice(&saved);
+    Ok(flags)
+}
+
+/// Writes the flags, creating the directories leading to `path` if needed
+pub fn save(path: &Path, flags: &[u8; RPL_FLAG_COUNT]) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, flags)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path of its own in the temporary directory, for the test called `name`
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("lascaoito-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn saves_and_loads_flags() {
+        let dir = temp_path("rpl");
+        let path = dir.join("flags").join("abc");
+        save(&path, &[9, 8, 7, 6, 5, 4, 3, 2]).unwrap();
+        let loaded = load(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.unwrap(), [9, 8, 7, 6, 5, 4, 3, 2]);
+    }
+
+    #[test]
+    fn missing_flags_are_zero() {
+        assert_eq!(load(&temp_path("rpl-missing")).unwrap(), [0; RPL_FLAG_COUNT]);
+    }
+
+    #[test]
+    fn rejects_a_file_of_the_wrong_size() {
+        let path = temp_path("rpl-short");
+        fs::write(&path, [1, 2, 3]).unwrap();
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+    }
+}
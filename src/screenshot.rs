@@ -0,0 +1,186 @@
+use crate::chip8::VirtualMachine;
+use crate::error::Error;
+use crate::render::pixel_color;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An RGB image, three bytes per pixel, row by row
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Paints the screen of the VM, in its current resolution, into an image
+/// where every CHIP-8 pixel becomes a `scale` x `scale` square colored
+/// by `palette`, which is indexed by the planes the pixel is set on, and inverted
+/// like the window is with --invert, see `render::pixel_color`.
+pub fn render(vm: &VirtualMachine, palette: &[[u8; 3]; 4], invert: bool, scale: u32) -> Image {
+    let framebuffer = vm.framebuffer();
+    let scale = scale as usize;
+    let width = framebuffer.width() * scale;
+    let height = framebuffer.height() * scale;
+    let mut pixels = Vec::with_capacity(width * height * 3);
+
+    for row in framebuffer.rows() {
+        // Build one scaled line, then repeat it `scale` times
+        let mut line = Vec::with_capacity(width * 3);
+        for pixel in row {
+            let color = pixel_color(palette, pixel, invert);
+            for _ in 0..scale {
+                line.extend_from_slice(&color);
+            }
+        }
+        for _ in 0..scale {
+            pixels.extend_from_slice(&line);
+        }
+    }
+
+    Image {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    }
+}
+
+/// Draws the screen of the VM, in its current resolution, as text:
+/// one line per row, with `#` for set pixels and `.` for unset ones.
+pub fn render_ascii(vm: &VirtualMachine) -> String {
+    let framebuffer = vm.framebuffer();
+    let mut text = String::with_capacity((framebuffer.width() + 1) * framebuffer.height());
+    for row in framebuffer.rows() {
+        for pixel in row {
+            text.push(if pixel == 0 { '.' } else { '#' });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Encodes the image as PNG
+pub fn write_png<W: Write>(image: &Image, w: W) -> Result<(), Error> {
+    let mut encoder = png::Encoder::new(w, image.width, image.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(Error::Png)?;
+    writer.write_image_data(&image.pixels).map_err(Error::Png)
+}
+
+/// Saves the image as a PNG in the current directory.
+/// The file is named after the current time so that screenshots never overwrite
+/// each other. Returns the name of the file written.
+pub fn save(image: &Image) -> Result<String, Error> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or(0);
+    let filename = format!("lascaoito-{}.png", timestamp);
+    let file = File::create(&filename)?;
+    write_png(image, BufWriter::new(file))?;
+    Ok(filename)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Cartridge;
+
+    const PALETTE: [[u8; 3]; 4] = [[1, 2, 3], [200, 100, 50], [0, 0, 0], [0, 0, 0]];
+
+    /// A machine showing two pixels, at (1, 2) and (5, 2)
+    fn two_pixels() -> VirtualMachine {
+        // The top row of the 0 glyph is 0xF0: drawn twice, one pixel apart,
+        // only the ends stay lit
+        // LD V0,1; LD V1,2; LD V2,2; LD V3,0; LD F,V3; DRW V0,V1,1; DRW V2,V1,1
+        let rom = [0x60, 0x01, 0x61, 0x02, 0x62, 0x02, 0x63, 0x00, 0xF3, 0x29, 0xD0, 0x11, 0xD2, 0x11];
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&Cartridge::from_bytes(&rom).unwrap()).unwrap();
+        for _ in 0..rom.len() / 2 {
+            vm.run_cycle().unwrap();
+        }
+        vm
+    }
+
+    #[test]
+    fn writes_a_png_of_the_screen() {
+        let image = render(&two_pixels(), &PALETTE, false, 3);
+        let mut png = Vec::new();
+        write_png(&image, &mut png).unwrap();
+
+        let decoder = png::Decoder::new(png.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (64 * 3, 32 * 3));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+
+        let pixel = |x: usize, y: usize| {
+            let start = (y * 64 * 3 + x) * 3;
+            [pixels[start], pixels[start + 1], pixels[start + 2]]
+        };
+        // (1, 2) scaled three times covers (3..6, 6..9)
+        assert_eq!(pixel(3, 6), [200, 100, 50]);
+        assert_eq!(pixel(5, 8), [200, 100, 50]);
+        assert_eq!(pixel(6, 8), [1, 2, 3]);
+        assert_eq!(pixel(0, 0), [1, 2, 3]);
+    }
+
+    #[test]
+    fn draws_the_screen_as_text() {
+        let text = render_ascii(&two_pixels());
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert_eq!(lines[2], format!(".#...#{}", ".".repeat(58)));
+        assert_eq!(text.matches('#').count(), 2);
+    }
+
+    /// The IBM logo program, which draws the logo in six sprites and then spins
+    const IBM_LOGO: [u8; 132] = [
+        0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08, 0xD0, 0x1F, 0x70, 0x09, 0xA2, 0x39, 0xD0, 0x1F,
+        0xA2, 0x48, 0x70, 0x08, 0xD0, 0x1F, 0x70, 0x04, 0xA2, 0x57, 0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x66,
+        0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x75, 0xD0, 0x1F, 0x12, 0x28, 0xFF, 0x00, 0xFF, 0x00, 0x3C, 0x00,
+        0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0xFF, 0x00, 0xFF, 0xFF, 0x00, 0xFF, 0x00, 0x38, 0x00, 0x3F,
+        0x00, 0x3F, 0x00, 0x38, 0x00, 0xFF, 0x00, 0xFF, 0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00,
+        0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0xF8, 0x00, 0xFC, 0x00, 0x3E, 0x00, 0x3F, 0x00, 0x3B,
+        0x00, 0x39, 0x00, 0xF8, 0x00, 0xF8, 0x03, 0x00, 0x07, 0x00, 0x0F, 0x00, 0xBF, 0x00, 0xFB, 0x00,
+        0xF3, 0x00, 0xE3, 0x00, 0x43, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80,
+        0x00, 0xE0, 0x00, 0xE0,
+    ];
+
+    #[test]
+    fn draws_the_ibm_logo_as_text() {
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&Cartridge::from_bytes(&IBM_LOGO).unwrap()).unwrap();
+        for _ in 0..100 {
+            vm.run_cycle().unwrap();
+        }
+        let text = render_ascii(&vm);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 32);
+        let logo = [
+            "########.#########...#####.........#####",
+            "",
+            "########.###########.######.......######",
+            "",
+            "..####.....###...###...#####.....#####..",
+            "",
+            "..####.....#######.....#######.#######..",
+            "",
+            "..####.....#######.....###.#######.###..",
+            "",
+            "..####.....###...###...###..#####..###..",
+            "",
+            "########.###########.#####...###...#####",
+            "",
+            "########.#########...#####....#....#####",
+        ];
+        for (y, line) in lines.iter().enumerate() {
+            let expected = match y {
+                8..=22 if !logo[y - 8].is_empty() => format!("{0}{1}{0}", ".".repeat(12), logo[y - 8]),
+                _ => ".".repeat(64),
+            };
+            assert_eq!(*line, expected, "row {}", y);
+        }
+    }
+}
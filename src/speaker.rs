@@ -0,0 +1,70 @@
+use lascaoito::audio::{self, AudioSink, Beep, Tone};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::Sdl;
+
+/// Feeds SDL with whatever the VM is currently playing
+struct Speaker {
+    // None while the sound timer is stopped
+    tone: Option<Tone>,
+    beep: Beep,
+    sample_rate: u32,
+    phase: f64,
+}
+
+impl AudioCallback for Speaker {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        match &self.tone {
+            Some(tone) => audio::synthesize(tone, &self.beep, self.sample_rate, &mut self.phase, out),
+            None => {
+                for sample in out.iter_mut() {
+                    *sample = 0.0;
+                }
+                self.phase = 0.0;
+            }
+        }
+    }
+}
+
+/// Plays the sound of the VM through the default SDL audio device
+pub struct SdlAudio {
+    device: AudioDevice<Speaker>,
+    // What to play on the next `beep_on`
+    tone: Tone,
+}
+
+impl SdlAudio {
+    /// Opens the default audio device and starts it, silent until a tone is set.
+    /// The buzzer sounds as `beep` says.
+    pub fn open(sdl_context: &Sdl, beep: Beep) -> Result<SdlAudio, String> {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: Some(512),
+        };
+        let device = sdl_context.audio()?.open_playback(None, &desired_spec, |spec| {
+            Speaker { tone: None, beep, sample_rate: spec.freq as u32, phase: 0.0 }
+        })?;
+        device.resume();
+        Ok(SdlAudio { device, tone: Tone::Square })
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn beep_on(&mut self) {
+        self.device.lock().tone = Some(self.tone);
+    }
+
+    fn beep_off(&mut self) {
+        self.device.lock().tone = None;
+    }
+
+    fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+        let mut speaker = self.device.lock();
+        if speaker.tone.is_some() {
+            speaker.tone = Some(tone);
+        }
+    }
+}
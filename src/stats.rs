@@ -0,0 +1,172 @@
+use crate::instruction::Instruction;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+/// Counters describing how much work the emulator has done so far
+pub struct Stats {
+    // Instructions executed by the VM
+    pub instructions: u64,
+    // Frames drawn to the screen
+    pub frames: u64,
+    // Opcodes that could not be decoded
+    pub unknown_opcodes: u64,
+    // Instructions executed during the last frame, from one timer tick to the next
+    pub frame_instructions: u64,
+    // What `instructions` was when the current frame started
+    frame_start: u64,
+    // When the counting started
+    #[cfg(feature = "std")]
+    started: Instant,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            instructions: 0,
+            frames: 0,
+            unknown_opcodes: 0,
+            frame_instructions: 0,
+            frame_start: 0,
+            #[cfg(feature = "std")]
+            started: Instant::now(),
+        }
+    }
+
+    /// Closes the current frame, counting the instructions executed during it
+    pub fn end_frame(&mut self) {
+        self.frame_instructions = self.instructions - self.frame_start;
+        self.frame_start = self.instructions;
+    }
+
+    /// Wall-clock time elapsed since the counters were created
+    #[cfg(feature = "std")]
+    pub fn runtime(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats::new()
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Instructions executed: {}", self.instructions)?;
+        writeln!(f, "Unknown opcodes:       {}", self.unknown_opcodes)?;
+        write!(f, "Frames drawn:          {}", self.frames)?;
+        #[cfg(feature = "std")]
+        write!(f, "\nRuntime:               {:.2}s", self.runtime().as_secs_f64())?;
+        Ok(())
+    }
+}
+
+/// How many times each kind of instruction ran, by opcode pattern such as `8XY4`.
+/// Kept by the VM once enabled with `VirtualMachine::enable_opcode_stats`.
+#[derive(Clone, Default)]
+pub struct OpcodeStats {
+    counts: BTreeMap<&'static str, u64>,
+}
+
+impl OpcodeStats {
+    pub fn new() -> OpcodeStats {
+        OpcodeStats::default()
+    }
+
+    /// Counts one more run of `instruction`
+    pub fn count(&mut self, instruction: Instruction) {
+        *self.counts.entry(instruction.pattern()).or_insert(0) += 1;
+    }
+
+    /// How many times instructions of the given `pattern` ran, e.g. `get("DXYN")`
+    pub fn get(&self, pattern: &str) -> u64 {
+        self.counts.get(pattern).copied().unwrap_or(0)
+    }
+
+    /// Every pattern that ran at least once along with its count, in opcode order
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        self.counts.iter().map(|(pattern, count)| (*pattern, *count))
+    }
+}
+
+/// Lists the patterns that ran, the most frequent first
+impl fmt::Display for OpcodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut counts: Vec<(&str, u64)> = self.iter().collect();
+        counts.sort_by_key(|(_, count)| Reverse(*count));
+        write!(f, "Opcode counts:")?;
+        for (pattern, count) in counts {
+            write!(f, "\n  {:<7} {}", pattern, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::VirtualMachine;
+    use crate::rom::Cartridge;
+    use alloc::string::ToString;
+
+    fn vm_with(rom: &[u8]) -> VirtualMachine {
+        let cart = Cartridge::from_bytes(rom).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&cart).unwrap();
+        vm
+    }
+
+    #[test]
+    fn reports_every_counter() {
+        let mut stats = Stats::new();
+        stats.instructions = 1234;
+        stats.unknown_opcodes = 2;
+        stats.frames = 60;
+        let report = stats.to_string();
+        assert!(report.starts_with("Instructions executed: 1234\nUnknown opcodes:       2\nFrames drawn:          60"));
+    }
+
+    #[test]
+    fn counts_every_executed_instruction() {
+        // LD V0,0; ADD V0,1; SE V0,3; JP 0x202
+        let mut vm = vm_with(&[0x60, 0x00, 0x70, 0x01, 0x30, 0x03, 0x12, 0x02]);
+        for _ in 0..7 {
+            vm.run_cycle().unwrap();
+        }
+        assert_eq!(vm.stats.instructions, 7);
+        assert_eq!(vm.stats.unknown_opcodes, 0);
+    }
+
+    #[test]
+    fn counts_unknown_opcodes() {
+        let mut vm = vm_with(&[0x00, 0x01]);
+        assert!(vm.run_cycle().is_err());
+        assert_eq!(vm.stats.unknown_opcodes, 1);
+    }
+
+    #[test]
+    fn counts_each_kind_of_opcode() {
+        // LD V0,3; ADD V0,0xff; SE V0,0; JP 0x202; EXIT
+        let rom = [0x60, 0x03, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x02, 0x00, 0xFD];
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&Cartridge::from_bytes(&rom).unwrap()).unwrap();
+        assert!(vm.opcode_stats().is_none());
+        vm.enable_opcode_stats();
+        while !vm.is_halted() {
+            vm.run_cycle().unwrap();
+        }
+        let stats = vm.opcode_stats().unwrap();
+        assert_eq!(stats.get("6XNN"), 1);
+        assert_eq!(stats.get("7XNN"), 3);
+        assert_eq!(stats.get("3XNN"), 3);
+        assert_eq!(stats.get("1NNN"), 2);
+        assert_eq!(stats.get("00FD"), 1);
+        assert_eq!(stats.get("DXYN"), 0);
+        assert_eq!(stats.iter().map(|(_, count)| count).sum::<u64>(), vm.stats.instructions);
+    }
+}
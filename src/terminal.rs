@@ -0,0 +1,49 @@
+use lascaoito::render::{self, Framebuffer, RenderError, Renderer};
+use std::io::Write;
+
+// Moves the cursor to the top-left corner, so that frames overwrite each other
+const CURSOR_HOME: &str = "\x1b[H";
+// Blanks the whole terminal
+const CLEAR_SCREEN: &str = "\x1b[2J";
+
+/// Draws the screen of the VM as text, for --tui
+pub struct TerminalRenderer<W: Write> {
+    out: W,
+    // The frame drawn since the last `present`, if any
+    frame: Option<String>,
+    // The resolution of the last frame drawn, as the terminal must be cleared when it changes
+    size: (usize, usize),
+}
+
+impl<W: Write> TerminalRenderer<W> {
+    pub fn new(out: W) -> TerminalRenderer<W> {
+        TerminalRenderer { out, frame: None, size: (0, 0) }
+    }
+}
+
+impl<W: Write> Renderer for TerminalRenderer<W> {
+    fn clear(&mut self) -> Result<(), RenderError> {
+        write!(self.out, "{}{}", CLEAR_SCREEN, CURSOR_HOME).map_err(|err| RenderError(err.to_string()))
+    }
+
+    fn draw(&mut self, framebuffer: &Framebuffer) -> Result<(), RenderError> {
+        // A terminal is written over line by line, so the whole screen is drawn every time
+        let mut frame = render::render_terminal(framebuffer);
+        let size = (framebuffer.width(), framebuffer.height());
+        if size != self.size {
+            // Going down to a smaller resolution would leave parts of the bigger one behind
+            self.size = size;
+            frame.insert_str(0, CLEAR_SCREEN);
+        }
+        self.frame = Some(frame);
+        Ok(())
+    }
+
+    fn present(&mut self) -> Result<(), RenderError> {
+        // Unchanged frames aren't written again, which would only make the terminal flicker
+        if let Some(frame) = self.frame.take() {
+            write!(self.out, "{}{}", CURSOR_HOME, frame).map_err(|err| RenderError(err.to_string()))?;
+        }
+        self.out.flush().map_err(|err| RenderError(err.to_string()))
+    }
+}
@@ -0,0 +1,182 @@
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// How many frames are emulated per second.
+/// The delay and sound timers tick once per frame.
+pub const FRAME_RATE: u32 = 60;
+
+/// How long a frame lasts at `FRAME_RATE`
+pub const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / FRAME_RATE as u64);
+
+/// Instructions executed per second by default, i.e. 10 per frame.
+/// A good fit for most classic CHIP-8 games.
+pub const DEFAULT_IPS: u32 = 600;
+
+/// How many times faster the emulation runs while turbo is held, by default
+pub const DEFAULT_TURBO: u32 = 8;
+
+/// How long a frame lasts with the user's `delay` added on, see --delay.
+/// A frame runs the same number of cycles however long it lasts, so the delay slows
+/// the whole emulation down, timers included: with a delay of one `FRAME_DURATION`,
+/// everything runs at half speed. Turbo frames skip the delay, which would only slow them down.
+pub fn frame_target(delay: Duration, turbo: bool) -> Duration {
+    if turbo {
+        FRAME_DURATION
+    } else {
+        FRAME_DURATION + delay
+    }
+}
+
+/// Splits a number of instructions per second into per-frame batches.
+/// When the rate isn't a multiple of the frame rate, the leftover is carried
+/// over so that every second still runs exactly `ips` instructions.
+pub struct CycleBudget {
+    // Instructions per second
+    ips: u32,
+    // Accumulated fraction of an instruction, in 1/FRAME_RATE units
+    remainder: u32,
+}
+
+impl CycleBudget {
+    pub fn new(ips: u32) -> CycleBudget {
+        CycleBudget { ips, remainder: 0 }
+    }
+
+    /// How many cycles must be run in the next frame
+    pub fn next_frame(&mut self) -> u32 {
+        let total = self.ips + self.remainder;
+        self.remainder = total % FRAME_RATE;
+        total / FRAME_RATE
+    }
+
+    /// How many cycles must be run in the next frame when running `factor` times faster.
+    /// Only the number of cycles grows, frames (and so timers) keep their pace.
+    pub fn next_frame_scaled(&mut self, factor: u32) -> u32 {
+        self.next_frame().saturating_mul(factor)
+    }
+}
+
+/// Paces frames against a running deadline rather than each frame's own start,
+/// so that oversleeping in one frame is made up for in the next one and the
+/// frame rate doesn't drift.
+#[cfg(feature = "std")]
+pub struct FramePacer {
+    // When the current frame should end
+    deadline: Instant,
+}
+
+#[cfg(feature = "std")]
+impl FramePacer {
+    /// Starts pacing with a frame that begins at `start`
+    pub fn new(start: Instant) -> FramePacer {
+        FramePacer { deadline: start }
+    }
+
+    /// How long to sleep, at `now`, so that the current frame ends `target` after the previous one.
+    /// Frames that overran their deadline aren't slept on, and once more than a whole frame
+    /// behind, e.g. after the window was dragged, the pacer starts over instead of catching up.
+    pub fn frame_sleep(&mut self, target: Duration, now: Instant) -> Duration {
+        self.deadline += target;
+        if now > self.deadline + target {
+            self.deadline = now;
+        }
+        self.deadline.saturating_duration_since(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::VirtualMachine;
+    use crate::rom::Cartridge;
+    use alloc::vec::Vec;
+
+    /// The cycles of the first `frames` frames at `ips`
+    fn frames(ips: u32, frames: usize) -> Vec<u32> {
+        let mut budget = CycleBudget::new(ips);
+        (0..frames).map(|_| budget.next_frame()).collect()
+    }
+
+    #[test]
+    fn splits_the_rate_into_frames() {
+        assert_eq!(frames(DEFAULT_IPS, 3), [10, 10, 10]);
+        assert_eq!(frames(1800, 3), [30, 30, 30]);
+        assert_eq!(frames(60, 3), [1, 1, 1]);
+    }
+
+    #[test]
+    fn carries_the_leftover_over() {
+        // 12.5 a frame
+        assert_eq!(frames(750, 4), [12, 13, 12, 13]);
+        // Fewer instructions than frames
+        assert_eq!(frames(30, 4), [0, 1, 0, 1]);
+        // Every second runs exactly the rate asked for
+        for ips in [1, 59, 61, 500, 700, 1001] {
+            assert_eq!(frames(ips, FRAME_RATE as usize).iter().sum::<u32>(), ips);
+        }
+    }
+
+    #[test]
+    fn a_frame_runs_its_cycles_then_ticks_the_timers_once() {
+        // LD V0,0x20; LD DT,V0; then ADD V1,1 over and over
+        let rom = [0x60, 0x20, 0xF0, 0x15, 0x71, 0x01, 0x12, 0x04];
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&Cartridge::from_bytes(&rom).unwrap()).unwrap();
+        let mut budget = CycleBudget::new(10 * FRAME_RATE);
+        for frame in 1..=3 {
+            for _ in 0..budget.next_frame() {
+                vm.run_cycle().unwrap();
+            }
+            vm.tick_timers();
+            assert_eq!(vm.stats.instructions, 10 * frame);
+            assert!(vm.trace_line().ends_with(&format!("DT={}", 0x20 - frame)));
+        }
+    }
+
+    #[test]
+    fn the_delay_lengthens_frames() {
+        assert_eq!(frame_target(Duration::ZERO, false), FRAME_DURATION);
+        assert_eq!(frame_target(Duration::from_millis(5), false), FRAME_DURATION + Duration::from_millis(5));
+        // Half speed: cycles per frame stay the same, over twice the time
+        assert_eq!(frame_target(FRAME_DURATION, false), FRAME_DURATION * 2);
+        assert_eq!(frame_target(Duration::from_millis(5), true), FRAME_DURATION);
+    }
+
+    #[test]
+    fn turbo_runs_more_cycles_per_frame() {
+        let mut budget = CycleBudget::new(750);
+        assert_eq!(budget.next_frame_scaled(DEFAULT_TURBO), 12 * 8);
+        // The leftover is carried over at the normal rate
+        assert_eq!(budget.next_frame_scaled(DEFAULT_TURBO), 13 * 8);
+        assert_eq!(budget.next_frame(), 12);
+        assert_eq!(budget.next_frame_scaled(1), 13);
+        assert_eq!(CycleBudget::new(u32::MAX).next_frame_scaled(1000), u32::MAX);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn sleeps_what_is_left_of_the_frame() {
+        let ms = Duration::from_millis;
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(start);
+        // 5ms were spent emulating and drawing
+        assert_eq!(pacer.frame_sleep(ms(20), start + ms(5)), ms(15));
+        // The next frame ends 20ms after the first one did, at 40ms
+        assert_eq!(pacer.frame_sleep(ms(20), start + ms(21)), ms(19));
+        assert_eq!(pacer.frame_sleep(ms(20), start + ms(60)), ms(0));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn starts_over_when_far_behind() {
+        let ms = Duration::from_millis;
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(start);
+        assert_eq!(pacer.frame_sleep(FRAME_DURATION, start), FRAME_DURATION);
+        // The window was dragged for half a second: no catching up
+        let later = start + ms(500);
+        assert_eq!(pacer.frame_sleep(FRAME_DURATION, later), Duration::ZERO);
+        assert_eq!(pacer.frame_sleep(FRAME_DURATION, later + ms(1)), FRAME_DURATION - ms(1));
+    }
+}
@@ -0,0 +1,24 @@
+//! The emulator core used from outside the crate, the way other frontends would.
+
+use lascaoito::chip8::VirtualMachine;
+use lascaoito::profile::Profile;
+use lascaoito::quirks::Quirks;
+use lascaoito::rom::Cartridge;
+
+#[test]
+fn runs_a_rom_from_a_byte_slice() {
+    // LD V0,0; LD F,V0; DRW V0,V0,5
+    let rom = Cartridge::from_bytes(&[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05]).unwrap();
+    let mut vm = VirtualMachine::with_options(Quirks::default(), Some(0), Profile::default());
+    vm.load_rom(&rom).unwrap();
+    for _ in 0..3 {
+        vm.run_cycle().unwrap();
+    }
+    assert_eq!(vm.stats.instructions, 3);
+    assert!(vm.trace_line().contains(" 0x206:"));
+
+    // The top row of the 0 glyph, 0xF0
+    let screen = vm.framebuffer();
+    let row: Vec<u8> = (0..8).map(|x| screen.pixel(x, 0)).collect();
+    assert_eq!(row, [1, 1, 1, 1, 0, 0, 0, 0]);
+}
@@ -0,0 +1,39 @@
+//! `lascaoito --rom-info`, which describes a ROM without opening a window.
+// The command line is part of the SDL frontend
+#![cfg(feature = "sdl")]
+
+use std::process::Command;
+
+#[test]
+fn describes_the_self_test_rom() {
+    let output = Command::new(env!("CARGO_BIN_EXE_lascaoito"))
+        .args(["--rom-info", "ROMs/selftest.ch8"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[..6], [
+        "Name:     selftest",
+        "Size:     60 bytes",
+        "SHA-1:    9d6be3d72e99e4c61955ee4961833b24411bb779",
+        "Variant:  CHIP-8 (guessed from its opcodes)",
+        "Memory:   fits in the 3584 bytes of classic memory past 0x200",
+        "First instructions:",
+    ]);
+    // Eight of them, the first being the screen clear
+    assert_eq!(lines.len(), 14);
+    assert_eq!(lines[6], "  0x200  00E0       CLS");
+}
+
+#[test]
+fn says_when_a_rom_is_too_big_for_classic_memory() {
+    let path = std::env::temp_dir().join(format!("lascaoito-big-{}.ch8", std::process::id()));
+    std::fs::write(&path, vec![0x00; 3600]).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lascaoito")).arg("--rom-info").arg(&path).output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Size:     3600 bytes\n"));
+    assert!(stdout.contains("Memory:   too big for classic memory, which has 3584 bytes past 0x200\n"));
+}
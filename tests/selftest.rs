@@ -0,0 +1,37 @@
+//! The ROM run by `lascaoito --test`, run headless.
+// Drawn as text by `screenshot`, which needs std
+#![cfg(feature = "std")]
+
+use lascaoito::chip8::VirtualMachine;
+use lascaoito::rom::{sha1_hex, Cartridge};
+use lascaoito::screenshot::render_ascii;
+
+#[test]
+fn runs_the_self_test_rom() {
+    let rom = Cartridge::from_bytes(include_bytes!("../ROMs/selftest.ch8")).unwrap();
+    let mut vm = VirtualMachine::builder().seed(0).build().unwrap();
+    vm.load_rom(&rom).unwrap();
+
+    // A second of frames, counting when the buzzer starts
+    let mut beeps = 0;
+    let mut beeping = false;
+    for _ in 0..60 {
+        for _ in 0..10 {
+            vm.run_cycle().unwrap();
+        }
+        vm.tick_timers();
+        if vm.tone().is_some() && !beeping {
+            beeps += 1;
+        }
+        beeping = vm.tone().is_some();
+    }
+    assert_eq!(beeps, 1);
+
+    // Checkered bars at the top and bottom, "C8" in between
+    let screen = render_ascii(&vm);
+    let lines: Vec<&str> = screen.lines().collect();
+    assert_eq!(lines[0], "#.".repeat(32));
+    assert_eq!(lines[31], ".#".repeat(32));
+    assert_eq!(&lines[13][25..35], "####..####");
+    assert_eq!(sha1_hex(screen.as_bytes()), "8eebfb23727a5051e4eb6734e49299ed398d52fd");
+}
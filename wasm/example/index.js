@@ -0,0 +1,57 @@
+import init, { Emulator } from "../pkg/lascaoito_wasm.js";
+
+// The same layout as the desktop frontend: 1234/QWER/ASDF/ZXCV
+const KEYS = {
+  x: 0x0, 1: 0x1, 2: 0x2, 3: 0x3,
+  q: 0x4, w: 0x5, e: 0x6, a: 0x7,
+  s: 0x8, d: 0x9, z: 0xA, c: 0xB,
+  4: 0xC, r: 0xD, f: 0xE, v: 0xF,
+};
+
+const CYCLES_PER_FRAME = 10;
+
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+let emulator = null;
+
+function frame() {
+  if (emulator) {
+    try {
+      emulator.step(CYCLES_PER_FRAME);
+    } catch (error) {
+      // The program can't go on: leave its last frame on screen
+      console.error(error);
+      emulator = null;
+      requestAnimationFrame(frame);
+      return;
+    }
+    emulator.tick_timers();
+    const width = emulator.width();
+    const height = emulator.height();
+    if (canvas.width !== width || canvas.height !== height) {
+      canvas.width = width;
+      canvas.height = height;
+    }
+    const pixels = new Uint8ClampedArray(emulator.framebuffer_rgba());
+    context.putImageData(new ImageData(pixels, width, height), 0, 0);
+  }
+  requestAnimationFrame(frame);
+}
+
+document.addEventListener("keydown", (event) => {
+  const key = KEYS[event.key.toLowerCase()];
+  if (emulator && key !== undefined) emulator.key_down(key);
+});
+
+document.addEventListener("keyup", (event) => {
+  const key = KEYS[event.key.toLowerCase()];
+  if (emulator && key !== undefined) emulator.key_up(key);
+});
+
+document.getElementById("rom").addEventListener("change", async (event) => {
+  const rom = new Uint8Array(await event.target.files[0].arrayBuffer());
+  emulator = new Emulator(rom, Date.now() >>> 0);
+});
+
+await init();
+requestAnimationFrame(frame);
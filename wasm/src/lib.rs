@@ -0,0 +1,112 @@
+//! The emulator core for web pages, through wasm-bindgen.
+//! The page drives it: it runs a frame's worth of instructions with `step`,
+//! ticks the timers, and draws `framebuffer_rgba` into a canvas.
+
+use lascaoito::chip8::VirtualMachine;
+use lascaoito::quirks::Quirks;
+use lascaoito::render::DEFAULT_PALETTE;
+use lascaoito::rom::Cartridge;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct Emulator {
+    vm: VirtualMachine,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    /// Loads `rom` into a new CHIP-8 machine. There's no entropy source without std,
+    /// so the random number generator is seeded by the page, e.g. with `Date.now()`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8], seed: u32) -> Result<Emulator, JsValue> {
+        let cart = Cartridge::from_bytes(rom).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        let mut vm = VirtualMachine::builder()
+            .quirks(Quirks::default())
+            .seed(seed as u64)
+            .build()
+            .map_err(|err| JsValue::from_str(&err.to_string()))?;
+        vm.load_rom(&cart).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        Ok(Emulator { vm })
+    }
+
+    /// Runs `n` instructions, usually 10 per frame.
+    /// Throws if the program can't go on, such as on an unknown opcode.
+    pub fn step(&mut self, n: u32) -> Result<(), JsValue> {
+        self.vm.step(n).map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// Ticks the delay and sound timers, which must happen 60 times per second
+    pub fn tick_timers(&mut self) {
+        self.vm.tick_timers();
+    }
+
+    /// Whether the sound timer is running, i.e. the buzzer should sound
+    pub fn beeping(&self) -> bool {
+        self.vm.tone().is_some()
+    }
+
+    /// Width of the screen in the current resolution
+    pub fn width(&self) -> usize {
+        self.vm.width()
+    }
+
+    /// Height of the screen in the current resolution
+    pub fn height(&self) -> usize {
+        self.vm.height()
+    }
+
+    /// The screen as `width() * height()` RGBA pixels, row by row,
+    /// ready for an `ImageData`
+    pub fn framebuffer_rgba(&self) -> Vec<u8> {
+        let framebuffer = self.vm.framebuffer();
+        let mut pixels = Vec::with_capacity(framebuffer.width() * framebuffer.height() * 4);
+        for row in framebuffer.rows() {
+            for pixel in row {
+                pixels.extend_from_slice(&DEFAULT_PALETTE[pixel as usize]);
+                pixels.push(0xFF);
+            }
+        }
+        pixels
+    }
+
+    /// Presses `key` on the hex keypad, 0x0 to 0xF
+    pub fn key_down(&mut self, key: u8) {
+        self.vm.set_key(key, true);
+    }
+
+    /// Releases `key` on the hex keypad, 0x0 to 0xF
+    pub fn key_up(&mut self, key: u8) {
+        self.vm.set_key(key, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Natively a plain test, in wasm one for `wasm-pack test`
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+    fn loads_a_rom_and_steps_it() {
+        // LD V0,0; LD F,V0; DRW V0,V0,5; LD V1,K
+        let mut emulator = Emulator::new(&[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0xF1, 0x0A], 0).unwrap();
+        emulator.step(4).unwrap();
+        assert_eq!((emulator.width(), emulator.height()), (64, 32));
+
+        let pixels = emulator.framebuffer_rgba();
+        assert_eq!(pixels.len(), 64 * 32 * 4);
+        // The top row of the 0 glyph: four lit pixels, then the background
+        let set = [DEFAULT_PALETTE[1][0], DEFAULT_PALETTE[1][1], DEFAULT_PALETTE[1][2], 0xFF];
+        let unset = [DEFAULT_PALETTE[0][0], DEFAULT_PALETTE[0][1], DEFAULT_PALETTE[0][2], 0xFF];
+        assert_eq!(pixels[..16], set.repeat(4)[..]);
+        assert_eq!(pixels[16..20], unset);
+
+        // Waits for a key until one is pressed
+        emulator.step(2).unwrap();
+        assert!(emulator.vm.trace_line().contains(" 0x206:"));
+        emulator.key_down(0x3);
+        emulator.step(1).unwrap();
+        emulator.key_up(0x3);
+        assert!(emulator.vm.trace_line().contains(" 0x208:"));
+    }
+}